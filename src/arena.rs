@@ -0,0 +1,377 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::{
+    game::{MAX_PLAYERS, WINDOW_HEIGHT, WINDOW_WIDTH},
+    rng::Rng,
+    win_condition,
+};
+
+// the longest map rotation that can be configured
+pub const MAX_ROTATION: usize = 8;
+// the most walls a generated arena has
+pub const MAX_GENERATED_WALLS: usize = 8;
+
+const WALL_COLOR: Color = DARKGRAY;
+
+// generated arenas are laid out on a grid of cells this many pixels wide
+const CELL: u16 = 20;
+// walls of generated arenas keep this much space to each other and to the spots win conditions
+// send ships to, so ships fit through everywhere
+const CLEARANCE: i32 = 60;
+// ships of generated arenas spawn this far apart, and this far from walls and the window border
+const SPAWN_DISTANCE: i32 = 160;
+const SPAWN_CLEARANCE: i32 = 50;
+// generating gives up on a wall or spawn point after this many tries, so it always ends
+const ATTEMPTS: usize = 32;
+
+// Wall is an axis aligned rectangle ships can't pass through, in whole pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct Wall {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+}
+
+impl Wall {
+    const fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // for checksums
+    pub fn bytes(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        for (chunk, value) in bytes
+            .chunks_mut(2)
+            .zip([self.x, self.y, self.width, self.height])
+        {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    // the squared distance from `(x, y)` to the closest point of the wall, 0 inside of it
+    fn distance_sq(&self, (x, y): (i32, i32)) -> i32 {
+        let (left, top) = (self.x as i32, self.y as i32);
+        let (right, bottom) = (left + self.width as i32, top + self.height as i32);
+        let dx = x - x.clamp(left, right);
+        let dy = y - y.clamp(top, bottom);
+        dx * dx + dy * dy
+    }
+
+    // whether the walls come closer than `gap` to each other
+    fn near(&self, other: &Wall, gap: i32) -> bool {
+        (self.x as i32) < other.x as i32 + other.width as i32 + gap
+            && (other.x as i32) < self.x as i32 + self.width as i32 + gap
+            && (self.y as i32) < other.y as i32 + other.height as i32 + gap
+            && (other.y as i32) < self.y as i32 + self.height as i32 + gap
+    }
+}
+
+// walls stay clear of the circle ships spawn on
+const PILLARS: [Wall; 5] = [
+    Wall::new(120, 120, 60, 60),
+    Wall::new(420, 120, 60, 60),
+    Wall::new(260, 360, 80, 80),
+    Wall::new(120, 620, 60, 60),
+    Wall::new(420, 620, 60, 60),
+];
+const CORRIDORS: [Wall; 2] = [Wall::new(0, 190, 400, 20), Wall::new(200, 590, 400, 20)];
+
+// Arena is a layout of walls, all peers have to play the same one on the same frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub enum Arena {
+    Open,
+    Pillars,
+    Corridors,
+    // laid out from the shared random number generator whenever a round in it starts
+    Generated,
+}
+
+impl Arena {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Pillars => "pillars",
+            Self::Corridors => "corridors",
+            Self::Generated => "generated",
+        }
+    }
+
+    // the walls of the fixed arenas, generated ones have theirs in their `Layout`
+    pub fn walls(self) -> &'static [Wall] {
+        match self {
+            Self::Open | Self::Generated => &[],
+            Self::Pillars => &PILLARS,
+            Self::Corridors => &CORRIDORS,
+        }
+    }
+}
+
+// Layout is the walls and spawn points of a generated arena, part of the state so rollbacks and
+// replays don't have to generate it again
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct Layout {
+    walls: [Wall; MAX_GENERATED_WALLS],
+    len: u8,
+    // where each player spawns, in whole pixels
+    spawns: [(u16, u16); MAX_PLAYERS],
+}
+
+impl Layout {
+    // lays out walls clear of the spots win conditions send ships to, then spawn points clear of
+    // the walls and apart from each other
+    // everything is drawn from `rng` in integers, so all peers sharing the seed get the same layout
+    pub fn generate(rng: &mut Rng) -> Self {
+        let mut layout = Self::default();
+        let (columns, rows) = (
+            WINDOW_WIDTH as u32 / CELL as u32,
+            WINDOW_HEIGHT as u32 / CELL as u32,
+        );
+        let count = 4 + rng.next_u32() as usize % (MAX_GENERATED_WALLS - 3);
+        for _ in 0..count {
+            for _ in 0..ATTEMPTS {
+                // walls are blocks or bars in either direction, measured in cells
+                let long = 2 + rng.next_u32() % 7;
+                let thick = 1 + rng.next_u32() % 3;
+                let (width, height) = match rng.next_u32() % 2 {
+                    0 => (long, thick),
+                    _ => (thick, long),
+                };
+                let x = rng.next_u32() % (columns - width + 1);
+                let y = rng.next_u32() % (rows - height + 1);
+                let cells = |value: u32| (value * CELL as u32) as u16;
+                let wall = Wall::new(cells(x), cells(y), cells(width), cells(height));
+                let blocks_landmark = win_condition::landmarks().any(|((x, y), radius)| {
+                    let distance = radius as i32 + CLEARANCE;
+                    wall.distance_sq((x as i32, y as i32)) < distance * distance
+                });
+                if !blocks_landmark
+                    && !layout
+                        .walls()
+                        .iter()
+                        .any(|other| wall.near(other, CLEARANCE))
+                {
+                    layout.walls[layout.len as usize] = wall;
+                    layout.len += 1;
+                    break;
+                }
+            }
+        }
+
+        let (width, height) = (WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32);
+        let mut fallbacks = win_condition::landmarks();
+        for i in 0..MAX_PLAYERS {
+            let spawn = (0..ATTEMPTS).find_map(|_| {
+                let margin = SPAWN_CLEARANCE as u32;
+                let x = margin + rng.next_u32() % (width - 2 * margin);
+                let y = margin + rng.next_u32() % (height - 2 * margin);
+                let point = (x as i32, y as i32);
+                let clear = layout
+                    .walls()
+                    .iter()
+                    .all(|wall| wall.distance_sq(point) >= SPAWN_CLEARANCE * SPAWN_CLEARANCE);
+                let apart = layout.spawns[..i].iter().all(|&(other_x, other_y)| {
+                    let (dx, dy) = (point.0 - other_x as i32, point.1 - other_y as i32);
+                    dx * dx + dy * dy >= SPAWN_DISTANCE * SPAWN_DISTANCE
+                });
+                (clear && apart).then_some((x as u16, y as u16))
+            });
+            // the landmarks are clear of walls, and there are more of them than players
+            layout.spawns[i] = spawn.unwrap_or_else(|| {
+                let ((x, y), _) = fallbacks.next().unwrap();
+                (x as u16, y as u16)
+            });
+        }
+        layout
+    }
+
+    pub fn walls(&self) -> &[Wall] {
+        &self.walls[..self.len as usize]
+    }
+
+    pub fn spawn(&self, player: usize) -> (f32, f32) {
+        let (x, y) = self.spawns[player];
+        (x as f32, y as f32)
+    }
+
+    // reverses the spawn points of the first `num_players` players, like the state when mirroring
+    pub fn mirror(&mut self, num_players: usize) {
+        self.spawns[..num_players].reverse();
+    }
+}
+
+// moves a ship of `radius` out of all walls and stops its movement towards them
+pub fn collide(
+    walls: &[Wall],
+    (mut x, mut y): (f32, f32),
+    (mut vel_x, mut vel_y): (f32, f32),
+    radius: f32,
+) -> ((f32, f32), (f32, f32)) {
+    for wall in walls {
+        let (wall_x, wall_y) = (wall.x as f32, wall.y as f32);
+        let (wall_width, wall_height) = (wall.width as f32, wall.height as f32);
+        // closest point of the wall to the center of the ship
+        let closest_x = x.clamp(wall_x, wall_x + wall_width);
+        let closest_y = y.clamp(wall_y, wall_y + wall_height);
+        let (dx, dy) = (x - closest_x, y - closest_y);
+        let distance_sq = dx * dx + dy * dy;
+        if distance_sq >= radius * radius {
+            continue;
+        }
+        if distance_sq > 0.0 {
+            let distance = distance_sq.sqrt();
+            let (normal_x, normal_y) = (dx / distance, dy / distance);
+            x = closest_x + normal_x * radius;
+            y = closest_y + normal_y * radius;
+            let towards = vel_x * normal_x + vel_y * normal_y;
+            if towards < 0.0 {
+                vel_x -= normal_x * towards;
+                vel_y -= normal_y * towards;
+            }
+        } else {
+            // the center is inside the wall, leave through the closest edge
+            let left = x - wall_x;
+            let right = wall_x + wall_width - x;
+            let top = y - wall_y;
+            let bottom = wall_y + wall_height - y;
+            let closest = left.min(right).min(top).min(bottom);
+            if closest == left {
+                (x, vel_x) = (wall_x - radius, vel_x.min(0.0));
+            } else if closest == right {
+                (x, vel_x) = (wall_x + wall_width + radius, vel_x.max(0.0));
+            } else if closest == top {
+                (y, vel_y) = (wall_y - radius, vel_y.min(0.0));
+            } else {
+                (y, vel_y) = (wall_y + wall_height + radius, vel_y.max(0.0));
+            }
+        }
+    }
+    ((x, y), (vel_x, vel_y))
+}
+
+pub fn draw(walls: &[Wall]) {
+    for wall in walls {
+        let (x, y) = (wall.x as f32, wall.y as f32);
+        draw_rectangle(x, y, wall.width as f32, wall.height as f32, WALL_COLOR);
+    }
+}
+
+impl FromStr for Arena {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(Self::Open),
+            "pillars" => Ok(Self::Pillars),
+            "corridors" => Ok(Self::Corridors),
+            "generated" => Ok(Self::Generated),
+            _ => Err(format!("unknown arena: {}", s)),
+        }
+    }
+}
+
+// MapRotation lists the arenas successive rounds are played in, starting over after the last
+// it is part of the tuning, so every peer knows the next arena without asking anyone
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct MapRotation {
+    arenas: [Arena; MAX_ROTATION],
+    len: usize,
+}
+
+impl MapRotation {
+    // the arena played in round `round`, counting from 0
+    pub fn get(&self, round: u32) -> Arena {
+        self.arenas[round as usize % self.len]
+    }
+
+    pub fn arenas(&self) -> &[Arena] {
+        &self.arenas[..self.len]
+    }
+}
+
+impl Default for MapRotation {
+    fn default() -> Self {
+        Self {
+            arenas: [Arena::Open; MAX_ROTATION],
+            len: 1,
+        }
+    }
+}
+
+// parses a comma separated list of arenas, e.g. `open,pillars`
+impl FromStr for MapRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rotation = Self {
+            arenas: [Arena::Open; MAX_ROTATION],
+            len: 0,
+        };
+        for name in s.split(',') {
+            if rotation.len == MAX_ROTATION {
+                return Err(format!("at most {} arenas can be rotated", MAX_ROTATION));
+            }
+            rotation.arenas[rotation.len] = name.trim().parse()?;
+            rotation.len += 1;
+        }
+        Ok(rotation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_layouts_follow_the_seed_and_keep_clear() {
+        for seed in 0..50 {
+            let layout = Layout::generate(&mut Rng::new(seed));
+            assert_eq!(layout, Layout::generate(&mut Rng::new(seed)));
+            assert!(layout.walls().len() <= MAX_GENERATED_WALLS);
+            for ((x, y), radius) in win_condition::landmarks() {
+                for wall in layout.walls() {
+                    assert!(wall.distance_sq((x as i32, y as i32)) >= (radius as i32).pow(2));
+                }
+            }
+            for player in 0..MAX_PLAYERS {
+                let (x, y) = layout.spawn(player);
+                assert!(x > 0.0 && x < WINDOW_WIDTH && y > 0.0 && y < WINDOW_HEIGHT);
+                for wall in layout.walls() {
+                    assert!(wall.distance_sq((x as i32, y as i32)) > 0);
+                }
+            }
+        }
+        assert_ne!(
+            Layout::generate(&mut Rng::new(1)),
+            Layout::generate(&mut Rng::new(2))
+        );
+    }
+}
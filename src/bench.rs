@@ -0,0 +1,108 @@
+use bevy_tasks::TaskPool;
+use criterion::{BatchSize, Criterion};
+use std::hint::black_box;
+
+use crate::{
+    game::{GameState, MovementModel, Tuning, MAX_METEORS, MAX_PLAYERS, WINDOW_HEIGHT},
+    meteor::{self, Meteor},
+    rng::Rng,
+};
+
+// the player counts measured, states hold at most `MAX_PLAYERS` ships
+const PLAYER_COUNTS: [usize; 2] = [2, MAX_PLAYERS];
+
+// a state from the middle of a match
+fn mid_match_state(players: usize) -> GameState {
+    let tuning = Tuning::new(MovementModel::Inertia, 1.0);
+    let mut state = GameState::new(players, tuning, 0);
+    for frame in 0..600 {
+        state.advance(&vec![Some((frame % 16) as u8); players]);
+    }
+    state
+}
+
+// measures what every rollback does a lot of: advancing, saving, checksumming and serializing
+// game states, and moving meteors one after the other or in chunks on a task pool
+// results are kept in `target/criterion`, `baseline` compares with one saved earlier with
+// `save_baseline` and reports regressions, or fails if there is no such baseline
+pub fn run(filter: Option<String>, baseline: Option<String>, save_baseline: Option<String>) {
+    let mut criterion = Criterion::default();
+    if let Some(filter) = filter {
+        criterion = criterion.with_filter(filter);
+    }
+    if let Some(baseline) = baseline {
+        criterion = criterion.retain_baseline(baseline, true);
+    }
+    if let Some(baseline) = save_baseline {
+        criterion = criterion.save_baseline(baseline);
+    }
+
+    for players in PLAYER_COUNTS {
+        let state = mid_match_state(players);
+        let inputs = vec![Some(5); players];
+        let mut group = criterion.benchmark_group(format!("{} players", players));
+        group.bench_function("advance", |b| {
+            b.iter_batched_ref(
+                || state.clone(),
+                |state| state.advance(black_box(&inputs)),
+                BatchSize::SmallInput,
+            )
+        });
+        // saving a state for backroll is a clone
+        group.bench_function("save", |b| b.iter(|| black_box(&state).clone()));
+        group.bench_function("checksum", |b| b.iter(|| black_box(&state).checksum()));
+
+        group.bench_function("bincode serialize", |b| {
+            b.iter(|| bincode::serialize(black_box(&state)).unwrap())
+        });
+        let encoded = bincode::serialize(&state).unwrap();
+        group.bench_function("bincode deserialize", |b| {
+            b.iter(|| bincode::deserialize::<GameState>(black_box(&encoded)).unwrap())
+        });
+
+        #[cfg(feature = "rkyv")]
+        {
+            group.bench_function("rkyv serialize", |b| {
+                b.iter(|| rkyv::to_bytes::<_, 256>(black_box(&state)).unwrap())
+            });
+            let archived = rkyv::to_bytes::<_, 256>(&state).unwrap();
+            // zero-copy: the validated archive can be read in place
+            group.bench_function("rkyv validate", |b| {
+                b.iter(|| rkyv::check_archived_root::<GameState>(black_box(&archived)).is_ok())
+            });
+            group.bench_function("rkyv deserialize", |b| {
+                b.iter(|| rkyv::from_bytes::<GameState>(black_box(&archived)).unwrap())
+            });
+        }
+        group.finish();
+    }
+
+    // the heaviest shower there can be, every slot holds a meteor halfway across the arena
+    let tuning = Tuning::new(MovementModel::Inertia, 1.0);
+    let mut rng = Rng::new(0);
+    let meteors = [(); MAX_METEORS].map(|_| {
+        let mut meteor = Meteor::spawn(&mut rng, &tuning);
+        meteor.position.1 = WINDOW_HEIGHT / 2.0;
+        Some(meteor)
+    });
+    let mut group = criterion.benchmark_group("meteors");
+    group.bench_function("serial", |b| {
+        b.iter_batched_ref(
+            || meteors,
+            |slots| meteor::advance_all(black_box(slots)),
+            BatchSize::SmallInput,
+        )
+    });
+    let pool = TaskPool::new();
+    for chunk in [MAX_METEORS / 4, MAX_METEORS / 2] {
+        group.bench_function(format!("in chunks of {}", chunk), |b| {
+            b.iter_batched_ref(
+                || meteors,
+                |slots| meteor::advance_parallel(&pool, black_box(slots), chunk),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+    criterion.final_summary();
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fs, path::Path};
+
+use crate::{identity::PeerId, opponents::Opponents, Error};
+
+// BlockList holds the peers this installation never wants to hear from, they are muted in every
+// session they show up in
+#[derive(Default, Serialize, Deserialize)]
+pub struct BlockList {
+    blocked: BTreeSet<PeerId>,
+}
+
+impl BlockList {
+    // an empty list if nobody was blocked yet
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(path).map_err(Error::read(path))?;
+        serde_json::from_str(&json).map_err(Error::corrupt(path, "block list"))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self).map_err(Error::encode("block list"))?;
+        fs::write(path, json).map_err(Error::write(path))
+    }
+
+    pub fn contains(&self, id: &PeerId) -> bool {
+        self.blocked.contains(id)
+    }
+}
+
+// finds the opponent whose id starts with `prefix`, as printed by the `opponents` subcommand
+fn find(opponents: &Opponents, prefix: &str) -> Result<PeerId, Error> {
+    let prefix = prefix.to_lowercase();
+    let mut matching = opponents
+        .recent()
+        .into_iter()
+        .filter(|(id, _)| id.to_string().starts_with(&prefix));
+    match (matching.next(), matching.next()) {
+        (Some((id, _)), None) => Ok(*id),
+        (None, _) => Err(Error::UnknownOpponent(prefix)),
+        (Some(_), Some(_)) => Err(Error::AmbiguousOpponent(prefix)),
+    }
+}
+
+// blocks or unblocks the opponent whose id starts with `prefix`
+pub fn set_blocked(
+    path: &Path,
+    opponents: &Path,
+    prefix: &str,
+    blocked: bool,
+) -> Result<(), Error> {
+    let id = find(&Opponents::load(opponents)?, prefix)?;
+    let mut block_list = BlockList::load(path)?;
+    if blocked {
+        block_list.blocked.insert(id);
+        println!("Blocked {}", id);
+    } else {
+        block_list.blocked.remove(&id);
+        println!("Unblocked {}", id);
+    }
+    block_list.save(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opponents_are_found_by_an_unambiguous_prefix() {
+        let mut opponents = Opponents::default();
+        opponents.record(PeerId([0xab; 16]), "Ada", 100);
+        opponents.record(PeerId([0xac; 16]), "Bob", 200);
+        assert_eq!(find(&opponents, "ABAB").unwrap(), PeerId([0xab; 16]));
+        assert!(matches!(
+            find(&opponents, "a"),
+            Err(Error::AmbiguousOpponent(_))
+        ));
+        assert!(matches!(
+            find(&opponents, "ff"),
+            Err(Error::UnknownOpponent(_))
+        ));
+    }
+}
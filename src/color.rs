@@ -0,0 +1,105 @@
+use macroquad::prelude::*;
+use std::str::FromStr;
+
+use crate::game::player_color;
+
+// colors that are picked instead when two players chose colors that are hard to tell apart
+const FALLBACK_COLORS: [Color; 8] = [GOLD, BLUE, GREEN, RED, PURPLE, ORANGE, PINK, SKYBLUE];
+// colors closer than this in rgb are considered the same
+const MIN_DISTANCE: f32 = 0.3;
+
+// PlayerColor is the color a player chose for their ship, as it is sent to the other peers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayerColor(pub [u8; 3]);
+
+impl PlayerColor {
+    // the color players get if they don't choose one
+    pub fn default_for(player: usize) -> Self {
+        Self::from(player_color(player))
+    }
+
+    pub fn color(self) -> Color {
+        let [r, g, b] = self.0;
+        Color::from_rgba(r, g, b, 255)
+    }
+}
+
+impl From<Color> for PlayerColor {
+    fn from(color: Color) -> Self {
+        let [r, g, b, _]: [u8; 4] = color.into();
+        Self([r, g, b])
+    }
+}
+
+// colors are given by name, e.g. `red`, or as hex, e.g. `#ff8800`
+impl FromStr for PlayerColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let named = match s {
+            "gold" => GOLD,
+            "blue" => BLUE,
+            "green" => GREEN,
+            "red" => RED,
+            "purple" => PURPLE,
+            "orange" => ORANGE,
+            "pink" => PINK,
+            "skyblue" => SKYBLUE,
+            "white" => WHITE,
+            _ => {
+                let hex = s.strip_prefix('#').unwrap_or(s);
+                let rgb = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 6)
+                    .ok_or_else(|| format!("unknown color: {}", s))?;
+                let [_, r, g, b] = rgb.to_be_bytes();
+                return Ok(Self([r, g, b]));
+            }
+        };
+        Ok(Self::from(named))
+    }
+}
+
+// picks the colors ships are drawn in from the ones the players chose
+// players keep their color unless an earlier player already has a similar one, then they get the
+// first fallback color nobody has, so all peers end up with the same colors
+pub fn resolve(chosen: &[PlayerColor]) -> Vec<Color> {
+    let mut colors: Vec<Color> = Vec::with_capacity(chosen.len());
+    for (i, choice) in chosen.iter().enumerate() {
+        let taken = |color: Color| {
+            colors
+                .iter()
+                .any(|other| distance(*other, color) < MIN_DISTANCE)
+        };
+        let color = match choice.color() {
+            color if !taken(color) => color,
+            _ => FALLBACK_COLORS
+                .into_iter()
+                .find(|color| !taken(*color))
+                .unwrap_or_else(|| player_color(i)),
+        };
+        colors.push(color);
+    }
+    colors
+}
+
+fn distance(a: Color, b: Color) -> f32 {
+    ((a.r - b.r).powi(2) + (a.g - b.g).powi(2) + (a.b - b.b).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similar_colors_fall_back_in_player_order() {
+        let red: PlayerColor = "red".parse().unwrap();
+        let almost_red: PlayerColor = "#e62935".parse().unwrap();
+        let chosen = [red, almost_red, PlayerColor::default_for(0)];
+        let colors = resolve(&chosen);
+        assert_eq!(colors[0], red.color());
+        assert_eq!(colors[1], GOLD);
+        assert_eq!(colors[2], BLUE);
+        assert!("#12345".parse::<PlayerColor>().is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use backroll_transport::Peer;
+use bevy_tasks::TaskPool;
+
+use crate::transport::wrap_peer;
+
+// smaller messages are sent as they are, compressing them isn't worth the time
+const COMPRESSION_THRESHOLD: usize = 64;
+
+// the first byte of every message tells how the rest of it is stored
+const RAW: u8 = 0;
+const LZ4: u8 = 1;
+
+// larger sizes in compressed messages are rejected instead of allocating that much memory
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+// wraps the peer of a remote player to compress large messages with LZ4
+// the returned peer is handed to backroll
+pub fn wrap(pool: &TaskPool, network: Peer) -> Peer {
+    wrap_peer(pool, network, |message| Some(compress(message)), decompress)
+}
+
+fn compress(message: &[u8]) -> Box<[u8]> {
+    if message.len() >= COMPRESSION_THRESHOLD {
+        let compressed = lz4_flex::compress_prepend_size(message);
+        // incompressible messages are sent as they are
+        if compressed.len() < message.len() {
+            return [&[LZ4], compressed.as_slice()].concat().into_boxed_slice();
+        }
+    }
+    [&[RAW], message].concat().into_boxed_slice()
+}
+
+fn decompress(message: &[u8]) -> Option<Box<[u8]>> {
+    match message.split_first()? {
+        (&RAW, payload) => Some(payload.into()),
+        (&LZ4, payload) => {
+            let size = u32::from_le_bytes(payload.get(..4)?.try_into().ok()?) as usize;
+            if size > MAX_MESSAGE_SIZE {
+                return None;
+            }
+            lz4_flex::decompress(&payload[4..], size)
+                .ok()
+                .map(Vec::into_boxed_slice)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_messages() {
+        let small = b"input".to_vec();
+        let repetitive = vec![7; 1000];
+        for message in [small, repetitive] {
+            let compressed = compress(&message);
+            assert_eq!(&*decompress(&compressed).unwrap(), message.as_slice());
+        }
+        assert!(compress(&[7; 1000]).len() < 100);
+    }
+}
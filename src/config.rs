@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::Error;
+
+// ControlScheme is the set of keys the first local player steers with, a second local player
+// gets the other one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlScheme {
+    Wasd,
+    Arrows,
+    // steering, voting and rematches all within reach of the left hand
+    OneHanded,
+}
+
+impl ControlScheme {
+    // the index into `CONTROL_SCHEMES`
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    // the scheme of a second player sharing the keyboard, one-handed players steer on the right
+    // half of the keyboard and leave the left one to them
+    pub fn other(self) -> Self {
+        match self {
+            Self::Wasd => Self::Arrows,
+            Self::Arrows | Self::OneHanded => Self::Wasd,
+        }
+    }
+
+    // the scheme the menu switches to
+    pub fn next(self) -> Self {
+        match self {
+            Self::Wasd => Self::Arrows,
+            Self::Arrows => Self::OneHanded,
+            Self::OneHanded => Self::Wasd,
+        }
+    }
+}
+
+// Config holds the settings of this player, written by the setup on the first launch
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    pub name: String,
+    pub controls: ControlScheme,
+    // a tap on thrust turns it on until the next tap, instead of holding the key
+    // missing in configs written before the option existed
+    #[serde(default)]
+    pub toggle_thrust: bool,
+}
+
+impl Config {
+    // `None` if there is no config yet
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(path).map_err(Error::read(path))?;
+        let config = serde_json::from_str(&json).map_err(Error::corrupt(path, "config"))?;
+        Ok(Some(config))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self).map_err(Error::encode("config"))?;
+        fs::write(path, json).map_err(Error::write(path))
+    }
+}
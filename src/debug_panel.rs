@@ -0,0 +1,210 @@
+use backroll::NetworkStats;
+use egui_macroquad::egui;
+use std::time::Duration;
+
+use crate::game::{Frame, GameState};
+
+// Overlays selects the debug information drawn on top of the game
+pub struct Overlays {
+    pub checksums: bool,
+    pub first_inputs: bool,
+    // connection quality and status of remote players, and dropped messages
+    pub connection: bool,
+    pub rollback_ghosts: bool,
+    // eases ships into place after rollbacks instead of letting them jump
+    pub smoothing: bool,
+    pub rollback_stats: bool,
+    pub win_condition: bool,
+    pub frame_graph: bool,
+}
+
+impl Default for Overlays {
+    fn default() -> Self {
+        Self {
+            checksums: true,
+            first_inputs: true,
+            connection: true,
+            rollback_ghosts: true,
+            smoothing: true,
+            rollback_stats: true,
+            win_condition: true,
+            frame_graph: false,
+        }
+    }
+}
+
+// PeerStats are the network stats of a remote player last reported by backroll
+#[derive(Clone, Copy)]
+pub struct PeerStats {
+    pub ping: Duration,
+    pub send_queue_len: usize,
+    pub kbps_sent: u32,
+    pub local_frames_behind: Frame,
+    pub remote_frames_behind: Frame,
+}
+
+impl PeerStats {
+    pub fn new(stats: &NetworkStats) -> Self {
+        Self {
+            ping: stats.ping,
+            send_queue_len: stats.send_queue_len,
+            kbps_sent: stats.kbps_sent,
+            local_frames_behind: stats.local_frames_behind,
+            remote_frames_behind: stats.remote_frames_behind,
+        }
+    }
+}
+
+// PeerInfo is what the panel shows about one player
+pub struct PeerInfo {
+    pub name: Option<String>,
+    pub status: String,
+    // `None` for local players
+    pub stats: Option<PeerStats>,
+    // messages dropped by full queues, sent and received
+    pub dropped: (u64, u64),
+}
+
+// SessionInfo is a view of the session the game hands to the panel every frame
+pub struct SessionInfo<'a> {
+    pub running: bool,
+    pub last_checksum: (Frame, u64),
+    pub periodic_checksum: (Frame, u64),
+    pub periodic_input_hash: u64,
+    pub mirror_mismatches: Option<u32>,
+    pub rollback_summary: String,
+    pub pacing_jitter: Duration,
+    pub peers: Vec<PeerInfo>,
+    pub state: &'a GameState,
+}
+
+// DebugPanel is a collapsible egui window with the session state and the overlay toggles
+// it is toggled with F1 and drawn on top of everything else
+#[derive(Default)]
+pub struct DebugPanel {
+    pub visible: bool,
+    pub overlays: Overlays,
+}
+
+impl DebugPanel {
+    pub fn draw(&mut self, session: &SessionInfo) {
+        if !self.visible {
+            return;
+        }
+        egui_macroquad::ui(|ctx| {
+            egui::Window::new("Debug")
+                .default_pos((20.0, 100.0))
+                .show(ctx, |ui| {
+                    ui.collapsing("Session", |ui| session_section(ui, session));
+                    ui.collapsing("Peers", |ui| peers_section(ui, &session.peers));
+                    ui.collapsing("Game state", |ui| state_section(ui, session.state));
+                    ui.collapsing("Overlays", |ui| self.overlays_section(ui));
+                });
+        });
+        egui_macroquad::draw();
+    }
+
+    fn overlays_section(&mut self, ui: &mut egui::Ui) {
+        let overlays = &mut self.overlays;
+        ui.checkbox(&mut overlays.checksums, "Checksums");
+        ui.checkbox(&mut overlays.first_inputs, "First inputs");
+        ui.checkbox(&mut overlays.connection, "Connection status");
+        ui.checkbox(&mut overlays.rollback_ghosts, "Rollback ghosts");
+        ui.checkbox(&mut overlays.smoothing, "Smooth rollback corrections");
+        ui.checkbox(&mut overlays.rollback_stats, "Rollback stats");
+        ui.checkbox(&mut overlays.win_condition, "Win condition progress");
+        ui.checkbox(&mut overlays.frame_graph, "Frame graph (F3)");
+    }
+}
+
+fn session_section(ui: &mut egui::Ui, session: &SessionInfo) {
+    let running = if session.running {
+        "running"
+    } else {
+        "synchronizing"
+    };
+    ui.label(format!("Session: {}", running));
+    ui.label(format!("Frame: {}", session.state.frame));
+    let (frame, checksum) = session.last_checksum;
+    ui.label(format!(
+        "Last checksum: {:016x} (frame {})",
+        checksum, frame
+    ));
+    let (frame, checksum) = session.periodic_checksum;
+    ui.label(format!(
+        "Periodic checksum: {:016x} (frame {})",
+        checksum, frame
+    ));
+    ui.label(format!("Input hash: {:016x}", session.periodic_input_hash));
+    if let Some(mismatches) = session.mirror_mismatches {
+        ui.label(format!("Mirror mismatches: {}", mismatches));
+    }
+    ui.label(&session.rollback_summary);
+    ui.label(format!(
+        "Frame pacing jitter: {:.2} ms",
+        session.pacing_jitter.as_secs_f64() * 1000.0
+    ));
+}
+
+fn peers_section(ui: &mut egui::Ui, peers: &[PeerInfo]) {
+    egui::Grid::new("peers").striped(true).show(ui, |ui| {
+        for header in [
+            "", "name", "status", "ping", "queue", "kbps", "behind", "dropped",
+        ] {
+            ui.strong(header);
+        }
+        ui.end_row();
+        for (i, peer) in peers.iter().enumerate() {
+            ui.label(format!("P{}", i + 1));
+            ui.label(peer.name.as_deref().unwrap_or("-"));
+            ui.label(&peer.status);
+            match peer.stats {
+                Some(stats) => {
+                    ui.label(format!("{} ms", stats.ping.as_millis()));
+                    ui.label(stats.send_queue_len.to_string());
+                    ui.label(stats.kbps_sent.to_string());
+                    ui.label(format!(
+                        "{} / {}",
+                        stats.local_frames_behind, stats.remote_frames_behind
+                    ));
+                }
+                None => {
+                    for _ in 0..4 {
+                        ui.label("-");
+                    }
+                }
+            }
+            let (sent, received) = peer.dropped;
+            ui.label(format!("{} / {}", sent, received));
+            ui.end_row();
+        }
+    });
+}
+
+fn state_section(ui: &mut egui::Ui, state: &GameState) {
+    ui.label(format!(
+        "Round {} in {}",
+        state.round.number + 1,
+        state.round.arena.name()
+    ));
+    ui.label(format!("Rng: {:016x}", state.rng.state()));
+    let meteors = state.meteors.iter().flatten().count();
+    ui.label(format!("Meteors: {}", meteors));
+    egui::Grid::new("ships").striped(true).show(ui, |ui| {
+        for header in ["", "position", "velocity", "rotation", "score", "hull"] {
+            ui.strong(header);
+        }
+        ui.end_row();
+        for i in 0..state.num_players {
+            let (x, y) = state.positions[i];
+            let (vel_x, vel_y) = state.velocities[i];
+            ui.label(format!("P{}", i + 1));
+            ui.label(format!("{:.1}, {:.1}", x, y));
+            ui.label(format!("{:.2}, {:.2}", vel_x, vel_y));
+            ui.label(format!("{:.2}", state.rotations[i]));
+            ui.label(state.scores[i].to_string());
+            ui.label(state.hull[i].to_string());
+            ui.end_row();
+        }
+    });
+}
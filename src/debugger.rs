@@ -0,0 +1,122 @@
+use macroquad::prelude::*;
+use std::collections::VecDeque;
+
+use crate::{
+    game::{Frame, Game, GameState},
+    rollback_game::FixedStep,
+    Error,
+};
+
+// seconds of play that can be travelled back to
+const HISTORY_DURATION: f32 = 10.0;
+
+const SLIDER_HEIGHT: f32 = 20.0;
+const SLIDER_MARGIN: f32 = 20.0;
+// leaves room for the first input frames shown at the bottom of the window
+const SLIDER_BOTTOM: f32 = 50.0;
+
+// runs an offline game with local players only, keeping the states of the last frames around
+// while paused with P, the game can be scrubbed through with the slider or the arrow keys
+// resuming continues from the shown frame, throwing away everything after it
+pub async fn run(num_players: usize, game_state: GameState) -> Result<(), Error> {
+    let mut game = Game::from_state(game_state.clone());
+    let history_frames = game_state.tuning.frames(HISTORY_DURATION) as usize;
+    let tick = game_state.tuning.tick_duration();
+    let mut history = VecDeque::with_capacity(history_frames);
+    history.push_back(game_state);
+    // index of the shown state in `history` while paused
+    let mut cursor: Option<usize> = None;
+
+    // time variables for tick rate
+    let mut step = FixedStep::default();
+
+    prevent_quit();
+    while !is_quit_requested() {
+        if is_key_pressed(KeyCode::P) {
+            cursor = match cursor {
+                Some(index) => {
+                    history.truncate(index + 1);
+                    None
+                }
+                None => Some(history.len() - 1),
+            };
+        }
+        if let Some(index) = &mut cursor {
+            let last = history.len() - 1;
+            if is_key_pressed(KeyCode::Left) {
+                *index = index.saturating_sub(1);
+            }
+            if is_key_pressed(KeyCode::Right) {
+                *index = (*index + 1).min(last);
+            }
+            if is_mouse_button_down(MouseButton::Left) {
+                let (x, y) = mouse_position();
+                if (slider_top()..=slider_top() + SLIDER_HEIGHT).contains(&y) {
+                    let width = screen_width() - 2.0 * SLIDER_MARGIN;
+                    let fraction = ((x - SLIDER_MARGIN) / width).clamp(0.0, 1.0);
+                    *index = (fraction * last as f32).round() as usize;
+                }
+            }
+            if game.frame() != history[*index].frame {
+                game.load_state(history[*index].clone());
+            }
+        }
+
+        // get delta time from last iteration and accumulate it
+        step.accumulate();
+        let inputs: Vec<Option<u8>> = (0..num_players)
+            .map(|i| Some(game.local_input(i).buttons_pressed))
+            .collect();
+
+        // if enough time is accumulated, we run a frame
+        while step.tick(tick) {
+            if cursor.is_some() {
+                continue;
+            }
+            game.advance_offline(inputs.clone());
+            if history.len() == history_frames {
+                history.pop_front();
+            }
+            history.push_back(game.state().clone());
+        }
+
+        let alpha = match cursor {
+            Some(_) => 1.0,
+            None => step.alpha(tick),
+        };
+        game.render(alpha);
+        render_slider(&history, cursor);
+        step.draw_catch_up();
+        next_frame().await;
+    }
+    Ok(())
+}
+
+fn slider_top() -> f32 {
+    screen_height() - SLIDER_BOTTOM - SLIDER_HEIGHT
+}
+
+// shows the range of frames that can be travelled to and the shown one
+fn render_slider(history: &VecDeque<GameState>, cursor: Option<usize>) {
+    let (first, last) = match (history.front(), history.back()) {
+        (Some(first), Some(last)) => (first.frame, last.frame),
+        _ => return,
+    };
+    let top = slider_top();
+    let width = screen_width() - 2.0 * SLIDER_MARGIN;
+    let color = if cursor.is_some() { YELLOW } else { GRAY };
+    draw_rectangle_lines(SLIDER_MARGIN, top, width, SLIDER_HEIGHT, 2.0, color);
+
+    let shown: Frame = cursor.map_or(last, |index| history[index].frame);
+    let fraction = (shown - first) as f32 / (last - first).max(1) as f32;
+    let x = SLIDER_MARGIN + fraction * width;
+    draw_rectangle(x - 3.0, top, 6.0, SLIDER_HEIGHT, color);
+
+    let status = if cursor.is_some() {
+        "paused, drag or use the arrow keys to travel"
+    } else {
+        "press P to pause"
+    };
+    let status_str = format!("Frame {} of {}-{}, {}", shown, first, last, status);
+    draw_text(&status_str, SLIDER_MARGIN, top - 10.0, 20.0, color);
+}
@@ -0,0 +1,140 @@
+use backroll_transport::Peer;
+use bevy_tasks::TaskPool;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::transport::wrap_peer;
+
+// every message starts with its counter and the random nonce it was encrypted with
+const COUNTER_LEN: usize = 8;
+const NONCE_LEN: usize = 24;
+
+// SessionKey encrypts and authenticates all messages between peers sharing the same key
+// messages are bound to their sender and receiver, so they can't be reflected back to the sender,
+// and counted, so they can't be recorded and sent again
+#[derive(Clone)]
+pub struct SessionKey {
+    cipher: XChaCha20Poly1305,
+}
+
+impl SessionKey {
+    // derives the key from a passphrase all peers agreed on beforehand
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"box game session key\0");
+        hasher.update(passphrase.as_bytes());
+        Self {
+            cipher: XChaCha20Poly1305::new(&hasher.finalize()),
+        }
+    }
+
+    // wraps the peer of a remote player, the returned peer is handed to backroll
+    // `local_player` and `remote_player` are the indices of the players in the player list
+    // forged, corrupted, misdirected or replayed messages are dropped
+    pub fn wrap(
+        &self,
+        pool: &TaskPool,
+        network: Peer,
+        local_player: usize,
+        remote_player: usize,
+    ) -> Peer {
+        let outgoing_aad = [local_player as u8, remote_player as u8];
+        let incoming_aad = [remote_player as u8, local_player as u8];
+        // the counter of the last message sent and accepted, counting starts at 1 in each direction
+        let (sent, accepted) = (AtomicU64::new(0), AtomicU64::new(0));
+        let (encrypting, decrypting) = (self.clone(), self.clone());
+        wrap_peer(
+            pool,
+            network,
+            move |message| {
+                let counter = sent.fetch_add(1, Ordering::Relaxed) + 1;
+                encrypting.encrypt(message, &outgoing_aad, counter)
+            },
+            move |message| decrypting.decrypt(message, &incoming_aad, &accepted),
+        )
+    }
+
+    fn encrypt(&self, message: &[u8], direction: &[u8], counter: u64) -> Option<Box<[u8]>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let counter = counter.to_le_bytes();
+        let aad = [direction, &counter].concat();
+        let payload = Payload {
+            msg: message,
+            aad: &aad,
+        };
+        let ciphertext = self.cipher.encrypt(&nonce, payload).ok()?;
+        let mut encrypted = counter.to_vec();
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Some(encrypted.into_boxed_slice())
+    }
+
+    // messages overtaken by a newer one on the way are dropped too, backroll treats them as lost
+    fn decrypt(&self, message: &[u8], direction: &[u8], accepted: &AtomicU64) -> Option<Box<[u8]>> {
+        if message.len() < COUNTER_LEN + NONCE_LEN {
+            return None;
+        }
+        let (counter, rest) = message.split_at(COUNTER_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let aad = [direction, counter].concat();
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &aad,
+        };
+        let decrypted = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .ok()?;
+        // the counter is authenticated, so only after decrypting it can be trusted
+        let counter = u64::from_le_bytes(counter.try_into().ok()?);
+        if accepted.fetch_max(counter, Ordering::Relaxed) >= counter {
+            return None;
+        }
+        Some(decrypted.into_boxed_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_addressed_peer_can_decrypt() {
+        let key = SessionKey::from_passphrase("correct horse battery staple");
+        let encrypted = key.encrypt(b"input", &[0, 1], 1).unwrap();
+        let decrypt = |key: &SessionKey, direction: &[u8]| {
+            key.decrypt(&encrypted, direction, &AtomicU64::new(0))
+        };
+        assert_eq!(&*decrypt(&key, &[0, 1]).unwrap(), b"input");
+        // reflected back to the sender
+        assert!(decrypt(&key, &[1, 0]).is_none());
+        // encrypted with a different key
+        let other = SessionKey::from_passphrase("wrong passphrase");
+        assert!(decrypt(&other, &[0, 1]).is_none());
+    }
+
+    #[test]
+    fn replayed_messages_are_dropped() {
+        let key = SessionKey::from_passphrase("correct horse battery staple");
+        let accepted = AtomicU64::new(0);
+        let first = key.encrypt(b"first", &[0, 1], 1).unwrap();
+        let second = key.encrypt(b"second", &[0, 1], 2).unwrap();
+        assert_eq!(
+            &*key.decrypt(&second, &[0, 1], &accepted).unwrap(),
+            b"second"
+        );
+        // sent again, or overtaken by a newer message
+        assert!(key.decrypt(&second, &[0, 1], &accepted).is_none());
+        assert!(key.decrypt(&first, &[0, 1], &accepted).is_none());
+        // a recorded message with its counter raised fails to authenticate
+        let mut raised = first.to_vec();
+        raised[..COUNTER_LEN].copy_from_slice(&3u64.to_le_bytes());
+        assert!(key.decrypt(&raised, &[0, 1], &accepted).is_none());
+        let third = key.encrypt(b"third", &[0, 1], 3).unwrap();
+        assert_eq!(&*key.decrypt(&third, &[0, 1], &accepted).unwrap(), b"third");
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    fmt::Display,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+use crate::{
+    game::Frame,
+    handshake::{PlayerOrder, HANDSHAKE_TIMEOUT},
+};
+
+// Error is what can go wrong around the game: loading and saving files, connecting to peers and
+// starting a session, each with enough context to be told apart by what shows it to the player
+// the simulation itself can't fail
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to write {}: {source}", path.display())]
+    Write { path: PathBuf, source: io::Error },
+    // the file was read, but its contents can't be used, e.g. a config edited by hand or a replay
+    // of another version
+    #[error("{} is not a valid {kind}: {reason}", path.display())]
+    Corrupt {
+        path: PathBuf,
+        kind: &'static str,
+        reason: String,
+    },
+    #[error("failed to encode the {kind}: {reason}")]
+    Encode { kind: &'static str, reason: String },
+    #[error("no directory to keep files in, {0}")]
+    NoDataDirectory(&'static str),
+    // the command line asks for something that can't work
+    #[error("{0}")]
+    InvalidOptions(&'static str),
+    #[error("at most {0} players can share the keyboard")]
+    TooManyLocalPlayers(usize),
+    #[error("failed to resolve {addr}: {reason}")]
+    Resolve { addr: String, reason: String },
+    #[error("failed to listen on {addr}: {source}")]
+    Listen { addr: SocketAddr, source: io::Error },
+    #[error("failed to start the session: {0}")]
+    Session(#[from] backroll::BackrollError),
+    // a profile that doesn't decode, e.g. from a peer running an incompatible version
+    #[error("malformed profile, {0}")]
+    MalformedProfile(&'static str),
+    #[error(
+        "frames {start} to {end} are not within the replay, it covers frames {first} to {last}"
+    )]
+    FramesOutOfRange {
+        start: Frame,
+        end: Frame,
+        first: Frame,
+        last: Frame,
+    },
+    // a peer that lists the players in another order than we do, their inputs would be mixed up
+    #[error(
+        "{name} lists their players as {reported}, but they are {expected} here, all peers have to list the players in the same order"
+    )]
+    PlayerOrderMismatch {
+        name: String,
+        expected: PlayerOrder,
+        reported: PlayerOrder,
+    },
+    // a peer simulating another number of frames per second, it would run at another speed
+    #[error("{name} runs at {remote} ticks per second, but it is {local} here, all peers have to use the same --tick-rate")]
+    TickRateMismatch {
+        name: String,
+        local: u32,
+        remote: u32,
+    },
+    // a peer that connected, but never told us who they are, e.g. because they block us
+    #[error(
+        "player {} sent no profile within {} seconds",
+        .player + 1,
+        HANDSHAKE_TIMEOUT.as_secs()
+    )]
+    HandshakeTimeout { player: usize },
+    // a peer playing with other gameplay options, e.g. another --ship-scale
+    #[error("{name} plays with other gameplay options, all peers have to pick the same --movement-model, --ship-scale, --maps and rules")]
+    TuningMismatch { name: String },
+    #[error("no opponent with an id starting with {0}")]
+    UnknownOpponent(String),
+    #[error("several opponents have ids starting with {0}")]
+    AmbiguousOpponent(String),
+    #[error("{failed} of {total} scenarios failed")]
+    ScenariosFailed { failed: usize, total: usize },
+    // a line of a file written by hand, like an input script or a scenario, that can't be parsed
+    #[error("invalid {kind} line {number}: {line} ({reason})")]
+    InvalidLine {
+        kind: &'static str,
+        number: usize,
+        line: String,
+        reason: String,
+    },
+    // a delta snapshot that doesn't apply to the last decoded state, e.g. after a lost snapshot,
+    // decoding starts over with the next keyframe
+    #[error("snapshot for frame {frame} is missing its base frame {base_frame}")]
+    MissingBaseFrame { frame: Frame, base_frame: Frame },
+    #[error("snapshot for unknown player {0}")]
+    UnknownPlayer(usize),
+    #[error("snapshot for unknown meteor slot {0}")]
+    UnknownMeteor(usize),
+    // the simulation depends on something outside of the game state
+    #[error("frame {frame} changed its checksum after a rollback of {distance} frames")]
+    RollbackChecksumMismatch { frame: Frame, distance: Frame },
+    #[error("frame {0} differs after a rollback")]
+    RollbackStateMismatch(Frame),
+    // two peers of the fuzzer that ended up in different states
+    #[error("periodic checksums differ on frame {frame}, {cause}")]
+    Desync { frame: Frame, cause: &'static str },
+    #[error("the server rejected the request: {0}")]
+    RequestRejected(String),
+    #[error("failed to pin the thread to core {core}, {reason}")]
+    PinToCore { core: usize, reason: String },
+    #[error("failed to map the port on the router, {step}: {reason}")]
+    PortMapping { step: &'static str, reason: String },
+    #[error("{0} isn't supported on this platform")]
+    Unsupported(&'static str),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl Error {
+    // for `map_err`, e.g. `fs::read(path).map_err(Error::read(path))`
+    pub fn read(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Read {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn write(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Write {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn corrupt<'a, E: Display>(
+        path: &'a Path,
+        kind: &'static str,
+    ) -> impl FnOnce(E) -> Self + 'a {
+        move |e| Self::Corrupt {
+            path: path.to_owned(),
+            kind,
+            reason: e.to_string(),
+        }
+    }
+
+    pub fn encode<E: Display>(kind: &'static str) -> impl FnOnce(E) -> Self {
+        move |e| Self::Encode {
+            kind,
+            reason: e.to_string(),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+// pings are measured for this long after all peers synchronized, which is about the countdown
+const MEASURE_DURATION: Duration = Duration::from_secs(3);
+
+// FrameDelayTuner picks a frame delay from the round trip time to the worst peer
+// measured at the start of the session
+pub struct FrameDelayTuner {
+    min: usize,
+    max: usize,
+    // the time a frame takes at the tick rate of the match
+    tick: Duration,
+    start: Option<Instant>,
+    // sum and count of the pings of every remote player
+    pings: Vec<(Duration, u32)>,
+    done: bool,
+}
+
+impl FrameDelayTuner {
+    pub fn new(min: usize, max: usize, num_players: usize, tick: Duration) -> Self {
+        Self {
+            min,
+            max: max.max(min),
+            tick,
+            start: None,
+            pings: vec![(Duration::ZERO, 0); num_players],
+            done: false,
+        }
+    }
+
+    // adds a ping sample of a remote player, once the session is running
+    pub fn sample(&mut self, player: usize, ping: Duration) {
+        if self.done {
+            return;
+        }
+        self.start.get_or_insert_with(Instant::now);
+        let (sum, count) = &mut self.pings[player];
+        *sum += ping;
+        *count += 1;
+    }
+
+    // returns the frame delay and the ping it is based on once, after the pings were measured
+    pub fn poll(&mut self) -> Option<(usize, Duration)> {
+        let start = self.start?;
+        if self.done || start.elapsed() < MEASURE_DURATION {
+            return None;
+        }
+        self.done = true;
+        let worst = self
+            .pings
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|&(sum, count)| sum / count)
+            .max()
+            .unwrap_or_default();
+        Some((pick(worst, self.tick, self.min, self.max), worst))
+    }
+}
+
+// delays local inputs by the frames they take to reach the worst peer, so they usually arrive
+// before that peer simulates them and no rollback is needed
+fn pick(ping: Duration, tick: Duration, min: usize, max: usize) -> usize {
+    let one_way_frames = (ping.as_secs_f32() / 2.0 / tick.as_secs_f32()).ceil() as usize;
+    one_way_frames.clamp(min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_covers_half_the_round_trip() {
+        let tick = Duration::from_secs_f32(1.0 / 60.0);
+        assert_eq!(pick(Duration::ZERO, tick, 0, 4), 0);
+        // 40 ms one way are a little more than two frames
+        assert_eq!(pick(Duration::from_millis(80), tick, 0, 4), 3);
+        assert_eq!(pick(Duration::from_millis(500), tick, 0, 4), 4);
+        assert_eq!(pick(Duration::from_millis(10), tick, 2, 4), 2);
+        // and almost five at 120 ticks per second
+        assert_eq!(pick(Duration::from_millis(80), tick / 2, 0, 8), 5);
+    }
+}
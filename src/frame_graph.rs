@@ -0,0 +1,91 @@
+use macroquad::prelude::*;
+use std::{collections::VecDeque, time::Duration};
+
+// number of rendered frames shown in the graph
+const SAMPLES: usize = 120;
+const BAR_WIDTH: f32 = 2.0;
+const GRAPH_HEIGHT: f32 = 100.0;
+// the top of the graph corresponds to this many ticks worth of time
+const GRAPH_TICKS: f32 = 2.0;
+
+const SIMULATION_COLOR: Color = GREEN;
+const RESIMULATION_COLOR: Color = ORANGE;
+const RENDER_COLOR: Color = SKYBLUE;
+
+// time spent on one rendered frame, including all frames simulated before it
+#[derive(Clone, Copy, Default)]
+struct FrameTimes {
+    simulation: Duration,
+    resimulation: Duration,
+    render: Duration,
+}
+
+// FrameGraph keeps the frame times of the last rendered frames and draws them as a scrolling graph
+// multiple frames simulated before rendering to catch up show up as tall simulation bars
+#[derive(Default)]
+pub struct FrameGraph {
+    samples: VecDeque<FrameTimes>,
+    current: FrameTimes,
+}
+
+impl FrameGraph {
+    // adds the time spent advancing a frame, `resimulation` is set for frames replayed after a rollback
+    pub fn add_simulation(&mut self, duration: Duration, resimulation: bool) {
+        if resimulation {
+            self.current.resimulation += duration;
+        } else {
+            self.current.simulation += duration;
+        }
+    }
+
+    // completes the sample of the current frame with the time it took to render
+    // returns the total time spent on the frame
+    pub fn finish_frame(&mut self, render: Duration) -> Duration {
+        self.current.render = render;
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(self.current);
+        let FrameTimes {
+            simulation,
+            resimulation,
+            render,
+        } = std::mem::take(&mut self.current);
+        simulation + resimulation + render
+    }
+
+    // allocates the room for all samples up front
+    pub fn reserve(&mut self) {
+        self.samples.reserve(SAMPLES);
+    }
+
+    // `tick` is the time a tick takes, everything is drawn relative to it
+    pub fn draw(&self, x: f32, y: f32, tick: Duration) {
+        let width = SAMPLES as f32 * BAR_WIDTH;
+        draw_rectangle(x, y, width, GRAPH_HEIGHT, Color::new(0.0, 0.0, 0.0, 0.6));
+
+        let scale = GRAPH_HEIGHT / (GRAPH_TICKS * tick.as_secs_f32());
+        for (i, sample) in self.samples.iter().enumerate() {
+            let bar_x = x + i as f32 * BAR_WIDTH;
+            let mut bar_y = y + GRAPH_HEIGHT;
+            for (duration, color) in [
+                (sample.simulation, SIMULATION_COLOR),
+                (sample.resimulation, RESIMULATION_COLOR),
+                (sample.render, RENDER_COLOR),
+            ] {
+                let height = (duration.as_secs_f32() * scale).min(bar_y - y);
+                bar_y -= height;
+                draw_rectangle(bar_x, bar_y, BAR_WIDTH, height, color);
+            }
+        }
+
+        // everything above this line takes longer than a frame
+        let budget_y = y + GRAPH_HEIGHT - GRAPH_HEIGHT / GRAPH_TICKS;
+        draw_line(x, budget_y, x + width, budget_y, 1.0, RED);
+
+        let legend_y = y + GRAPH_HEIGHT + 15.0;
+        draw_text("simulation", x, legend_y, 20.0, SIMULATION_COLOR);
+        draw_text("rollback", x + 85.0, legend_y, 20.0, RESIMULATION_COLOR);
+        draw_text("render", x + 155.0, legend_y, 20.0, RENDER_COLOR);
+    }
+}
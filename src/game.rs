@@ -1,277 +1,1015 @@
-use backroll::{
-    command::{Command, Commands},
-    Event, GameInput, PlayerHandle,
-};
-use bytemuck::*;
+// Game runs the simulation inside a session: it answers the requests of backroll, keeps the
+// inputs and checksums peers compare, and shows the state of the connections
+// the simulation, the inputs, rendering and checksums live in the submodules
+mod checksum;
+mod input;
+mod render;
+mod state;
+mod visual;
+
+use backroll::{Event, GameInput, NetworkStats, PlayerHandle};
 use macroquad::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use crate::BackrollConfig;
+#[cfg(unix)]
+use crate::observer::{Observer, ObserverMessage};
+use crate::{
+    color::{self, PlayerColor},
+    config::ControlScheme,
+    debug_panel::{DebugPanel, PeerStats},
+    frame_graph::FrameGraph,
+    input_log::{InputLog, INPUT_LOG_FRAMES},
+    metrics::Metrics,
+    pacing::Jitter,
+    profile::SessionProfile,
+    replay::{NetLog, NetSample, Replay},
+    rollback_game::RollbackGame,
+    rollback_stats::RollbackStats,
+    toast::Toasts,
+    upload::MatchStats,
+};
+use render::RenderState;
+use state::NULL_FRAME;
+use visual::VisualState;
+
+pub use checksum::{determinism_self_test, scripted_input, CHECKSUM_PERIOD};
+// other games hash their states the same way
+pub(crate) use checksum::Fnv1a64;
+pub use input::{
+    FrameInputs, PlayerInput, CONTROL_SCHEMES, INPUT_DOWN, INPUT_LEFT, INPUT_RIGHT,
+    INPUT_TOGGLE_THRUST, INPUT_UP, MAX_LOCAL_PLAYERS,
+};
+pub use render::{cache_glyphs, player_color};
+pub use state::{
+    parse_ship_scale, Frame, GameState, MatchEnd, MovementModel, Round, RoundTransition, Tuning,
+    FPS, MAX_METEORS, MAX_PLAYERS, WINDOW_HEIGHT, WINDOW_WIDTH,
+};
 
-type Frame = i32;
+// backroll never predicts further ahead than this many frames
+pub const PREDICTION_WINDOW: usize = 8;
+
+// ticks are stretched or shortened by at most this fraction of a frame to keep peers in step
+const TIME_SYNC_RATE: f32 = 0.1;
+
+// confirmed input hashes are kept for this many frames, enough to resimulate every frame that
+// can still be rolled back to
+const CONFIRMED_INPUT_HASHES: usize = 2 * PREDICTION_WINDOW;
+// frames simulated on a throwaway copy of the state before the match starts
+const WARM_UP_FRAMES: usize = 4 * PREDICTION_WINDOW;
+// recordings reserve room for this many frames up front, five minutes at the default tick rate
+const RESERVED_RECORDING_FRAMES: usize = (5.0 * 60.0 * FPS) as usize;
+
+// StateDump captures everything needed to investigate a desync at a given frame
+#[derive(Serialize)]
+struct StateDump {
+    frame: Frame,
+    checksum: u64,
+    input_hash: u64,
+    // inputs of the last `PREDICTION_WINDOW` frames leading up to `frame`
+    inputs: Vec<FrameInputs>,
+    state: GameState,
+}
 
-pub const FPS: f32 = 60.0;
-const CHECKSUM_PERIOD: i32 = 100;
-const NULL_FRAME: Frame = -1;
+fn write_dump(path: &Path, dump: &StateDump) {
+    let result = File::create(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| serde_json::to_writer_pretty(file, dump).map_err(|e| e.to_string()));
+    match result {
+        Ok(_) => println!("Wrote {}", path.display()),
+        Err(e) => println!("Failed to write {}: {}", path.display(), e),
+    }
+}
 
-const SHIP_HEIGHT: f32 = 50.;
-const SHIP_BASE: f32 = 40.;
-const WINDOW_HEIGHT: f32 = 800.0;
-const WINDOW_WIDTH: f32 = 600.0;
+// the message shown to players about a session event, and the player it is about
+fn event_toast(event: &Event) -> Option<(Option<usize>, String, Color)> {
+    let toast = match *event {
+        Event::Connected(player) => (
+            Some(player.0),
+            format!("P{} connected", player.0 + 1),
+            WHITE,
+        ),
+        Event::Synchronizing {
+            player,
+            count,
+            total,
+        } => (
+            Some(player.0),
+            format!("Synchronizing with P{}: {}/{}", player.0 + 1, count, total),
+            WHITE,
+        ),
+        Event::Synchronized(player) => (
+            Some(player.0),
+            format!("P{} synchronized", player.0 + 1),
+            GREEN,
+        ),
+        Event::Running => (None, "All players are in".to_owned(), GREEN),
+        Event::ConnectionInterrupted { player, .. } => (
+            Some(player.0),
+            format!("Connection to P{} interrupted", player.0 + 1),
+            ORANGE,
+        ),
+        Event::ConnectionResumed(player) => (
+            Some(player.0),
+            format!("Connection to P{} resumed", player.0 + 1),
+            GREEN,
+        ),
+        Event::Disconnected(player) => (
+            Some(player.0),
+            format!("P{} disconnected", player.0 + 1),
+            RED,
+        ),
+        // time syncs happen all the time and are handled without the players noticing
+        Event::TimeSync { .. } => return None,
+    };
+    Some(toast)
+}
 
-const INPUT_UP: u8 = 1 << 0;
-const INPUT_DOWN: u8 = 1 << 1;
-const INPUT_LEFT: u8 = 1 << 2;
-const INPUT_RIGHT: u8 = 1 << 3;
+// ConnectionQuality summarizes the network stats of a remote player
+#[derive(Clone, Copy)]
+enum ConnectionQuality {
+    Good,
+    Fair,
+    Poor,
+}
 
-const MOVEMENT_SPEED: f32 = 15.0 / FPS;
-const ROTATION_SPEED: f32 = 2.5 / FPS;
-const MAX_SPEED: f32 = 7.0;
-const FRICTION: f32 = 0.98;
+impl ConnectionQuality {
+    // inputs piling up in the send queue are not being acknowledged, hinting at packet loss
+    fn new(stats: &NetworkStats) -> Self {
+        let ping = stats.ping.as_millis();
+        if ping < 80 && stats.send_queue_len < 10 {
+            Self::Good
+        } else if ping < 150 && stats.send_queue_len < 30 {
+            Self::Fair
+        } else {
+            Self::Poor
+        }
+    }
 
-#[repr(C)]
-#[derive(Clone, Copy, Eq, PartialEq, Pod, Zeroable)]
-pub struct PlayerInput {
-    pub buttons_pressed: u8,
+    fn color(&self) -> Color {
+        match self {
+            Self::Good => GREEN,
+            Self::Fair => YELLOW,
+            Self::Poor => RED,
+        }
+    }
 }
 
-// BoxGameState holds all relevant information about the game state
-#[derive(Clone, Serialize, Deserialize)]
-pub struct GameState {
-    pub frame: i32,
-    pub num_players: usize,
-    pub positions: Vec<(f32, f32)>,
-    pub velocities: Vec<(f32, f32)>,
-    pub rotations: Vec<f32>,
+// PeerStatus is the connection state of a remote player, as reported by backroll events
+#[derive(Clone, Copy)]
+enum PeerStatus {
+    Connected,
+    Synchronizing {
+        count: u8,
+        total: u8,
+    },
+    Synchronized,
+    Interrupted {
+        since: Instant,
+        disconnect_timeout: Duration,
+    },
+    Disconnected,
 }
 
-impl GameState {
-    pub fn new(num_players: usize) -> Self {
-        let mut positions = Vec::new();
-        let mut velocities = Vec::new();
-        let mut rotations = Vec::new();
+impl PeerStatus {
+    // text shown below the ship of the player, `None` while the connection is fine
+    fn label(&self) -> Option<String> {
+        match self {
+            Self::Connected => Some("connected".to_owned()),
+            Self::Synchronizing { count, total } => Some(format!("syncing {}/{}", count, total)),
+            Self::Synchronized => None,
+            Self::Interrupted {
+                since,
+                disconnect_timeout,
+            } => {
+                let remaining = disconnect_timeout.saturating_sub(since.elapsed());
+                Some(format!("dropping in {:.1}s", remaining.as_secs_f32()))
+            }
+            Self::Disconnected => Some("disconnected".to_owned()),
+        }
+    }
+}
 
-        let r = WINDOW_WIDTH / 4.0;
+// BoxGame will handle rendering, gamestate, inputs and GGRSRequests
+pub struct Game {
+    num_players: usize,
+    game_state: GameState,
+    last_checksum: (Frame, u64),
+    periodic_checksum: (Frame, u64),
+    // hashes of the confirmed inputs up to the last confirmed frame and up to the periodic
+    // checksum frame, predicted inputs never make it in
+    input_hash: u64,
+    periodic_input_hash: u64,
+    // the input hash after each of the last confirmed frames, saved states pick theirs from here
+    confirmed_input_hashes: VecDeque<(Frame, u64)>,
+    // frames the local loop still has to slow down by (positive) or speed up by (negative) to
+    // stay in step with the other peers, paid off a little every tick
+    time_drift: f32,
+    input_history: VecDeque<FrameInputs>,
+    // inputs that left `input_history` and can't be rolled back anymore
+    input_log: InputLog,
+    periodic_dump: Option<StateDump>,
+    // desync dumps and snapshots are written here
+    dump_dir: PathBuf,
+    // the first local player steers with `controls`, a second one with the other scheme
+    controls: ControlScheme,
+    // the up key toggles thrust instead of being held
+    toggle_thrust: bool,
+    // names the players picked, remote ones are known once they arrived
+    names: Vec<Option<String>>,
+    // colors the players picked, and the colors ships are drawn in after resolving collisions
+    chosen_colors: Vec<PlayerColor>,
+    colors: Vec<Color>,
+    previous_render_state: RenderState,
+    // the ships as they are shown, eased after rollbacks
+    visual: VisualState,
+    // mispredicted ship positions captured right before the last rollback
+    rollback_ghost: Option<(RenderState, Instant)>,
+    // number of frames where the mirrored simulation disagreed, `None` if the check is disabled
+    mirror_mismatches: Option<u32>,
+    recording: Option<Replay>,
+    // network stats of the recorded frames, saved next to the replay
+    net_log: NetLog,
+    // the frame the last rollback went back from and how many frames it went back
+    last_rollback: Option<(Frame, Frame)>,
+    // ship of a previous run to race against
+    race_ghost: Option<RenderState>,
+    #[cfg(unix)]
+    observer: Option<Observer>,
+    // connection quality of remote players, indexed by player handle
+    connection_quality: Vec<Option<ConnectionQuality>>,
+    // connection state of remote players, indexed by player handle
+    peer_status: Vec<Option<PeerStatus>>,
+    // last network stats of remote players, indexed by player handle
+    network_stats: Vec<Option<PeerStats>>,
+    // messages to and from remote players dropped by full bounded queues, indexed by player handle
+    dropped_messages: Vec<(u64, u64)>,
+    // set once all players are synchronized and the match started
+    running: bool,
+    frame_graph: FrameGraph,
+    debug_panel: DebugPanel,
+    profile: SessionProfile,
+    rollback_stats: RollbackStats,
+    pacing_jitter: Jitter,
+    toasts: Toasts,
+    metrics: Option<Arc<Metrics>>,
+}
 
-        for i in 0..num_players as i32 {
-            let rot = i as f32 / num_players as f32 * 2.0 * std::f32::consts::PI;
-            let x = WINDOW_WIDTH / 2.0 + r * rot.cos();
-            let y = WINDOW_HEIGHT / 2.0 + r * rot.sin();
-            positions.push((x, y));
-            velocities.push((0.0, 0.0));
-            rotations.push((rot + std::f32::consts::PI) % (2.0 * std::f32::consts::PI));
-        }
+impl Game {
+    pub fn new(num_players: usize, tuning: Tuning, seed: u64) -> Self {
+        Self::from_state(GameState::new(num_players, tuning, seed))
+    }
 
+    // creates a game continuing from the given state
+    pub fn from_state(game_state: GameState) -> Self {
         Self {
-            frame: 0,
-            num_players,
-            positions,
-            velocities,
-            rotations,
-        }
-    }
-
-    pub fn advance(&mut self, inputs: GameInput<PlayerInput>) {
-        // increase the frame counter
-        self.frame += 1;
-
-        for i in 0..self.num_players {
-            let handle = PlayerHandle(i);
-            // get input of that player
-            let input = if inputs.is_disconnected(handle).unwrap() {
-                // disconnected players spin
-                INPUT_LEFT
-            } else {
-                inputs.get(handle).unwrap().buttons_pressed
-            };
+            num_players: game_state.num_players,
+            previous_render_state: RenderState::new(&game_state),
+            visual: VisualState::new(&game_state),
+            rollback_ghost: None,
+            mirror_mismatches: None,
+            recording: None,
+            net_log: NetLog::default(),
+            last_rollback: None,
+            race_ghost: None,
+            #[cfg(unix)]
+            observer: None,
+            connection_quality: vec![None; game_state.num_players],
+            peer_status: vec![None; game_state.num_players],
+            network_stats: vec![None; game_state.num_players],
+            dropped_messages: vec![(0, 0); game_state.num_players],
+            names: vec![None; game_state.num_players],
+            chosen_colors: (0..game_state.num_players)
+                .map(PlayerColor::default_for)
+                .collect(),
+            colors: (0..game_state.num_players).map(player_color).collect(),
+            // games without a session don't have to wait for anyone
+            running: true,
+            frame_graph: FrameGraph::default(),
+            debug_panel: DebugPanel::default(),
+            profile: SessionProfile::default(),
+            rollback_stats: RollbackStats::default(),
+            pacing_jitter: Jitter::default(),
+            toasts: Toasts::default(),
+            metrics: None,
+            game_state,
+            last_checksum: (NULL_FRAME, 0),
+            periodic_checksum: (NULL_FRAME, 0),
+            input_hash: 0,
+            periodic_input_hash: 0,
+            confirmed_input_hashes: VecDeque::with_capacity(CONFIRMED_INPUT_HASHES),
+            time_drift: 0.0,
+            input_history: VecDeque::with_capacity(PREDICTION_WINDOW),
+            input_log: InputLog::new(INPUT_LOG_FRAMES),
+            periodic_dump: None,
+            dump_dir: PathBuf::new(),
+            controls: ControlScheme::Wasd,
+            toggle_thrust: false,
+        }
+    }
 
-            // old values
-            let (old_x, old_y) = self.positions[i];
-            let (old_vel_x, old_vel_y) = self.velocities[i];
-            let mut rot = self.rotations[i];
+    // runs a second simulation with swapped players next to the real one to catch
+    // bugs where the outcome depends on the player order
+    pub fn enable_mirror_check(&mut self) {
+        self.mirror_mismatches = Some(0);
+    }
 
-            // slow down
-            let mut vel_x = old_vel_x * FRICTION;
-            let mut vel_y = old_vel_y * FRICTION;
+    // records the inputs of all following frames into a replay
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Replay::new(self.game_state.clone()));
+        self.net_log = NetLog::default();
+    }
 
-            // thrust
-            if input & INPUT_UP != 0 && input & INPUT_DOWN == 0 {
-                vel_x += MOVEMENT_SPEED * rot.cos();
-                vel_y += MOVEMENT_SPEED * rot.sin();
-            }
-            // break
-            if input & INPUT_UP == 0 && input & INPUT_DOWN != 0 {
-                vel_x -= MOVEMENT_SPEED * rot.cos();
-                vel_y -= MOVEMENT_SPEED * rot.sin();
-            }
-            // turn left
-            if input & INPUT_LEFT != 0 && input & INPUT_RIGHT == 0 {
-                rot = (rot - ROTATION_SPEED).rem_euclid(2.0 * std::f32::consts::PI);
-            }
-            // turn right
-            if input & INPUT_LEFT == 0 && input & INPUT_RIGHT != 0 {
-                rot = (rot + ROTATION_SPEED).rem_euclid(2.0 * std::f32::consts::PI);
+    // runs the simulation for a few frames on a copy of the state and reserves the buffers that
+    // grow during the match, so the first frames don't stall on cold caches and allocations,
+    // which would make backroll correct the timing right away
+    pub fn warm_up(&mut self) {
+        let mut state = self.game_state.clone();
+        // the countdown would skip most of the simulation
+        state.frame = state.frame.max(state.tuning.countdown_frames());
+        let inputs = vec![Some(0); self.num_players];
+        for _ in 0..WARM_UP_FRAMES {
+            if self.mirror_mismatches.is_some() {
+                state.mirrored().advance(&inputs);
             }
+            state.advance(&inputs);
+            std::hint::black_box(state.checksum());
+        }
+        self.input_history
+            .reserve(PREDICTION_WINDOW.saturating_sub(self.input_history.len()));
+        if let Some(replay) = &mut self.recording {
+            replay.reserve(RESERVED_RECORDING_FRAMES);
+        }
+        self.frame_graph.reserve();
+    }
 
-            // limit speed
-            let magnitude = (vel_x * vel_x + vel_y * vel_y).sqrt();
-            if magnitude > MAX_SPEED {
-                vel_x = (vel_x * MAX_SPEED) / magnitude;
-                vel_y = (vel_y * MAX_SPEED) / magnitude;
-            }
+    // ends the recording and returns the replay
+    // the last frames are left out, since their inputs might still be mispredicted
+    pub fn take_recording(&mut self) -> Option<Replay> {
+        let mut replay = self.recording.take()?;
+        replay.truncate(replay.last_frame() - PREDICTION_WINDOW as Frame);
+        Some(replay)
+    }
 
-            // compute new position
-            let mut x = old_x + vel_x;
-            let mut y = old_y + vel_y;
+    // sums up the session for uploads
+    pub fn match_stats(&self) -> MatchStats {
+        MatchStats {
+            version: env!("CARGO_PKG_VERSION"),
+            players: self.num_players,
+            frames: self.game_state.frame,
+            round_wins: self.game_state.round_wins[..self.num_players].to_vec(),
+            checksum: self.game_state.checksum(),
+            periodic_checksum: self.periodic_checksum,
+            periodic_input_hash: self.periodic_input_hash,
+            rollbacks: self.rollback_stats.rollbacks(),
+            mirror_mismatches: self.mirror_mismatches,
+        }
+    }
 
-            // constrain players to canvas borders
-            x = x.max(0.0);
-            x = x.min(WINDOW_WIDTH);
-            y = y.max(0.0);
-            y = y.min(WINDOW_HEIGHT);
+    // streams confirmed frames and network stats to external tools
+    #[cfg(unix)]
+    pub fn set_observer(&mut self, mut observer: Observer) {
+        observer.start(self.game_state.clone());
+        self.observer = Some(observer);
+    }
 
-            // update all state
-            self.positions[i] = (x, y);
-            self.velocities[i] = (vel_x, vel_y);
-            self.rotations[i] = rot;
-        }
+    pub fn set_control_scheme(&mut self, controls: ControlScheme) {
+        self.controls = controls;
     }
-}
 
-/// computes the fletcher16 checksum, copied from wikipedia: <https://en.wikipedia.org/wiki/Fletcher%27s_checksum>
-fn fletcher16(data: &[u8]) -> u16 {
-    let mut sum1: u16 = 0;
-    let mut sum2: u16 = 0;
+    pub fn set_toggle_thrust(&mut self, toggle_thrust: bool) {
+        self.toggle_thrust = toggle_thrust;
+    }
 
-    for index in 0..data.len() {
-        sum1 = (sum1 + data[index] as u16) % 255;
-        sum2 = (sum2 + sum1) % 255;
+    pub fn set_name(&mut self, player: usize, name: String) {
+        self.names[player] = Some(name);
     }
 
-    (sum2 << 8) | sum1
-}
+    pub fn clear_name(&mut self, player: usize) {
+        self.names[player] = None;
+    }
 
-// BoxGame will handle rendering, gamestate, inputs and GGRSRequests
-pub struct Game {
-    num_players: usize,
-    game_state: GameState,
-    last_checksum: (Frame, u16),
-    periodic_checksum: (Frame, u16),
-    wait_frames: u8,
-}
+    pub fn set_color(&mut self, player: usize, color: PlayerColor) {
+        self.chosen_colors[player] = color;
+        self.colors = color::resolve(&self.chosen_colors);
+    }
 
-impl Game {
-    pub fn new(num_players: usize) -> Self {
-        assert!(num_players <= 4);
-        Self {
-            num_players,
-            game_state: GameState::new(num_players),
-            last_checksum: (NULL_FRAME, 0),
-            periodic_checksum: (NULL_FRAME, 0),
-            wait_frames: 0,
+    pub fn set_dump_dir(&mut self, dir: PathBuf) {
+        self.dump_dir = dir;
+    }
+
+    // reports session metrics to Prometheus
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    // updates the connection quality shown for a remote player and reports the stats to observers
+    pub fn update_network_stats(&mut self, handle: PlayerHandle, stats: &NetworkStats) {
+        if let Some(quality) = self.connection_quality.get_mut(handle.0) {
+            *quality = Some(ConnectionQuality::new(stats));
+        }
+        if let Some(network_stats) = self.network_stats.get_mut(handle.0) {
+            *network_stats = Some(PeerStats::new(stats));
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.set_ping(handle.0, Some(stats.ping));
+        }
+        // backroll only tells the peer that is ahead to slow down, the one behind speeds up by
+        // its half of the gap
+        if stats.local_frames_behind > 0 && self.time_drift <= 0.0 {
+            self.time_drift = self
+                .time_drift
+                .min(-(stats.local_frames_behind as f32) / 2.0);
+        }
+        #[cfg(unix)]
+        if let Some(observer) = &mut self.observer {
+            observer.send(&ObserverMessage::NetworkStats {
+                player: handle.0,
+                ping_ms: stats.ping.as_millis(),
+                send_queue_len: stats.send_queue_len,
+                kbps_sent: stats.kbps_sent,
+                local_frames_behind: stats.local_frames_behind,
+                remote_frames_behind: stats.remote_frames_behind,
+            });
         }
     }
 
-    pub fn handle_commands(&mut self, cmds: Commands<BackrollConfig>) {
-        for cmd in cmds.into_iter() {
-            match cmd {
-                Command::Save(save) => save.save_without_hash(self.game_state.clone()),
-                Command::Load(load) => self.game_state = load.load(),
-                Command::AdvanceFrame(inputs) => self.advance_frame(inputs),
-                Command::Event(event) => self.handle_event(event),
+    pub fn update_dropped_messages(&mut self, handle: PlayerHandle, sent: u64, received: u64) {
+        if let Some(dropped) = self.dropped_messages.get_mut(handle.0) {
+            *dropped = (sent, received);
+        }
+    }
+
+    fn confirm_frame(&mut self, mut confirmed: FrameInputs) {
+        self.input_hash = confirmed.hash_onto(self.input_hash);
+        confirmed.input_hash = self.input_hash;
+        if self.confirmed_input_hashes.len() == CONFIRMED_INPUT_HASHES {
+            self.confirmed_input_hashes.pop_front();
+        }
+        self.confirmed_input_hashes
+            .push_back((confirmed.frame, self.input_hash));
+        // the periodic checksum was taken `PREDICTION_WINDOW` frames ago, its inputs are final now
+        if confirmed.frame == self.periodic_checksum.0 {
+            self.periodic_input_hash = self.input_hash;
+            if let Some(dump) = &mut self.periodic_dump {
+                dump.input_hash = self.input_hash;
             }
         }
+        #[cfg(unix)]
+        if let Some(observer) = &mut self.observer {
+            observer.send_confirmed(confirmed.clone());
+        }
+        self.input_log.record(confirmed);
     }
 
-    fn handle_event(&mut self, event: Event) {
-        println!("Event: {:?}", event);
-        match event {
-            Event::TimeSync { frames_ahead } => self.wait_frames = frames_ahead,
-            _ => (),
+    // shows the first ship of `state` as a ghost to race against
+    pub fn set_race_ghost(&mut self, state: &GameState) {
+        self.race_ghost = Some(RenderState::new(state));
+    }
+
+    pub fn frame(&self) -> Frame {
+        self.game_state.frame
+    }
+
+    pub fn state(&self) -> &GameState {
+        &self.game_state
+    }
+
+    pub fn periodic_checksum(&self) -> (Frame, u64) {
+        self.periodic_checksum
+    }
+
+    // the hash of all inputs up to the frame of the periodic checksum, once that frame is confirmed
+    pub fn periodic_input_hash(&self) -> u64 {
+        self.periodic_input_hash
+    }
+
+    // the hash of the inputs that were confirmed when `frame` was first simulated, i.e. up to
+    // `PREDICTION_WINDOW` frames earlier
+    // it only depends on the frame, so resimulating the frame after a rollback gets the same hash
+    fn confirmed_input_hash(&self, frame: Frame) -> u64 {
+        let confirmed = frame - PREDICTION_WINDOW as Frame;
+        self.confirmed_input_hashes
+            .iter()
+            .rev()
+            .find(|(frame, _)| *frame <= confirmed)
+            .map_or(0, |(_, hash)| *hash)
+    }
+
+    // jumps to the given state, discarding everything that referred to the old one
+    pub fn load_state(&mut self, game_state: GameState) {
+        self.previous_render_state = RenderState::new(&game_state);
+        self.visual = VisualState::new(&game_state);
+        self.rollback_ghost = None;
+        self.input_history.clear();
+        self.input_log.clear();
+        self.confirmed_input_hashes.clear();
+        self.game_state = game_state;
+    }
+
+    // starts over with a fresh state from `seed` and the same rules, for offline games only
+    // everything shown about the old match goes as well, a recording starts over with the new one
+    pub fn restart(&mut self, seed: u64) {
+        let tuning = self.game_state.tuning;
+        self.load_state(GameState::new(self.num_players, tuning, seed));
+        self.last_checksum = (NULL_FRAME, 0);
+        self.periodic_checksum = (NULL_FRAME, 0);
+        self.input_hash = 0;
+        self.periodic_input_hash = 0;
+        self.periodic_dump = None;
+        self.toasts = Toasts::default();
+        if self.recording.is_some() {
+            self.start_recording();
         }
     }
 
-    fn advance_frame(&mut self, inputs: GameInput<PlayerInput>) {
+    // waits for all players to synchronize before the match is shown as running
+    pub fn start_session(&mut self) {
+        self.running = false;
+    }
+
+    fn set_peer_status(&mut self, player: PlayerHandle, status: PeerStatus) {
+        if let Some(peer_status) = self.peer_status.get_mut(player.0) {
+            *peer_status = Some(status);
+        }
+    }
+
+    // advances the game without a session, e.g. when playing back a replay
+    pub fn advance_offline(&mut self, buttons_pressed: Vec<Option<u8>>) {
+        let frame = self.game_state.frame + 1;
+        self.advance_with(FrameInputs {
+            frame,
+            buttons_pressed,
+            input_hash: 0,
+        });
+    }
+
+    fn advance_with(&mut self, frame_inputs: FrameInputs) {
+        let start = Instant::now();
+        let resimulation = self
+            .input_history
+            .back()
+            .is_some_and(|last| last.frame >= frame_inputs.frame);
+
+        // remember the inputs of the frame we are about to simulate
+        // after a rollback, the resimulated frames overwrite their mispredicted entries
+        while self
+            .input_history
+            .back()
+            .is_some_and(|last| last.frame >= frame_inputs.frame)
+        {
+            self.input_history.pop_back();
+        }
+        if self.input_history.len() == PREDICTION_WINDOW {
+            // this frame is too old to be rolled back, so its inputs are confirmed
+            if let Some(confirmed) = self.input_history.pop_front() {
+                self.confirm_frame(confirmed);
+            }
+        }
+
+        // keep the state before advancing to interpolate between the two when rendering
+        self.previous_render_state = RenderState::new(&self.game_state);
+
+        // advance the same frame with swapped players, the outcome has to be swapped as well
+        let mirrored = self.mirror_mismatches.map(|_| {
+            let mut mirrored = self.game_state.mirrored();
+            let mut mirrored_inputs = frame_inputs.buttons_pressed.clone();
+            mirrored_inputs.reverse();
+            mirrored.advance(&mirrored_inputs);
+            mirrored
+        });
+
         // advance the game state
-        self.game_state.advance(inputs);
+        self.game_state.advance(&frame_inputs.buttons_pressed);
+
+        // ships spawn and roles are handed out by player index when a round starts, so on that
+        // frame the mirrored players get each other's spawn points and roles, every later frame
+        // is mirrored from this state again
+        let round_started = self.game_state.round.start_frame == self.game_state.frame;
+        if let (Some(mirrored), Some(mismatches)) = (mirrored, &mut self.mirror_mismatches) {
+            if !round_started && mirrored.mirrored() != self.game_state {
+                *mismatches += 1;
+                if let Some(metrics) = &self.metrics {
+                    metrics.add_mirror_mismatch();
+                }
+                println!(
+                    "Mirrored simulation diverged on frame {}",
+                    self.game_state.frame
+                );
+            }
+        }
+        if let Some(replay) = &mut self.recording {
+            replay.record(&frame_inputs.buttons_pressed, &self.game_state);
+            self.record_net_sample();
+        }
+        self.input_history.push_back(frame_inputs);
 
         // remember checksum to render it later
-        // it is very inefficient to serialize the gamestate here just for the checksum
-        let buffer = bincode::serialize(&self.game_state).unwrap();
-        let checksum = fletcher16(&buffer);
+        let checksum = self.game_state.checksum();
         self.last_checksum = (self.game_state.frame, checksum);
         if self.game_state.frame % CHECKSUM_PERIOD == 0 {
             self.periodic_checksum = (self.game_state.frame, checksum);
+            self.periodic_dump = Some(StateDump {
+                frame: self.game_state.frame,
+                checksum,
+                input_hash: self.input_hash,
+                inputs: self.input_history.iter().cloned().collect(),
+                state: self.game_state.clone(),
+            });
         }
+        if let Some(metrics) = &self.metrics {
+            metrics.set_frame(self.game_state.frame);
+        }
+        let elapsed = start.elapsed();
+        self.frame_graph.add_simulation(elapsed, resimulation);
+        self.profile.add_advance(elapsed, resimulation);
     }
 
-    // renders the game to the window
-    pub fn render(&self) {
-        clear_background(BLACK);
-
-        // render players
-        for i in 0..self.num_players {
-            let color = match i {
-                0 => GOLD,
-                1 => BLUE,
-                2 => GREEN,
-                3 => RED,
-                _ => WHITE,
-            };
-            let (x, y) = self.game_state.positions[i];
-            let rotation = self.game_state.rotations[i] + std::f32::consts::PI / 2.0;
-            let v1 = Vec2::new(
-                x + rotation.sin() * SHIP_HEIGHT / 2.,
-                y - rotation.cos() * SHIP_HEIGHT / 2.,
-            );
-            let v2 = Vec2::new(
-                x - rotation.cos() * SHIP_BASE / 2. - rotation.sin() * SHIP_HEIGHT / 2.,
-                y - rotation.sin() * SHIP_BASE / 2. + rotation.cos() * SHIP_HEIGHT / 2.,
-            );
-            let v3 = Vec2::new(
-                x + rotation.cos() * SHIP_BASE / 2. - rotation.sin() * SHIP_HEIGHT / 2.,
-                y + rotation.sin() * SHIP_BASE / 2. + rotation.cos() * SHIP_HEIGHT / 2.,
-            );
-            draw_triangle(v1, v2, v3, color);
+    // records the network conditions the frame just simulated was played under, once the first
+    // stats of a remote player arrived
+    fn record_net_sample(&mut self) {
+        let peers: Vec<&PeerStats> = self.network_stats.iter().flatten().collect();
+        if peers.is_empty() {
+            return;
         }
+        let frame = self.game_state.frame;
+        let rollback_depth = match self.last_rollback {
+            Some((from, depth)) if from == frame => depth,
+            _ => 0,
+        };
+        self.net_log.record(NetSample {
+            frame,
+            ping_ms: peers
+                .iter()
+                .map(|p| p.ping.as_millis() as u32)
+                .max()
+                .unwrap_or(0),
+            rollback_depth,
+            confirmed_lag: peers
+                .iter()
+                .map(|p| p.remote_frames_behind.max(0))
+                .max()
+                .unwrap_or(0),
+        });
+    }
 
-        // render checksums
-        let last_checksum_str = format!(
-            "Frame {}: Checksum {}",
-            self.last_checksum.0, self.last_checksum.1
+    // the network stats recorded along with the replay
+    pub fn take_net_log(&mut self) -> NetLog {
+        std::mem::take(&mut self.net_log)
+    }
+
+    // prints where the simulation time of the session went
+    pub fn print_profile(&self) {
+        println!(
+            "{}",
+            self.rollback_stats
+                .summary(self.game_state.tuning.seconds(self.game_state.frame))
         );
-        let periodic_checksum_str = format!(
-            "Frame {}: Checksum {}",
-            self.periodic_checksum.0, self.periodic_checksum.1
+        self.profile.print_report();
+    }
+
+    // adds the time it took to render the last frame to the frame time graph
+    pub fn finish_frame(&mut self, render_time: Duration) {
+        let frame_time = self.frame_graph.finish_frame(render_time);
+        self.pacing_jitter.finish_frame();
+        if let Some(metrics) = &self.metrics {
+            metrics.set_frame_time(frame_time);
+            metrics.set_pacing_jitter(self.pacing_jitter.get());
+        }
+    }
+
+    // handles keys that are not part of the game input
+    pub fn handle_debug_keys(&mut self) {
+        if is_key_pressed(KeyCode::F1) {
+            self.debug_panel.visible = !self.debug_panel.visible;
+        }
+        if is_key_pressed(KeyCode::F3) {
+            let overlays = &mut self.debug_panel.overlays;
+            overlays.frame_graph = !overlays.frame_graph;
+        }
+        if is_key_pressed(KeyCode::F9) {
+            if let Some(dump) = &self.periodic_dump {
+                self.write_periodic_dump(&format!("desync_{}.json", dump.frame));
+            }
+        }
+        if is_key_pressed(KeyCode::F10) {
+            self.write_snapshot();
+        }
+        if is_key_pressed(KeyCode::F11) {
+            self.export_input_log();
+        }
+    }
+
+    // writes the confirmed inputs of the last minutes to a timestamped file, to attach to bug
+    // reports about the netcode
+    fn export_input_log(&self) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let name = format!("inputs_{}_{}.json", timestamp, self.game_state.frame);
+        let path = self.dump_dir.join(name);
+        match self.input_log.export(&path) {
+            Ok(()) => println!("Wrote {}", path.display()),
+            Err(e) => println!("Failed to write {}: {}", path.display(), e),
+        }
+    }
+
+    // the last confirmed frame and the hash of all inputs up to it
+    #[cfg(test)]
+    pub fn last_confirmed(&self) -> Option<(Frame, u64)> {
+        let last = self.input_log.frames().last()?;
+        Some((last.frame, last.input_hash))
+    }
+
+    // the dumps of all peers can be compared when their periodic checksums differ
+    pub fn write_periodic_dump(&self, name: &str) {
+        if let Some(dump) = &self.periodic_dump {
+            write_dump(&self.dump_dir.join(name), dump);
+        }
+    }
+
+    // writes the current state, its checksum and the latest inputs to a timestamped file
+    fn write_snapshot(&self) {
+        let dump = StateDump {
+            frame: self.game_state.frame,
+            checksum: self.last_checksum.1,
+            input_hash: self.input_hash,
+            inputs: self.input_history.iter().cloned().collect(),
+            state: self.game_state.clone(),
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let name = format!("snapshot_{}_{}.json", timestamp, dump.frame);
+        write_dump(&self.dump_dir.join(name), &dump);
+    }
+}
+
+impl RollbackGame for Game {
+    type State = GameState;
+    type Rules = Tuning;
+
+    fn new(num_players: usize, tuning: Tuning, seed: u64) -> Self {
+        Game::new(num_players, tuning, seed)
+    }
+
+    fn advance(&mut self, inputs: GameInput<PlayerInput>) {
+        let frame_inputs = FrameInputs::new(self.game_state.frame + 1, self.num_players, &inputs);
+        self.advance_with(frame_inputs);
+        self.rollback_stats.add_advance(self.game_state.frame);
+    }
+
+    // the checksum handed to backroll covers the confirmed inputs as well as the state
+    fn save(&mut self) -> (GameState, u64) {
+        let start = Instant::now();
+        let mut hasher = Fnv1a64::new();
+        hasher.write(&self.game_state.checksum().to_le_bytes());
+        hasher.write(
+            &self
+                .confirmed_input_hash(self.game_state.frame)
+                .to_le_bytes(),
         );
-        draw_text(&last_checksum_str, 20.0, 20.0, 30.0, WHITE);
-        draw_text(&periodic_checksum_str, 20.0, 40.0, 30.0, WHITE);
+        let saved = (self.game_state.clone(), hasher.finish());
+        self.profile.add_save(start.elapsed());
+        saved
+    }
+
+    fn load(&mut self, state: GameState) {
+        let start = Instant::now();
+        self.rollback_ghost = Some((RenderState::new(&self.game_state), Instant::now()));
+        self.visual.mark_corrected();
+        let from_frame = self.game_state.frame;
+        self.game_state = state;
+        self.profile.add_load(start.elapsed());
+        self.rollback_stats
+            .add_load(from_frame, self.game_state.frame);
+        self.last_rollback = Some((from_frame, from_frame - self.game_state.frame));
+        if let Some(metrics) = &self.metrics {
+            metrics.add_rollback();
+        }
     }
 
-    // creates a compact representation of currently pressed keys
-    pub fn local_input(&self, _handle: PlayerHandle) -> PlayerInput {
-        let mut buttons_pressed: u8 = 0;
-        if is_key_down(KeyCode::W) {
-            buttons_pressed |= INPUT_UP;
+    fn render(&mut self, alpha: f32) {
+        Game::render(self, alpha)
+    }
+
+    fn local_input(&self, local_index: usize) -> PlayerInput {
+        Game::local_input(self, local_index)
+    }
+
+    // there is no catch-all, so new events have to be handled here before this compiles again
+    fn handle_event(&mut self, event: Event) {
+        println!("Event: {:?}", event);
+        if let Some((player, text, color)) = event_toast(&event) {
+            self.toasts.show(player, text, color);
         }
-        if is_key_down(KeyCode::A) {
-            buttons_pressed |= INPUT_LEFT;
+        match event {
+            Event::Connected(player) => self.set_peer_status(player, PeerStatus::Connected),
+            Event::Synchronizing {
+                player,
+                count,
+                total,
+            } => self.set_peer_status(player, PeerStatus::Synchronizing { count, total }),
+            Event::Synchronized(player) => self.set_peer_status(player, PeerStatus::Synchronized),
+            Event::Running => self.running = true,
+            Event::ConnectionInterrupted {
+                player,
+                disconnect_timeout,
+            } => self.set_peer_status(
+                player,
+                PeerStatus::Interrupted {
+                    since: Instant::now(),
+                    disconnect_timeout,
+                },
+            ),
+            Event::ConnectionResumed(player) => {
+                self.set_peer_status(player, PeerStatus::Synchronized)
+            }
+            Event::Disconnected(player) => {
+                self.set_peer_status(player, PeerStatus::Disconnected);
+                // the stats of a disconnected player don't mean anything anymore
+                if let Some(quality) = self.connection_quality.get_mut(player.0) {
+                    *quality = None;
+                }
+                if let Some(metrics) = &self.metrics {
+                    metrics.set_ping(player.0, None);
+                }
+            }
+            // we are ahead of the other players, slow down until they caught up
+            Event::TimeSync { frames_ahead } => self.time_drift = frames_ahead as f32,
+        }
+    }
+
+    // the time the next tick of the fixed-step loop takes, stretched while we are ahead of the
+    // other players and shortened while we are behind
+    fn tick_duration(&self) -> Duration {
+        let step = self.time_drift.clamp(-TIME_SYNC_RATE, TIME_SYNC_RATE);
+        self.game_state.tuning.tick_duration().mul_f32(1.0 + step)
+    }
+
+    // pays off a tick's worth of the time drift
+    fn tick(&mut self) {
+        let step = self.time_drift.clamp(-TIME_SYNC_RATE, TIME_SYNC_RATE);
+        self.time_drift -= step;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        vote::VoteAction,
+        win_condition::{Role, WinConditionId},
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn input_hashes_cover_all_earlier_inputs() {
+        let advance = |inputs: &[Vec<Option<u8>>]| {
+            let mut game = Game::new(2, Tuning::new(MovementModel::Inertia, 1.0), 0);
+            for buttons_pressed in inputs {
+                game.advance_offline(buttons_pressed.clone());
+            }
+            // predicted inputs don't count until they are confirmed
+            assert_eq!(game.input_hash, 0);
+            for _ in 0..PREDICTION_WINDOW {
+                game.advance_offline(vec![Some(0), Some(0)]);
+            }
+            game.input_hash
+        };
+        let pressed = advance(&[vec![Some(INPUT_UP), Some(0)], vec![Some(0), Some(0)]]);
+        let released = advance(&[vec![Some(0), Some(0)], vec![Some(0), Some(0)]]);
+        let disconnected = advance(&[vec![Some(INPUT_UP), None], vec![Some(0), None]]);
+        assert_ne!(pressed, released);
+        assert_ne!(pressed, disconnected);
+        assert_eq!(
+            pressed,
+            advance(&[vec![Some(INPUT_UP), Some(0)], vec![Some(0), Some(0)]])
+        );
+    }
+
+    #[test]
+    fn saved_checksums_stay_the_same_after_rollbacks() {
+        let mut game = Game::new(2, Tuning::new(MovementModel::Inertia, 1.0), 0);
+        let mut saves = Vec::new();
+        for frame in 0..30 {
+            saves.push(game.save());
+            game.advance_offline(vec![Some(frame as u8 % 16), Some(INPUT_UP)]);
         }
-        if is_key_down(KeyCode::S) {
-            buttons_pressed |= INPUT_DOWN;
+        let confirmed = game.input_hash;
+        let (state, _) = saves[25].clone();
+        game.load(state);
+        for (frame, (_, checksum)) in saves.iter().enumerate().skip(25) {
+            assert_eq!(game.save().1, *checksum);
+            game.advance_offline(vec![Some(frame as u8 % 16), Some(INPUT_UP)]);
         }
-        if is_key_down(KeyCode::D) {
-            buttons_pressed |= INPUT_RIGHT;
+        assert_eq!(game.input_hash, confirmed);
+        // the inputs are part of the saved checksum once they are confirmed
+        assert_ne!(saves[20].1, saves[20].0.checksum());
+    }
+
+    #[test]
+    fn warming_up_leaves_the_game_untouched() {
+        let mut tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        tuning.countdown = true;
+        let mut game = Game::new(2, tuning, 0);
+        game.enable_mirror_check();
+        game.start_recording();
+        let state = game.state().clone();
+        game.warm_up();
+        assert!(game.state() == &state);
+        assert_eq!(game.input_hash, 0);
+    }
+
+    #[test]
+    fn mirror_check_crosses_round_boundaries() {
+        let mut tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        tuning.rotation = "open,generated".parse().unwrap();
+        tuning.round_frames = 60;
+        let mut game = Game::new(2, tuning, 0);
+        game.enable_mirror_check();
+        let mut frame: u32 = 0;
+        while game.state().round.number < 3 {
+            game.advance_offline(vec![Some((frame % 16) as u8), Some(INPUT_UP)]);
+            frame += 1;
         }
+        assert_eq!(game.mirror_mismatches, Some(0));
+    }
 
-        PlayerInput { buttons_pressed }
+    #[test]
+    fn hunter_picks_are_left_out_of_the_mirror_check() {
+        let mut tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        tuning.win_condition = WinConditionId::Hunt;
+        tuning.round_frames = 60;
+        let mut game = Game::new(2, tuning, 0);
+        game.enable_mirror_check();
+        let mut hunters = HashSet::new();
+        while game.state().round.number < 6 {
+            game.advance_offline(vec![Some(INPUT_UP), Some(INPUT_LEFT)]);
+            hunters.extend(
+                game.state().roles[..2]
+                    .iter()
+                    .position(|&r| r == Role::Hunter),
+            );
+        }
+        // both players got to hunt, and every pick went to the other ship in the mirrored game
+        assert_eq!(hunters.len(), 2);
+        assert_eq!(game.mirror_mismatches, Some(0));
     }
 
-    pub fn should_wait(&self) -> bool {
-        self.wait_frames > 0
+    #[test]
+    fn mirror_check_holds_for_whole_matches() {
+        let mut tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        tuning.win_condition = WinConditionId::Hunt;
+        tuning.rotation = "generated".parse().unwrap();
+        tuning.meteor_showers = true;
+        tuning.best_of = 3;
+        tuning.round_frames = 300;
+        let mut game = Game::new(4, tuning, 0);
+        game.enable_mirror_check();
+        // two whole matches of hunts in generated arenas, with meteor showers from the second on
+        let rematch = VoteAction::Rematch.input();
+        let mut frame: u32 = 0;
+        let mut matches = 0;
+        while matches < 2 {
+            let inputs = match game.state().match_end {
+                Some(_) => vec![Some(rematch); 4],
+                None => (0..4).map(|i| Some(((frame + i) % 16) as u8)).collect(),
+            };
+            let ended = game.state().match_end.is_some();
+            game.advance_offline(inputs);
+            matches += (ended && game.state().match_end.is_none()) as u32;
+            frame += 1;
+        }
+        assert_eq!(game.mirror_mismatches, Some(0));
     }
 
-    pub fn wait(&mut self) {
-        self.wait_frames -= 1;
+    #[test]
+    fn restarting_starts_a_fresh_match() {
+        let tuning = Tuning::new(MovementModel::Tank, 1.0);
+        let mut game = Game::new(2, tuning, 0);
+        game.start_recording();
+        for _ in 0..200 {
+            game.advance_offline(vec![Some(INPUT_UP), Some(INPUT_LEFT)]);
+        }
+        game.restart(7);
+        let fresh = GameState::new(2, tuning, 7);
+        assert!(game.state() == &fresh);
+        assert_eq!(
+            (game.input_hash, game.periodic_checksum),
+            (0, (NULL_FRAME, 0))
+        );
+        assert!(game.input_history.is_empty());
+        let recording = game.take_recording().unwrap();
+        assert!(recording.initial_state == fresh);
+        assert!(recording.inputs.is_empty());
     }
 }
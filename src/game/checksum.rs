@@ -0,0 +1,295 @@
+// hashing game states and inputs, so peers can tell whether they are still in sync, and the
+// self test checking that this build simulates like everyone else's
+use super::{state::NULL_FRAME, Frame, GameState, MovementModel, Tuning};
+use crate::{vote::VoteKind, win_condition::Outcome};
+
+// a checksum of the state is compared with the other peers every this many frames
+pub const CHECKSUM_PERIOD: Frame = 100;
+
+/// computes the 64 bit FNV-1a hash incrementally: <https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function>
+pub(crate) struct Fnv1a64 {
+    pub(crate) hash: u64,
+}
+
+impl Fnv1a64 {
+    pub(crate) fn new() -> Self {
+        Self {
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    pub(crate) fn write(&mut self, data: &[u8]) {
+        for byte in data {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl Tuning {
+    // hashes the gameplay parameters, peers compare it during the handshake to catch other
+    // options before the first checksum would
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = Fnv1a64::new();
+        self.write_to(&mut hasher);
+        hasher.finish()
+    }
+
+    fn write_to(&self, hasher: &mut Fnv1a64) {
+        hasher.write(&[self.movement_model as u8]);
+        hasher.write(&self.ship_height.to_bits().to_le_bytes());
+        hasher.write(&self.ship_base.to_bits().to_le_bytes());
+        hasher.write(&[self.bot_takeover as u8]);
+        hasher.write(&[self.meteor_showers as u8]);
+        hasher.write(&[self.win_condition as u8]);
+        hasher.write(&self.round_frames.to_le_bytes());
+        hasher.write(&self.best_of.to_le_bytes());
+        hasher.write(&[self.countdown as u8]);
+        hasher.write(&self.tick_rate.to_le_bytes());
+        for arena in self.rotation.arenas() {
+            hasher.write(&[*arena as u8]);
+        }
+    }
+}
+
+impl GameState {
+    // hashes the state directly from its fields, without serializing or allocating
+    // it is shown on screen and handed to backroll with every saved state
+    pub fn checksum(&self) -> u64 {
+        // destructuring makes sure new fields can't be forgotten here
+        let Self {
+            frame,
+            num_players,
+            tuning,
+            rng,
+            round,
+            match_end,
+            vote,
+            positions,
+            velocities,
+            rotations,
+            first_input_frames,
+            last_active_frames,
+            kicked,
+            thrust_latched,
+            toggle_held,
+            scores,
+            round_wins,
+            hull,
+            roles,
+            rammed_frames,
+            zone,
+            checkpoints,
+            laps,
+            meteors,
+        } = self;
+
+        let mut hasher = Fnv1a64::new();
+        hasher.write(&frame.to_le_bytes());
+        hasher.write(&(*num_players as u64).to_le_bytes());
+        tuning.write_to(&mut hasher);
+        hasher.write(&rng.state().to_le_bytes());
+        hasher.write(&round.number.to_le_bytes());
+        hasher.write(&[round.arena as u8]);
+        hasher.write(&round.start_frame.to_le_bytes());
+        match round.transition {
+            Some(transition) => {
+                match transition.outcome {
+                    Some(Outcome::Won(winner)) => hasher.write(&[2, winner as u8]),
+                    Some(Outcome::Draw) => hasher.write(&[1]),
+                    Some(Outcome::Escaped) => hasher.write(&[3]),
+                    None => hasher.write(&[0]),
+                }
+                hasher.write(&[transition.next_arena as u8]);
+                hasher.write(&transition.start_frame.to_le_bytes());
+            }
+            None => hasher.write(&NULL_FRAME.to_le_bytes()),
+        }
+        // only generated arenas have a layout, so the checksums of the others stay the same
+        if let Some(layout) = &round.layout {
+            for wall in layout.walls() {
+                hasher.write(&wall.bytes());
+            }
+            for player in 0..*num_players {
+                let (x, y) = layout.spawn(player);
+                hasher.write(&x.to_le_bytes());
+                hasher.write(&y.to_le_bytes());
+            }
+        }
+        let n = *num_players;
+        match match_end {
+            Some(match_end) => {
+                for vote in &match_end.rematch_votes[..n] {
+                    hasher.write(&[*vote as u8]);
+                }
+                hasher.write(&match_end.rematch_frame.unwrap_or(NULL_FRAME).to_le_bytes());
+            }
+            None => hasher.write(&NULL_FRAME.to_le_bytes()),
+        }
+        match vote {
+            Some(vote) => {
+                match vote.kind {
+                    VoteKind::KickAfk(target) => hasher.write(&[1, target as u8]),
+                    VoteKind::SkipMap => hasher.write(&[2]),
+                    VoteKind::EndMatch => hasher.write(&[3]),
+                }
+                hasher.write(&[vote.caller as u8]);
+                hasher.write(&vote.start_frame.to_le_bytes());
+                for ballot in &vote.ballots[..n] {
+                    hasher.write(&[ballot.map_or(0, |ballot| 1 + ballot as u8)]);
+                }
+            }
+            None => hasher.write(&[0]),
+        }
+        for (x, y) in positions[..n].iter().chain(velocities[..n].iter()) {
+            hasher.write(&x.to_bits().to_le_bytes());
+            hasher.write(&y.to_bits().to_le_bytes());
+        }
+        for rot in &rotations[..n] {
+            hasher.write(&rot.to_bits().to_le_bytes());
+        }
+        for first_input_frame in &first_input_frames[..n] {
+            hasher.write(&first_input_frame.unwrap_or(NULL_FRAME).to_le_bytes());
+        }
+        for (last_active_frame, kicked) in last_active_frames[..n].iter().zip(&kicked[..n]) {
+            hasher.write(&last_active_frame.to_le_bytes());
+            hasher.write(&[*kicked as u8]);
+        }
+        for (latched, held) in thrust_latched[..n].iter().zip(&toggle_held[..n]) {
+            hasher.write(&[*latched as u8, *held as u8]);
+        }
+        for (((score, round_wins), hull), rammed_frame) in scores[..n]
+            .iter()
+            .zip(&round_wins[..n])
+            .zip(&hull[..n])
+            .zip(&rammed_frames[..n])
+        {
+            hasher.write(&score.to_le_bytes());
+            hasher.write(&round_wins.to_le_bytes());
+            hasher.write(&[*hull]);
+            hasher.write(&rammed_frame.unwrap_or(NULL_FRAME).to_le_bytes());
+        }
+        hasher.write(&(zone.spot as u64).to_le_bytes());
+        hasher.write(&zone.moved_frame.to_le_bytes());
+        for role in &roles[..n] {
+            hasher.write(&[*role as u8]);
+        }
+        hasher.write(&checkpoints[..n]);
+        hasher.write(&laps[..n]);
+        for meteor in meteors {
+            let Some(meteor) = meteor else {
+                hasher.write(&[0]);
+                continue;
+            };
+            let (x, y) = meteor.position;
+            let (vel_x, vel_y) = meteor.velocity;
+            hasher.write(&[1]);
+            for value in [x, y, vel_x, vel_y, meteor.radius] {
+                hasher.write(&value.to_bits().to_le_bytes());
+            }
+        }
+        hasher.finish()
+    }
+}
+
+const SCRIPT_PLAYERS: usize = 4;
+const SELF_TEST_FRAMES: Frame = 600;
+// outcome of the self test on a build that simulates correctly
+// after intentional changes to the simulation, update it along with the golden data
+const SELF_TEST_CHECKSUM: u64 = 0x69f2fb9bcd3fd44;
+
+// a canned input script exercising all buttons, the speed limit and the canvas borders
+// the last player disconnects halfway through the `frames` of the script
+pub fn scripted_input(frame: Frame, player: usize, frames: Frame) -> Option<u8> {
+    if player == SCRIPT_PLAYERS - 1 && frame > frames / 2 {
+        return None;
+    }
+    let phase = (frame / 45) as usize + player * 3;
+    Some(((phase * 7 + player) % 16) as u8)
+}
+
+// simulates the input script with every movement model and hashes the outcomes
+fn self_test_checksum() -> u64 {
+    let mut hasher = Fnv1a64::new();
+    for movement_model in [MovementModel::Inertia, MovementModel::Tank] {
+        let tuning = Tuning::new(movement_model, 1.0);
+        let mut state = GameState::new(SCRIPT_PLAYERS, tuning, 0);
+        while state.frame < SELF_TEST_FRAMES {
+            let inputs: Vec<Option<u8>> = (0..SCRIPT_PLAYERS)
+                .map(|player| scripted_input(state.frame + 1, player, SELF_TEST_FRAMES))
+                .collect();
+            state.advance(&inputs);
+        }
+        hasher.write(&state.checksum().to_le_bytes());
+    }
+    hasher.finish()
+}
+
+// checks that this build simulates exactly like the one the expected outcome came from
+// compiler flags like `target-cpu` or fast math can change floating point results,
+// which would make this peer desync from everyone else
+pub fn determinism_self_test() -> Result<(), String> {
+    let checksum = self_test_checksum();
+    if checksum != SELF_TEST_CHECKSUM {
+        return Err(format!(
+            "expected checksum {:016x}, got {:016x}",
+            SELF_TEST_CHECKSUM, checksum
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, path::PathBuf};
+
+    const SCRIPT_FRAMES: Frame = 3000;
+
+    // runs the script and collects the checksum of every periodic checksum frame
+    fn run_script(movement_model: MovementModel) -> String {
+        let tuning = Tuning::new(movement_model, 1.0);
+        let mut state = GameState::new(SCRIPT_PLAYERS, tuning, 0);
+        let mut golden = String::new();
+        while state.frame < SCRIPT_FRAMES {
+            let inputs: Vec<Option<u8>> = (0..SCRIPT_PLAYERS)
+                .map(|player| scripted_input(state.frame + 1, player, SCRIPT_FRAMES))
+                .collect();
+            state.advance(&inputs);
+            if state.frame % CHECKSUM_PERIOD == 0 {
+                golden.push_str(&format!(
+                    "{:?} {} {}\n",
+                    movement_model,
+                    state.frame,
+                    state.checksum()
+                ));
+            }
+        }
+        golden
+    }
+
+    // run with `cargo test` and `cargo test --release`, both have to match the same golden data
+    // after intentional changes to the simulation, regenerate it with `BLESS=1 cargo test`
+    #[test]
+    fn self_test_passes() {
+        assert_eq!(self_test_checksum(), SELF_TEST_CHECKSUM);
+    }
+
+    #[test]
+    fn simulation_matches_golden_data() {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "golden", "determinism.txt"]
+            .iter()
+            .collect();
+        let actual = run_script(MovementModel::Inertia) + &run_script(MovementModel::Tank);
+        if env::var_os("BLESS").is_some() {
+            fs::write(&path, &actual).unwrap();
+            return;
+        }
+        let expected = fs::read_to_string(&path).unwrap();
+        assert_eq!(actual, expected, "simulation diverged from golden data");
+    }
+}
@@ -0,0 +1,174 @@
+// what players send each other: the buttons of a frame, and how they are read from the keyboard
+use backroll::{BackrollResult, GameInput, PlayerHandle};
+use bytemuck::*;
+use macroquad::prelude::*;
+use serde::Serialize;
+
+use super::{checksum::Fnv1a64, Frame, Game};
+use crate::{config::ControlScheme, vote::VoteAction};
+
+pub const INPUT_UP: u8 = 1 << 0;
+pub const INPUT_DOWN: u8 = 1 << 1;
+pub const INPUT_LEFT: u8 = 1 << 2;
+pub const INPUT_RIGHT: u8 = 1 << 3;
+// turns latched thrust on and off, players with toggled thrust send it instead of `INPUT_UP`
+// the rematch vote lives with the other votes in the reserved vote bits
+pub const INPUT_TOGGLE_THRUST: u8 = 1 << 4;
+pub(super) const INPUT_MOVEMENT: u8 =
+    INPUT_UP | INPUT_DOWN | INPUT_LEFT | INPUT_RIGHT | INPUT_TOGGLE_THRUST;
+
+// players sharing the keyboard, each with their own `ControlScheme`
+pub const MAX_LOCAL_PLAYERS: usize = 2;
+
+// keys for up, left, down and right of each `ControlScheme`
+pub const CONTROL_SCHEMES: [[KeyCode; 4]; 3] = [
+    [KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D],
+    [KeyCode::Up, KeyCode::Left, KeyCode::Down, KeyCode::Right],
+    [KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L],
+];
+
+#[repr(C)]
+#[derive(Clone, Copy, Eq, PartialEq, Pod, Zeroable)]
+pub struct PlayerInput {
+    pub buttons_pressed: u8,
+}
+
+// FrameInputs holds the inputs all players used to advance to a given frame
+#[derive(Clone, Serialize)]
+pub struct FrameInputs {
+    pub frame: Frame,
+    // `None` if the player was disconnected on that frame
+    pub buttons_pressed: Vec<Option<u8>>,
+    // hash of these and all earlier inputs of the session, set once the frame is confirmed
+    // peers with the same state checksum but different input hashes got lucky, peers with the
+    // same input hash but different checksums simulate differently
+    pub input_hash: u64,
+}
+
+impl FrameInputs {
+    pub(crate) fn new(frame: Frame, num_players: usize, inputs: &GameInput<PlayerInput>) -> Self {
+        let buttons_pressed = (0..num_players)
+            .map(|i| {
+                let handle = PlayerHandle(i);
+                buttons_pressed(inputs.is_disconnected(handle), inputs.get(handle))
+            })
+            .collect();
+        Self {
+            frame,
+            buttons_pressed,
+            input_hash: 0,
+        }
+    }
+
+    // chains the inputs of this frame onto the hash of all inputs before them
+    pub(super) fn hash_onto(&self, previous: u64) -> u64 {
+        let mut hasher = Fnv1a64 { hash: previous };
+        hasher.write(&self.frame.to_le_bytes());
+        for buttons_pressed in &self.buttons_pressed {
+            match buttons_pressed {
+                Some(buttons_pressed) => hasher.write(&[1, *buttons_pressed]),
+                None => hasher.write(&[0]),
+            }
+        }
+        hasher.finish()
+    }
+}
+
+// the buttons of a player backroll reported, `None` if they are disconnected
+// a handle backroll doesn't know counts as disconnected rather than panicking in the middle of a
+// rollback, all peers have the same players, so they all agree on it
+fn buttons_pressed(
+    disconnected: BackrollResult<bool>,
+    input: BackrollResult<&PlayerInput>,
+) -> Option<u8> {
+    match (disconnected, input) {
+        (Ok(false), Ok(input)) => Some(input.buttons_pressed),
+        _ => None,
+    }
+}
+
+impl Game {
+    // creates a compact representation of currently pressed keys
+    // `local_index` selects the keys of one of the players sharing this keyboard
+    pub fn local_input(&self, local_index: usize) -> PlayerInput {
+        let scheme = match local_index {
+            0 => self.controls,
+            _ => self.controls.other(),
+        };
+        let [up, left, down, right] = CONTROL_SCHEMES[scheme.index()];
+        let mut buttons_pressed: u8 = 0;
+        if is_key_down(up) {
+            buttons_pressed |= match self.toggle_thrust {
+                true => INPUT_TOGGLE_THRUST,
+                false => INPUT_UP,
+            };
+        }
+        if is_key_down(left) {
+            buttons_pressed |= INPUT_LEFT;
+        }
+        if is_key_down(down) {
+            buttons_pressed |= INPUT_DOWN;
+        }
+        if is_key_down(right) {
+            buttons_pressed |= INPUT_RIGHT;
+        }
+        // every local player votes with the same keys, one-handed players get a second set right
+        // above their steering keys
+        let one_handed = self.controls == ControlScheme::OneHanded;
+        let vote_keys = [
+            (KeyCode::Y, VoteAction::Yes),
+            (KeyCode::N, VoteAction::No),
+            (KeyCode::Key1, VoteAction::CallKick),
+            (KeyCode::Key2, VoteAction::CallSkipMap),
+            (KeyCode::Key3, VoteAction::CallEndMatch),
+            (KeyCode::Enter, VoteAction::Rematch),
+        ];
+        let one_handed_vote_keys = [
+            (KeyCode::U, VoteAction::Yes),
+            (KeyCode::O, VoteAction::No),
+            (KeyCode::Key8, VoteAction::CallKick),
+            (KeyCode::Key9, VoteAction::CallSkipMap),
+            (KeyCode::Key0, VoteAction::CallEndMatch),
+        ];
+        let vote_keys = vote_keys
+            .iter()
+            .chain(one_handed_vote_keys.iter().filter(|_| one_handed));
+        if let Some((_, action)) = vote_keys.into_iter().find(|(key, _)| is_key_down(*key)) {
+            buttons_pressed |= action.input();
+        }
+
+        PlayerInput { buttons_pressed }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GameState, MovementModel, Tuning};
+    use backroll::{BackrollError, MAX_PLAYERS_PER_MATCH};
+
+    #[test]
+    fn missing_and_disconnected_players_have_no_buttons() {
+        let input = PlayerInput { buttons_pressed: 5 };
+        assert_eq!(buttons_pressed(Ok(false), Ok(&input)), Some(5));
+        assert_eq!(buttons_pressed(Ok(true), Ok(&input)), None);
+        let missing = PlayerHandle(MAX_PLAYERS_PER_MATCH);
+        assert_eq!(
+            buttons_pressed(
+                Err(BackrollError::InvalidPlayer(missing)),
+                Err(BackrollError::InvalidPlayer(missing))
+            ),
+            None
+        );
+
+        // the simulation takes whatever it gets, disconnected players and missing inputs alike
+        let tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        let mut state = GameState::new(2, tuning, 0);
+        for _ in 0..120 {
+            state.advance(&[None, Some(INPUT_UP)]);
+            state.advance(&[Some(INPUT_UP)]);
+            state.advance(&[]);
+        }
+        assert_eq!(state.frame, 360);
+    }
+}
@@ -0,0 +1,365 @@
+// drawing the game, everything in here only reads the state and may differ between peers
+use macroquad::prelude::*;
+use std::time::Duration;
+
+use super::{Frame, Game, GameState, Tuning, MAX_PLAYERS, WINDOW_HEIGHT, WINDOW_WIDTH};
+use crate::{
+    arena,
+    debug_panel::{PeerInfo, SessionInfo},
+    win_condition::Outcome,
+};
+
+// every size text is drawn in, their glyphs are rasterized before the match
+const TEXT_SIZES: [u16; 5] = [16, 20, 24, 30, 80];
+
+const GHOST_DURATION: Duration = Duration::from_millis(500);
+const GHOST_ALPHA: f32 = 0.3;
+
+// RenderState holds the parts of a game state needed for rendering
+// it is never saved or loaded by backroll, so rollbacks don't affect it
+// `VisualState` eases between these and what is shown
+#[derive(Clone, Copy)]
+pub(super) struct RenderState {
+    pub(super) positions: [(f32, f32); MAX_PLAYERS],
+    pub(super) rotations: [f32; MAX_PLAYERS],
+}
+
+impl RenderState {
+    pub(super) fn new(game_state: &GameState) -> Self {
+        Self {
+            positions: game_state.positions,
+            rotations: game_state.rotations,
+        }
+    }
+
+    // interpolates position and rotation of player `i` between `self` and `next`
+    pub(super) fn interpolate(&self, next: &GameState, i: usize, alpha: f32) -> ((f32, f32), f32) {
+        let (prev_x, prev_y) = self.positions[i];
+        let (next_x, next_y) = next.positions[i];
+        let x = prev_x + (next_x - prev_x) * alpha;
+        let y = prev_y + (next_y - prev_y) * alpha;
+
+        // rotate along the shorter arc, rotations wrap around at 2 PI
+        let prev_rot = self.rotations[i];
+        let delta = (next.rotations[i] - prev_rot + std::f32::consts::PI)
+            .rem_euclid(2.0 * std::f32::consts::PI)
+            - std::f32::consts::PI;
+        let rot = prev_rot + delta * alpha;
+
+        ((x, y), rot)
+    }
+}
+
+// rasterizes the glyphs of all text sizes, drawing a glyph the first time takes a while
+pub fn cache_glyphs() {
+    let characters: String = (' '..='~').collect();
+    for size in TEXT_SIZES {
+        measure_text(&characters, None, size, 1.0);
+    }
+}
+
+// the color a player gets unless they choose another one with `--color`
+pub fn player_color(i: usize) -> Color {
+    match i {
+        0 => GOLD,
+        1 => BLUE,
+        2 => GREEN,
+        3 => RED,
+        _ => WHITE,
+    }
+}
+
+// draws a ship as a triangle pointing in the direction of `rotation`
+fn draw_ship((x, y): (f32, f32), rotation: f32, tuning: &Tuning, color: Color) {
+    let (height, base) = (tuning.ship_height, tuning.ship_base);
+    let rotation = rotation + std::f32::consts::PI / 2.0;
+    let v1 = Vec2::new(
+        x + rotation.sin() * height / 2.,
+        y - rotation.cos() * height / 2.,
+    );
+    let v2 = Vec2::new(
+        x - rotation.cos() * base / 2. - rotation.sin() * height / 2.,
+        y - rotation.sin() * base / 2. + rotation.cos() * height / 2.,
+    );
+    let v3 = Vec2::new(
+        x + rotation.cos() * base / 2. - rotation.sin() * height / 2.,
+        y + rotation.sin() * base / 2. + rotation.cos() * height / 2.,
+    );
+    draw_triangle(v1, v2, v3, color);
+}
+
+impl Game {
+    // renders the game to the window
+    // `alpha` is the fraction of a frame that passed since the last simulated frame
+    pub fn render(&mut self, alpha: f32) {
+        self.visual.update(
+            &self.previous_render_state,
+            &self.game_state,
+            alpha,
+            get_frame_time(),
+            self.debug_panel.overlays.smoothing,
+        );
+        let overlays = &self.debug_panel.overlays;
+        clear_background(BLACK);
+        arena::draw(self.game_state.round.walls());
+        let win_condition = self.game_state.tuning.win_condition.get();
+        win_condition.draw(&self.game_state, &self.colors);
+        for meteor in self.game_state.meteors.iter().flatten() {
+            meteor.draw();
+        }
+
+        if let Some(ghost) = &self.race_ghost {
+            let color = Color {
+                a: GHOST_ALPHA,
+                ..WHITE
+            };
+            draw_ship(
+                ghost.positions[0],
+                ghost.rotations[0],
+                &self.game_state.tuning,
+                color,
+            );
+        }
+
+        // render ghosts of the ships as they were before the last rollback
+        if let Some((ghost, since)) = &self.rollback_ghost {
+            if overlays.rollback_ghosts && since.elapsed() < GHOST_DURATION {
+                for i in 0..self.num_players {
+                    let color = Color {
+                        a: GHOST_ALPHA,
+                        ..self.colors[i]
+                    };
+                    draw_ship(
+                        ghost.positions[i],
+                        ghost.rotations[i],
+                        &self.game_state.tuning,
+                        color,
+                    );
+                }
+            }
+        }
+
+        // render players
+        for i in 0..self.num_players {
+            let (position, rotation) = (self.visual.positions[i], self.visual.rotations[i]);
+            // wrecks are drawn faded, ships that were just hit flash white
+            let color = if self.game_state.hull[i] > 0 {
+                let flash = self.visual.flash(i);
+                let [r, g, b, a]: [f32; 4] = self.colors[i].into();
+                Color::new(
+                    r + (1.0 - r) * flash,
+                    g + (1.0 - g) * flash,
+                    b + (1.0 - b) * flash,
+                    a,
+                )
+            } else {
+                Color {
+                    a: GHOST_ALPHA,
+                    ..self.colors[i]
+                }
+            };
+            draw_ship(position, rotation, &self.game_state.tuning, color);
+            // names go above the connection quality
+            if let Some(name) = &self.names[i] {
+                let (x, y) = position;
+                let radius = self.game_state.tuning.ship_height / 2.0;
+                let width = measure_text(name, None, 20, 1.0).width;
+                draw_text(name, x - width / 2.0, y - radius - 22.0, 20.0, color);
+            }
+
+            if !overlays.connection {
+                continue;
+            }
+            // show the connection quality of remote players above their ship
+            if let Some(quality) = self.connection_quality[i] {
+                let (x, y) = position;
+                let radius = self.game_state.tuning.ship_height / 2.0;
+                draw_circle(x, y - radius - 10.0, 6.0, quality.color());
+            }
+            let label = match self.game_state.kicked[i] {
+                true => Some("kicked".to_owned()),
+                false => self.peer_status[i].and_then(|status| status.label()),
+            };
+            if let Some(label) = label {
+                let (x, y) = position;
+                let radius = self.game_state.tuning.ship_height / 2.0;
+                let width = measure_text(&label, None, 20, 1.0).width;
+                draw_text(&label, x - width / 2.0, y + radius + 20.0, 20.0, WHITE);
+            }
+        }
+
+        // render checksums
+        let last_checksum_str = format!(
+            "Frame {}: Checksum {:016x}",
+            self.last_checksum.0, self.last_checksum.1
+        );
+        let periodic_checksum_str = format!(
+            "Frame {}: Checksum {:016x}",
+            self.periodic_checksum.0, self.periodic_checksum.1
+        );
+        if overlays.checksums {
+            draw_text(&last_checksum_str, 20.0, 20.0, 30.0, WHITE);
+            draw_text(&periodic_checksum_str, 20.0, 40.0, 30.0, WHITE);
+        }
+
+        // render the frames of the first inputs of the round, once everyone pressed a button
+        // if players reacting to the same event differ systematically, frame delay should be tuned
+        let first_inputs: Option<Vec<Frame>> = self.game_state.first_input_frames
+            [..self.num_players]
+            .iter()
+            .copied()
+            .collect();
+        if let Some(first_inputs) = first_inputs.filter(|_| overlays.first_inputs) {
+            let earliest = first_inputs.iter().min().copied().unwrap_or(0);
+            let mut first_input_str = String::from("First input:");
+            for (i, frame) in first_inputs.iter().enumerate() {
+                first_input_str.push_str(&format!(" P{} +{}", i + 1, frame - earliest));
+            }
+            draw_text(&first_input_str, 20.0, WINDOW_HEIGHT - 20.0, 30.0, WHITE);
+        }
+
+        if let Some(mismatches) = self.mirror_mismatches {
+            let color = if mismatches > 0 { RED } else { WHITE };
+            let mirror_str = format!("Mirror mismatches: {}", mismatches);
+            draw_text(&mirror_str, 20.0, 60.0, 30.0, color);
+        }
+
+        // show how many messages bounded queues dropped, once they overflowed
+        let overflowed = self
+            .dropped_messages
+            .iter()
+            .enumerate()
+            .filter(|(_, (sent, received))| overlays.connection && sent + received > 0);
+        for (line, (i, (sent, received))) in overflowed.enumerate() {
+            let dropped_str = format!(
+                "P{} queue overflow: {} sent / {} received dropped",
+                i + 1,
+                sent,
+                received
+            );
+            let y = WINDOW_HEIGHT - 70.0 - 20.0 * line as f32;
+            draw_text(&dropped_str, 20.0, y, 20.0, ORANGE);
+        }
+
+        if overlays.rollback_stats {
+            let summary = self
+                .rollback_stats
+                .summary(self.game_state.tuning.seconds(self.game_state.frame));
+            draw_text(&summary, 20.0, WINDOW_HEIGHT - 48.0, 20.0, WHITE);
+        }
+
+        if let Some(transition) = self.game_state.round.transition {
+            let round = self.game_state.round.number + 1;
+            let outcome_str = match transition.outcome {
+                Some(Outcome::Won(winner)) => format!("P{} wins round {}", winner + 1, round),
+                Some(Outcome::Draw) => format!("Round {} is a draw", round),
+                Some(Outcome::Escaped) => format!("Runners escape in round {}", round),
+                None => format!("Round {} over", round),
+            };
+            let mut lines = vec![outcome_str];
+            match self.game_state.match_end {
+                Some(match_end) => {
+                    lines.push(match self.game_state.match_winner() {
+                        Some(winner) => format!(
+                            "P{} wins the match with {} rounds",
+                            winner + 1,
+                            self.game_state.round_wins[winner]
+                        ),
+                        None => "The match is a draw".to_owned(),
+                    });
+                    let votes = match_end.rematch_votes[..self.num_players]
+                        .iter()
+                        .filter(|&&vote| vote)
+                        .count();
+                    lines.push(match match_end.rematch_frame {
+                        Some(_) => "Rematch starting...".to_owned(),
+                        None => format!("Enter for a rematch ({}/{})", votes, self.num_players),
+                    });
+                }
+                None => lines.push(format!("next arena: {}", transition.next_arena.name())),
+            }
+            for (line, text) in lines.iter().enumerate() {
+                let width = measure_text(text, None, 30, 1.0).width;
+                draw_text(
+                    text,
+                    (WINDOW_WIDTH - width) / 2.0,
+                    WINDOW_HEIGHT / 2.0 + line as f32 * 30.0,
+                    30.0,
+                    WHITE,
+                );
+            }
+        }
+
+        // rounds won and progress of every player towards winning the round
+        for i in (0..self.num_players).filter(|_| overlays.win_condition) {
+            let wins = (self.game_state.tuning.best_of > 0)
+                .then(|| format!("{} won", self.game_state.round_wins[i]));
+            let status: Vec<String> = wins
+                .into_iter()
+                .chain(win_condition.status(&self.game_state, i))
+                .collect();
+            if !status.is_empty() {
+                let status_str = format!("P{} {}", i + 1, status.join(", "));
+                let y = 20.0 + 20.0 * i as f32;
+                draw_text(&status_str, WINDOW_WIDTH - 200.0, y, 20.0, self.colors[i]);
+            }
+        }
+
+        if !self.running {
+            let waiting_str = "Waiting for all players to synchronize...";
+            let width = measure_text(waiting_str, None, 30, 1.0).width;
+            draw_text(
+                waiting_str,
+                (WINDOW_WIDTH - width) / 2.0,
+                WINDOW_HEIGHT / 2.0,
+                30.0,
+                WHITE,
+            );
+        } else if let Some(seconds) = self.game_state.countdown() {
+            let countdown_str = seconds.to_string();
+            let width = measure_text(&countdown_str, None, 80, 1.0).width;
+            draw_text(
+                &countdown_str,
+                (WINDOW_WIDTH - width) / 2.0,
+                WINDOW_HEIGHT / 2.0,
+                80.0,
+                WHITE,
+            );
+        }
+
+        if let Some(vote) = &self.game_state.vote {
+            vote.draw(&self.game_state);
+        }
+        self.toasts.draw();
+
+        if overlays.frame_graph {
+            self.frame_graph
+                .draw(20.0, 80.0, self.game_state.tuning.tick_duration());
+        }
+
+        let peers = (0..self.num_players)
+            .map(|i| PeerInfo {
+                name: self.names[i].clone(),
+                status: match self.peer_status[i] {
+                    Some(status) => status.label().unwrap_or_else(|| "synchronized".to_owned()),
+                    None => "local".to_owned(),
+                },
+                stats: self.network_stats[i],
+                dropped: self.dropped_messages[i],
+            })
+            .collect();
+        self.debug_panel.draw(&SessionInfo {
+            running: self.running,
+            last_checksum: self.last_checksum,
+            periodic_checksum: self.periodic_checksum,
+            periodic_input_hash: self.periodic_input_hash,
+            mirror_mismatches: self.mirror_mismatches,
+            rollback_summary: self
+                .rollback_stats
+                .summary(self.game_state.tuning.seconds(self.game_state.frame)),
+            pacing_jitter: self.pacing_jitter.get(),
+            peers,
+            state: &self.game_state,
+        });
+    }
+}
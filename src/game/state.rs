@@ -0,0 +1,909 @@
+// the simulation: the state all peers keep in sync and the rules advancing it by a frame
+// nothing in here may depend on anything but the state and the inputs, or peers desync
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, time::Duration};
+
+use super::input::{
+    INPUT_DOWN, INPUT_LEFT, INPUT_MOVEMENT, INPUT_RIGHT, INPUT_TOGGLE_THRUST, INPUT_UP,
+};
+use crate::{
+    arena::{self, Arena, Layout, MapRotation, Wall},
+    meteor::{self, Meteor},
+    rng::Rng,
+    vote::{self, Vote, VoteAction, VoteKind},
+    win_condition::{ControlZone, Outcome, Role, WinConditionId, HULL},
+};
+
+pub type Frame = i32;
+
+// the tick rate matches run at unless `--tick-rate` picks another, the other demos always run at it
+pub const FPS: f32 = 60.0;
+pub const MAX_PLAYERS: usize = 4;
+// stands in for a frame that doesn't exist, e.g. before anything was simulated
+pub(super) const NULL_FRAME: Frame = -1;
+
+// durations are given in seconds and speeds per second, `Tuning` converts them to frames with the
+// tick rate of the match
+
+// ships stand still between rounds for this long, while the next arena is announced
+const ROUND_TRANSITION: f32 = 2.0;
+// a rematch starts this long after the last player agreed to it
+const REMATCH_DELAY: f32 = 1.0;
+// ships can't be controlled during the 3-2-1 countdown at the start of a session
+const COUNTDOWN: f32 = 3.0;
+
+// with meteor showers enabled, a shower of `SHOWER_DURATION` starts every `SHOWER_INTERVAL`
+pub const MAX_METEORS: usize = 16;
+const SHOWER_INTERVAL: f32 = 20.0;
+const SHOWER_DURATION: f32 = 4.0;
+// meteors spawned per second during a shower, on average
+const METEOR_SPAWN_RATE: f32 = 12.0;
+// ships hit by a meteor are pushed as far away as the meteor moves in this time
+const METEOR_KNOCKBACK: f32 = 0.05;
+
+const SHIP_HEIGHT: f32 = 50.;
+const SHIP_BASE: f32 = 40.;
+pub const WINDOW_HEIGHT: f32 = 800.0;
+pub const WINDOW_WIDTH: f32 = 600.0;
+
+// in pixels per second and per second squared, or radians per second
+const ACCELERATION: f32 = 900.0;
+const ROTATION_SPEED: f32 = 2.5;
+const MAX_SPEED: f32 = 420.0;
+const TANK_SPEED: f32 = 180.0;
+// the fraction of their speed drifting ships keep every sixtieth of a second
+const FRICTION: f32 = 0.98;
+
+// MovementModel selects the rules ships move by, all peers have to use the same one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub enum MovementModel {
+    // ships accelerate, drift and slow down by friction
+    Inertia,
+    // ships move at a constant speed while thrusting and stop immediately otherwise
+    Tank,
+}
+
+impl FromStr for MovementModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inertia" => Ok(Self::Inertia),
+            "tank" => Ok(Self::Tank),
+            _ => Err(format!("unknown movement model: {}", s)),
+        }
+    }
+}
+
+// Tuning holds the gameplay parameters all peers have to agree on
+// it is part of the game state, so any mismatch shows up in the checksums
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct Tuning {
+    pub movement_model: MovementModel,
+    pub ship_height: f32,
+    pub ship_base: f32,
+    // disconnected players are controlled by a bot instead of spinning in place
+    pub bot_takeover: bool,
+    // length of a round, rounds never end if it is 0
+    pub round_frames: Frame,
+    pub rotation: MapRotation,
+    // meteors periodically rain down on the arena
+    pub meteor_showers: bool,
+    // decides when a round is won
+    pub win_condition: WinConditionId,
+    // a match is over once a player won the majority of this many rounds, or all of them were
+    // played, matches never end if it is 0
+    pub best_of: u32,
+    // the session starts with a countdown, the simulation only runs once all peers synchronized,
+    // so everyone gets control on the same frame
+    pub countdown: bool,
+    // frames simulated per second, every duration and speed of the simulation is converted with it
+    pub tick_rate: u32,
+}
+
+// ship scales have to be positive, ships without a size or of infinite size break collisions
+pub fn parse_ship_scale(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(scale) if scale.is_finite() && scale > 0.0 => Ok(scale),
+        _ => Err(format!(
+            "invalid ship scale: {}, it has to be a positive number",
+            s
+        )),
+    }
+}
+
+impl Tuning {
+    pub fn new(movement_model: MovementModel, ship_scale: f32) -> Self {
+        assert!(
+            ship_scale.is_finite() && ship_scale > 0.0,
+            "invalid ship scale {}",
+            ship_scale
+        );
+        Self {
+            movement_model,
+            ship_height: SHIP_HEIGHT * ship_scale,
+            ship_base: SHIP_BASE * ship_scale,
+            bot_takeover: false,
+            round_frames: 0,
+            rotation: MapRotation::default(),
+            meteor_showers: false,
+            win_condition: WinConditionId::Endless,
+            best_of: 0,
+            countdown: false,
+            tick_rate: FPS as u32,
+        }
+    }
+
+    // the frames that take `seconds`
+    pub fn frames(&self, seconds: f32) -> Frame {
+        (seconds * self.tick_rate as f32).round() as Frame
+    }
+
+    // the seconds `frames` take
+    pub fn seconds(&self, frames: Frame) -> f32 {
+        frames as f32 / self.tick_rate as f32
+    }
+
+    // the part of an amount per second that falls on a single frame
+    pub fn per_frame(&self, per_second: f32) -> f32 {
+        per_second / self.tick_rate as f32
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.tick_rate as f32)
+    }
+
+    pub(super) fn countdown_frames(&self) -> Frame {
+        if self.countdown {
+            self.frames(COUNTDOWN)
+        } else {
+            0
+        }
+    }
+}
+
+// Round is the part of the state describing the round being played
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct Round {
+    // counts from 0
+    pub number: u32,
+    pub arena: Arena,
+    pub start_frame: Frame,
+    // set once the round is over, until the next one starts
+    pub transition: Option<RoundTransition>,
+    // only set in generated arenas
+    pub layout: Option<Layout>,
+}
+
+impl Round {
+    // generates the layout of generated arenas from `rng`, the other arenas don't touch it
+    pub fn new(number: u32, arena: Arena, start_frame: Frame, rng: &mut Rng) -> Self {
+        Self {
+            number,
+            arena,
+            start_frame,
+            transition: None,
+            layout: (arena == Arena::Generated).then(|| Layout::generate(rng)),
+        }
+    }
+
+    pub fn walls(&self) -> &[Wall] {
+        match &self.layout {
+            Some(layout) => layout.walls(),
+            None => self.arena.walls(),
+        }
+    }
+}
+
+// RoundTransition announces the next round, so every peer switches arenas on the same frame
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct RoundTransition {
+    // `None` if the round ended because its time was up
+    pub outcome: Option<Outcome>,
+    pub next_arena: Arena,
+    pub start_frame: Frame,
+}
+
+// MatchEnd is the part of the state after the last round of a match
+// every player votes for a rematch with their inputs, so all peers agree on when it starts
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct MatchEnd {
+    pub rematch_votes: [bool; MAX_PLAYERS],
+    // set once everyone voted
+    pub rematch_frame: Option<Frame>,
+}
+
+// BoxGameState holds all relevant information about the game state
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct GameState {
+    pub frame: i32,
+    pub num_players: usize,
+    pub tuning: Tuning,
+    pub rng: Rng,
+    pub round: Round,
+    // set once the last round of the match is over
+    pub match_end: Option<MatchEnd>,
+    pub vote: Option<Vote>,
+    // per player data lives in fixed size arrays, so saving a state never allocates
+    // only the first `num_players` entries are in use
+    pub positions: [(f32, f32); MAX_PLAYERS],
+    pub velocities: [(f32, f32); MAX_PLAYERS],
+    pub rotations: [f32; MAX_PLAYERS],
+    // frame on which each player pressed a button for the first time
+    pub first_input_frames: [Option<Frame>; MAX_PLAYERS],
+    // frame on which each player last pressed a movement button
+    pub last_active_frames: [Frame; MAX_PLAYERS],
+    // kicked players are controlled like disconnected ones
+    pub kicked: [bool; MAX_PLAYERS],
+    // ships with latched thrust thrust without holding up, `INPUT_TOGGLE_THRUST` turns it on and
+    // off when it is pressed, so whether it was held on the last frame is kept too
+    pub thrust_latched: [bool; MAX_PLAYERS],
+    pub toggle_held: [bool; MAX_PLAYERS],
+    // progress of the win condition, reset every round
+    pub scores: [u32; MAX_PLAYERS],
+    // rounds won in the match
+    pub round_wins: [u32; MAX_PLAYERS],
+    // ships without hull are out of the round
+    pub hull: [u8; MAX_PLAYERS],
+    pub roles: [Role; MAX_PLAYERS],
+    // frame on which each ship was last rammed
+    pub rammed_frames: [Option<Frame>; MAX_PLAYERS],
+    // only used by king of the hill
+    pub zone: ControlZone,
+    // race progress, the index of the next checkpoint and the finished laps
+    pub checkpoints: [u8; MAX_PLAYERS],
+    pub laps: [u8; MAX_PLAYERS],
+    // meteors keep their slot while they fly, free slots are reused by new ones
+    pub meteors: [Option<Meteor>; MAX_METEORS],
+}
+
+impl GameState {
+    // all peers have to start with the same `seed` to stay in sync
+    pub fn new(num_players: usize, tuning: Tuning, seed: u64) -> Self {
+        assert!(num_players <= MAX_PLAYERS);
+        let mut rng = Rng::new(seed);
+        let round = Round::new(
+            0,
+            tuning.rotation.get(0),
+            tuning.countdown_frames(),
+            &mut rng,
+        );
+        let mut state = Self {
+            frame: 0,
+            num_players,
+            tuning,
+            rng,
+            round,
+            match_end: None,
+            vote: None,
+            positions: [(0.0, 0.0); MAX_PLAYERS],
+            velocities: [(0.0, 0.0); MAX_PLAYERS],
+            rotations: [0.0; MAX_PLAYERS],
+            first_input_frames: [None; MAX_PLAYERS],
+            last_active_frames: [0; MAX_PLAYERS],
+            kicked: [false; MAX_PLAYERS],
+            thrust_latched: [false; MAX_PLAYERS],
+            toggle_held: [false; MAX_PLAYERS],
+            scores: [0; MAX_PLAYERS],
+            round_wins: [0; MAX_PLAYERS],
+            hull: [HULL; MAX_PLAYERS],
+            roles: [Role::Runner; MAX_PLAYERS],
+            rammed_frames: [None; MAX_PLAYERS],
+            zone: ControlZone {
+                moved_frame: tuning.countdown_frames(),
+                ..Default::default()
+            },
+            checkpoints: [0; MAX_PLAYERS],
+            laps: [0; MAX_PLAYERS],
+            meteors: [None; MAX_METEORS],
+        };
+        state.spawn_ships();
+        tuning.win_condition.get().start_round(&mut state);
+        state
+    }
+
+    // the seconds left until ships can be controlled, `None` once the countdown is over
+    pub fn countdown(&self) -> Option<u32> {
+        let remaining = self.tuning.countdown_frames() - self.frame;
+        (remaining > 0).then(|| self.tuning.seconds(remaining).ceil() as u32)
+    }
+
+    // places all ships at their starting positions, with their thrust off
+    fn spawn_ships(&mut self) {
+        self.thrust_latched = [false; MAX_PLAYERS];
+        if let Some(layout) = self.round.layout {
+            // ships of generated arenas spawn on their spawn points, facing the center
+            for i in 0..self.num_players {
+                let (x, y) = layout.spawn(i);
+                let rot = (WINDOW_HEIGHT / 2.0 - y).atan2(WINDOW_WIDTH / 2.0 - x);
+                self.positions[i] = (x, y);
+                self.velocities[i] = (0.0, 0.0);
+                self.rotations[i] = rot.rem_euclid(2.0 * std::f32::consts::PI);
+            }
+            return;
+        }
+
+        let r = WINDOW_WIDTH / 4.0;
+        // players spawn evenly spaced on a randomly rotated circle
+        let spawn_offset = self.rng.next_f32() * 2.0 * std::f32::consts::PI;
+
+        for i in 0..self.num_players {
+            let rot =
+                i as f32 / self.num_players as f32 * 2.0 * std::f32::consts::PI + spawn_offset;
+            let x = WINDOW_WIDTH / 2.0 + r * rot.cos();
+            let y = WINDOW_HEIGHT / 2.0 + r * rot.sin();
+            self.positions[i] = (x, y);
+            self.velocities[i] = (0.0, 0.0);
+            self.rotations[i] = (rot + std::f32::consts::PI) % (2.0 * std::f32::consts::PI);
+        }
+    }
+
+    // ends the round after `round_frames` and starts the next one after the transition
+    // returns whether ships can move on this frame
+    fn advance_round(&mut self) -> bool {
+        let match_decided = self.match_decided();
+        let round = &mut self.round;
+        match round.transition {
+            Some(transition) if self.frame >= transition.start_frame && match_decided => {
+                self.match_end = Some(MatchEnd::default());
+                false
+            }
+            Some(transition) if self.frame >= transition.start_frame => {
+                *round = Round::new(
+                    round.number + 1,
+                    transition.next_arena,
+                    self.frame,
+                    &mut self.rng,
+                );
+                self.spawn_ships();
+                // first inputs are compared per round
+                self.first_input_frames = [None; MAX_PLAYERS];
+                self.scores = [0; MAX_PLAYERS];
+                self.hull = [HULL; MAX_PLAYERS];
+                self.rammed_frames = [None; MAX_PLAYERS];
+                self.checkpoints = [0; MAX_PLAYERS];
+                self.laps = [0; MAX_PLAYERS];
+                self.tuning.win_condition.get().start_round(self);
+                true
+            }
+            Some(_) => false,
+            None if self.tuning.round_frames > 0
+                && self.frame - round.start_frame > self.tuning.round_frames =>
+            {
+                self.end_round(None);
+                false
+            }
+            None => true,
+        }
+    }
+
+    // announces the next round, which starts after the transition
+    pub fn end_round(&mut self, outcome: Option<Outcome>) {
+        if let Some(Outcome::Won(winner)) = outcome {
+            self.round_wins[winner] += 1;
+        }
+        self.round.transition = Some(RoundTransition {
+            outcome,
+            next_arena: self.tuning.rotation.get(self.round.number + 1),
+            start_frame: self.frame + self.tuning.frames(ROUND_TRANSITION),
+        });
+    }
+
+    // whether the match is over once the transition after the current round ends
+    fn match_decided(&self) -> bool {
+        let best_of = self.tuning.best_of;
+        let majority = best_of / 2 + 1;
+        best_of > 0
+            && (self.round.number + 1 >= best_of
+                || self.round_wins[..self.num_players]
+                    .iter()
+                    .any(|&wins| wins >= majority))
+    }
+
+    // the player who won the most rounds, `None` if several did
+    pub fn match_winner(&self) -> Option<usize> {
+        let wins = &self.round_wins[..self.num_players];
+        let most = wins.iter().max()?;
+        let mut winners = (0..self.num_players).filter(|&i| wins[i] == *most);
+        match (winners.next(), winners.next()) {
+            (Some(winner), None) => Some(winner),
+            _ => None,
+        }
+    }
+
+    // collects rematch votes after the match and starts the rematch once everyone agreed
+    // disconnected players can't vote and don't hold up the others
+    fn advance_match_end(&mut self, inputs: &[Option<u8>]) {
+        let Some(match_end) = &mut self.match_end else {
+            return;
+        };
+        for (vote, input) in match_end.rematch_votes.iter_mut().zip(inputs) {
+            *vote |= input
+                .is_none_or(|input| VoteAction::from_input(input) == Some(VoteAction::Rematch));
+        }
+        if match_end.rematch_frame.is_none()
+            && match_end.rematch_votes[..self.num_players]
+                .iter()
+                .all(|&vote| vote)
+        {
+            match_end.rematch_frame = Some(self.frame + self.tuning.frames(REMATCH_DELAY));
+        }
+        if match_end
+            .rematch_frame
+            .is_some_and(|rematch_frame| self.frame >= rematch_frame)
+        {
+            self.start_rematch();
+        }
+    }
+
+    // replaces the state with a fresh one, continuing from the current frame
+    // the seed of the rematch comes from the shared rng, so every peer creates the same state
+    fn start_rematch(&mut self) {
+        let seed = (self.rng.next_u32() as u64) << 32 | self.rng.next_u32() as u64;
+        let frame = self.frame;
+        let previous = std::mem::replace(self, Self::new(self.num_players, self.tuning, seed));
+        self.frame = frame;
+        self.round.start_frame = frame;
+        self.zone.moved_frame = frame;
+        // who was active and who got kicked carries over, first inputs are measured again
+        self.last_active_frames = previous.last_active_frames;
+        self.kicked = previous.kicked;
+    }
+
+    // `inputs` holds the pressed buttons of every player, `None` for disconnected players
+    pub fn advance(&mut self, inputs: &[Option<u8>]) {
+        // increase the frame counter
+        self.frame += 1;
+        if self.countdown().is_some() {
+            return;
+        }
+        if self.match_end.is_some() {
+            self.advance_match_end(inputs);
+            return;
+        }
+        vote::advance_votes(self, inputs);
+        if !self.advance_round() {
+            return;
+        }
+        let meteor_starts = self.advance_meteors();
+
+        // velocities are kept in pixels per frame
+        let acceleration = self.tuning.per_frame(self.tuning.per_frame(ACCELERATION));
+        let rotation_speed = self.tuning.per_frame(ROTATION_SPEED);
+        let friction = FRICTION.powf(self.tuning.per_frame(60.0));
+
+        // bots decide before anyone moves, so the player order doesn't matter
+        let mut bot_inputs = [INPUT_LEFT; MAX_PLAYERS];
+        if self.tuning.bot_takeover {
+            for (i, bot_input) in bot_inputs.iter_mut().enumerate().take(self.num_players) {
+                *bot_input = self.bot_input(i, inputs);
+            }
+        }
+
+        // meteors are checked against the whole way ships moved this frame
+        let start_positions = self.positions;
+        for (i, input) in inputs.iter().enumerate().take(self.num_players) {
+            let input = if self.kicked[i] { None } else { *input };
+            if self.first_input_frames[i].is_none() && input.is_some_and(|input| input != 0) {
+                self.first_input_frames[i] = Some(self.frame);
+            }
+            if input.is_some_and(|input| input & INPUT_MOVEMENT != 0) {
+                self.last_active_frames[i] = self.frame;
+            }
+            // wrecks stay where they were destroyed
+            if self.hull[i] == 0 {
+                self.velocities[i] = (0.0, 0.0);
+                continue;
+            }
+
+            // get input of that player, disconnected players spin or are taken over by a bot
+            let input = match input {
+                Some(input) => self.latch_thrust(i, input),
+                None => {
+                    self.thrust_latched[i] = false;
+                    bot_inputs[i]
+                }
+            };
+            let role = self.roles[i];
+
+            // old values
+            let (old_x, old_y) = self.positions[i];
+            let (old_vel_x, old_vel_y) = self.velocities[i];
+            let mut rot = self.rotations[i];
+
+            let (mut vel_x, mut vel_y) = match self.tuning.movement_model {
+                MovementModel::Inertia => {
+                    // slow down
+                    let mut vel_x = old_vel_x * friction;
+                    let mut vel_y = old_vel_y * friction;
+
+                    // thrust
+                    if input & INPUT_UP != 0 && input & INPUT_DOWN == 0 {
+                        vel_x += acceleration * role.speed() * rot.cos();
+                        vel_y += acceleration * role.speed() * rot.sin();
+                    }
+                    // break
+                    if input & INPUT_UP == 0 && input & INPUT_DOWN != 0 {
+                        vel_x -= acceleration * role.speed() * rot.cos();
+                        vel_y -= acceleration * role.speed() * rot.sin();
+                    }
+                    (vel_x, vel_y)
+                }
+                MovementModel::Tank => {
+                    // drive forward or backward, stand still otherwise
+                    let speed = self.tuning.per_frame(TANK_SPEED) * role.speed();
+                    if input & INPUT_UP != 0 && input & INPUT_DOWN == 0 {
+                        (speed * rot.cos(), speed * rot.sin())
+                    } else if input & INPUT_UP == 0 && input & INPUT_DOWN != 0 {
+                        (-speed * rot.cos(), -speed * rot.sin())
+                    } else {
+                        (0.0, 0.0)
+                    }
+                }
+            };
+            // turn left
+            if input & INPUT_LEFT != 0 && input & INPUT_RIGHT == 0 {
+                rot = (rot - rotation_speed * role.turn_speed())
+                    .rem_euclid(2.0 * std::f32::consts::PI);
+            }
+            // turn right
+            if input & INPUT_LEFT == 0 && input & INPUT_RIGHT != 0 {
+                rot = (rot + rotation_speed * role.turn_speed())
+                    .rem_euclid(2.0 * std::f32::consts::PI);
+            }
+
+            // limit speed
+            let max_speed = self.tuning.per_frame(MAX_SPEED) * role.speed();
+            let magnitude = (vel_x * vel_x + vel_y * vel_y).sqrt();
+            if magnitude > max_speed {
+                vel_x = (vel_x * max_speed) / magnitude;
+                vel_y = (vel_y * max_speed) / magnitude;
+            }
+
+            // compute new position
+            let radius = self.tuning.ship_height / 2.0;
+            let ((x, y), (vel_x, vel_y)) = arena::collide(
+                self.round.walls(),
+                (old_x + vel_x, old_y + vel_y),
+                (vel_x, vel_y),
+                radius,
+            );
+
+            // update all state
+            self.positions[i] = self.constrain_to_window((x, y));
+            self.velocities[i] = (vel_x, vel_y);
+            self.rotations[i] = rot;
+        }
+
+        // ships are hit after all of them moved, so the player order doesn't matter
+        self.collide_meteors(&meteor_starts, &start_positions);
+
+        // the win condition runs its own rules and decides when the round is over
+        let win_condition = self.tuning.win_condition.get();
+        win_condition.advance(self);
+        if let Some(outcome) = win_condition.outcome(self) {
+            self.end_round(Some(outcome));
+        }
+    }
+
+    // constrains a ship to the canvas borders, the whole ship has to stay inside
+    fn constrain_to_window(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let radius = self.tuning.ship_height / 2.0;
+        (
+            x.max(radius).min(WINDOW_WIDTH - radius),
+            y.max(radius).min(WINDOW_HEIGHT - radius),
+        )
+    }
+
+    // moves all meteors and spawns new ones during a shower, returns where every meteor was at
+    // the start of the frame, new ones start where they spawned
+    fn advance_meteors(&mut self) -> [(f32, f32); MAX_METEORS] {
+        let mut starts = [(0.0, 0.0); MAX_METEORS];
+        if !self.tuning.meteor_showers {
+            return starts;
+        }
+        for (slot, start) in self.meteors.iter().zip(starts.iter_mut()) {
+            if let Some(meteor) = slot {
+                *start = meteor.position;
+            }
+        }
+        meteor::advance_all(&mut self.meteors);
+        let interval = self.tuning.frames(SHOWER_INTERVAL);
+        let showering =
+            self.frame >= interval && self.frame % interval < self.tuning.frames(SHOWER_DURATION);
+        if showering && self.rng.next_f32() < self.tuning.per_frame(METEOR_SPAWN_RATE) {
+            if let Some(i) = self.meteors.iter().position(|slot| slot.is_none()) {
+                let meteor = Meteor::spawn(&mut self.rng, &self.tuning);
+                starts[i] = meteor.position;
+                self.meteors[i] = Some(meteor);
+            }
+        }
+        starts
+    }
+
+    // knocks away every ship hit by a meteor on the way from `meteor_starts` and `start_positions`,
+    // meteors break on impact
+    fn collide_meteors(
+        &mut self,
+        meteor_starts: &[(f32, f32); MAX_METEORS],
+        start_positions: &[(f32, f32); MAX_PLAYERS],
+    ) {
+        let radius = self.tuning.ship_height / 2.0;
+        let knockback = METEOR_KNOCKBACK * self.tuning.tick_rate as f32;
+        for (slot, meteor_start) in self.meteors.iter_mut().zip(meteor_starts) {
+            let Some(meteor) = slot else {
+                continue;
+            };
+            let mut hit = false;
+            for (i, from) in start_positions.iter().enumerate().take(self.num_players) {
+                if !meteor.hits(*meteor_start, *from, self.positions[i], radius) {
+                    continue;
+                }
+                hit = true;
+                let (meteor_vel_x, meteor_vel_y) = meteor.velocity;
+                let (x, y) = self.positions[i];
+                let (vel_x, vel_y) = self.velocities[i];
+                self.positions[i] = (x + meteor_vel_x * knockback, y + meteor_vel_y * knockback);
+                self.velocities[i] = (vel_x + meteor_vel_x, vel_y + meteor_vel_y);
+            }
+            if hit {
+                *slot = None;
+            }
+        }
+        for i in 0..self.num_players {
+            self.positions[i] = self.constrain_to_window(self.positions[i]);
+        }
+    }
+
+    // steers the ship of `player` towards the closest connected ship
+    // only uses the operations the simulation already relies on, so all peers get the same result
+    pub(crate) fn bot_input(&self, player: usize, inputs: &[Option<u8>]) -> u8 {
+        let (x, y) = self.positions[player];
+        let target = (0..self.num_players)
+            .filter(|&i| i != player && self.hull[i] > 0)
+            .filter(|&i| inputs.get(i).is_some_and(|input| input.is_some()))
+            .map(|i| {
+                let (target_x, target_y) = self.positions[i];
+                (target_x - x, target_y - y)
+            })
+            .min_by(|(ax, ay), (bx, by)| (ax * ax + ay * ay).total_cmp(&(bx * bx + by * by)));
+        let Some((dx, dy)) = target else {
+            return 0;
+        };
+
+        let rot = self.rotations[player];
+        let (dir_x, dir_y) = (rot.cos(), rot.sin());
+        // positive if the target is to the right of the ship
+        let cross = dir_x * dy - dir_y * dx;
+        let dot = dir_x * dx + dir_y * dy;
+        let distance = (dx * dx + dy * dy).sqrt();
+
+        let mut input = if cross > 0.0 { INPUT_RIGHT } else { INPUT_LEFT };
+        // only thrust when roughly facing the target and not already on top of it
+        if dot > 0.9 * distance && distance > self.tuning.ship_height {
+            input |= INPUT_UP;
+        }
+        input
+    }
+
+    // turns latched thrust on or off when `INPUT_TOGGLE_THRUST` is pressed, and returns the input
+    // the ship is steered with, thrusting while latched unless the player brakes
+    fn latch_thrust(&mut self, player: usize, mut input: u8) -> u8 {
+        let held = input & INPUT_TOGGLE_THRUST != 0;
+        if held && !self.toggle_held[player] {
+            self.thrust_latched[player] = !self.thrust_latched[player];
+        }
+        self.toggle_held[player] = held;
+        input &= !INPUT_TOGGLE_THRUST;
+        if self.thrust_latched[player] && input & INPUT_DOWN == 0 {
+            input |= INPUT_UP;
+        }
+        input
+    }
+
+    // returns a copy of the state with the order of all players reversed
+    pub fn mirrored(&self) -> Self {
+        let n = self.num_players;
+        let mut mirrored = self.clone();
+        mirrored.positions[..n].reverse();
+        mirrored.velocities[..n].reverse();
+        mirrored.rotations[..n].reverse();
+        mirrored.first_input_frames[..n].reverse();
+        mirrored.last_active_frames[..n].reverse();
+        mirrored.kicked[..n].reverse();
+        mirrored.thrust_latched[..n].reverse();
+        mirrored.toggle_held[..n].reverse();
+        if let Some(vote) = &mut mirrored.vote {
+            vote.caller = n - 1 - vote.caller;
+            vote.ballots[..n].reverse();
+            if let VoteKind::KickAfk(target) = &mut vote.kind {
+                *target = n - 1 - *target;
+            }
+        }
+        mirrored.scores[..n].reverse();
+        mirrored.round_wins[..n].reverse();
+        mirrored.hull[..n].reverse();
+        mirrored.roles[..n].reverse();
+        if let Some(match_end) = &mut mirrored.match_end {
+            match_end.rematch_votes[..n].reverse();
+        }
+        mirrored.rammed_frames[..n].reverse();
+        mirrored.checkpoints[..n].reverse();
+        mirrored.laps[..n].reverse();
+        if let Some(layout) = &mut mirrored.round.layout {
+            layout.mirror(n);
+        }
+        if let Some(transition) = &mut mirrored.round.transition {
+            if let Some(Outcome::Won(winner)) = &mut transition.outcome {
+                *winner = n - 1 - *winner;
+            }
+        }
+        mirrored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_cycle_through_the_rotation() {
+        let mut tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        tuning.rotation = "pillars,corridors".parse().unwrap();
+        tuning.round_frames = 60;
+        let mut state = GameState::new(2, tuning, 0);
+        let mut played = vec![state.round.arena];
+        let mut announced = Vec::new();
+        while state.round.number < 3 {
+            let transition = state.round.transition;
+            state.advance(&[Some(INPUT_UP), Some(INPUT_UP)]);
+            if state.round.transition.is_some() && transition.is_none() {
+                announced.extend(state.round.transition.map(|t| t.next_arena));
+            }
+            if state.round.number as usize == played.len() {
+                played.push(state.round.arena);
+            }
+        }
+        assert_eq!(
+            played,
+            [
+                Arena::Pillars,
+                Arena::Corridors,
+                Arena::Pillars,
+                Arena::Corridors
+            ]
+        );
+        assert_eq!(announced, played[1..]);
+        // first inputs are measured again in every round
+        let start = Some(state.round.start_frame);
+        assert_eq!(state.first_input_frames[..2], [start, start]);
+    }
+
+    #[test]
+    fn matches_end_once_a_player_won_the_majority() {
+        let mut tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        tuning.best_of = 3;
+        let mut state = GameState::new(2, tuning, 0);
+        for round in 0..2 {
+            assert_eq!(state.round.number, round);
+            state.end_round(Some(Outcome::Won(1)));
+            for _ in 0..tuning.frames(ROUND_TRANSITION) {
+                state.advance(&[Some(INPUT_UP), Some(INPUT_UP)]);
+            }
+        }
+        // the third round isn't played anymore
+        assert_eq!(state.round.number, 1);
+        assert!(state.match_end.is_some());
+        assert_eq!(state.round_wins[..2], [0, 2]);
+        assert_eq!(state.match_winner(), Some(1));
+
+        // the rematch measures first inputs from scratch
+        assert!(state.first_input_frames[0].is_some());
+        let rematch = VoteAction::Rematch.input();
+        while state.match_end.is_some() {
+            state.advance(&[Some(rematch), Some(rematch)]);
+        }
+        assert_eq!(state.first_input_frames[..2], [None, None]);
+    }
+
+    #[test]
+    fn ships_are_controlled_after_the_countdown() {
+        let mut tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        tuning.countdown = true;
+        let mut state = GameState::new(2, tuning, 0);
+        let spawn = state.positions;
+        for _ in 1..tuning.frames(COUNTDOWN) {
+            state.advance(&[Some(INPUT_UP), Some(INPUT_UP)]);
+        }
+        assert_eq!(state.countdown(), Some(1));
+        assert_eq!(state.positions, spawn);
+        state.advance(&[Some(INPUT_UP), Some(INPUT_UP)]);
+        assert_eq!(state.countdown(), None);
+        assert_ne!(state.positions, spawn);
+    }
+
+    #[test]
+    fn ships_cover_the_same_distance_at_every_tick_rate() {
+        for movement_model in [MovementModel::Inertia, MovementModel::Tank] {
+            let distances: Vec<f32> = [30, 60, 120]
+                .into_iter()
+                .map(|tick_rate| {
+                    let mut tuning = Tuning::new(movement_model, 1.0);
+                    tuning.tick_rate = tick_rate;
+                    let mut state = GameState::new(1, tuning, 0);
+                    let (start_x, start_y) = state.positions[0];
+                    for _ in 0..tuning.frames(0.5) {
+                        state.advance(&[Some(INPUT_UP)]);
+                    }
+                    let (x, y) = state.positions[0];
+                    ((x - start_x).powi(2) + (y - start_y).powi(2)).sqrt()
+                })
+                .collect();
+            for distance in &distances {
+                assert!((distance - distances[1]).abs() < 0.05 * distances[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn ship_scales_have_to_be_positive() {
+        assert_eq!(parse_ship_scale("2.5"), Ok(2.5));
+        for scale in ["0", "-1", "inf", "NaN", "big"] {
+            assert!(parse_ship_scale(scale).is_err());
+        }
+    }
+
+    #[test]
+    fn a_tap_on_toggle_thrust_thrusts_like_holding_up() {
+        let tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        let mut held = GameState::new(1, tuning, 0);
+        let mut toggled = GameState::new(1, tuning, 0);
+        for frame in 0..60 {
+            held.advance(&[Some(INPUT_UP)]);
+            // holding the toggle doesn't turn it off again
+            let input = if frame < 3 { INPUT_TOGGLE_THRUST } else { 0 };
+            toggled.advance(&[Some(input)]);
+        }
+        assert_eq!(toggled.positions[0], held.positions[0]);
+        assert!(toggled.thrust_latched[0]);
+
+        // braking doesn't turn it off either, and neither does holding up and down at once
+        toggled.advance(&[Some(INPUT_DOWN)]);
+        toggled.advance(&[Some(INPUT_UP | INPUT_DOWN)]);
+        assert!(toggled.thrust_latched[0]);
+        // another tap does
+        toggled.advance(&[Some(INPUT_TOGGLE_THRUST)]);
+        toggled.advance(&[Some(0)]);
+        assert!(!toggled.thrust_latched[0]);
+    }
+}
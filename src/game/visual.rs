@@ -0,0 +1,140 @@
+// what the ships look like on screen, as opposed to where the simulation has them
+// it is only ever written from the state, never the other way around, so easing and effects can
+// be tweaked freely without touching determinism or checksums
+use std::f32::consts::PI;
+
+use super::{render::RenderState, GameState, MAX_PLAYERS};
+
+// seconds until a correction after a rollback has shrunk to a third
+const SMOOTHING_TIME: f32 = 0.1;
+// ships further off than this after a rollback are shown where they are right away, e.g. after
+// they respawned, gliding across the arena would only confuse
+const SNAP_DISTANCE: f32 = 100.0;
+// seconds a ship flashes after losing hull
+const FLASH_DURATION: f32 = 0.2;
+
+// VisualState eases the ships shown towards the simulated ones after rollbacks instead of letting
+// them jump, and keeps the effects of what happened to them
+#[derive(Clone, Copy)]
+pub(super) struct VisualState {
+    pub(super) positions: [(f32, f32); MAX_PLAYERS],
+    pub(super) rotations: [f32; MAX_PLAYERS],
+    // how far the shown ships are off the simulated ones, shrinking every rendered frame
+    offsets: [(f32, f32); MAX_PLAYERS],
+    rotation_offsets: [f32; MAX_PLAYERS],
+    // seconds left of the flash of each ship
+    flashes: [f32; MAX_PLAYERS],
+    hull: [u8; MAX_PLAYERS],
+    // set by rollbacks, the next update measures how far they moved the ships
+    corrected: bool,
+}
+
+impl VisualState {
+    // shows the ships exactly where they are in `state`
+    pub(super) fn new(state: &GameState) -> Self {
+        Self {
+            positions: state.positions,
+            rotations: state.rotations,
+            offsets: [(0.0, 0.0); MAX_PLAYERS],
+            rotation_offsets: [0.0; MAX_PLAYERS],
+            flashes: [0.0; MAX_PLAYERS],
+            hull: state.hull,
+            corrected: false,
+        }
+    }
+
+    pub(super) fn mark_corrected(&mut self) {
+        self.corrected = true;
+    }
+
+    // follows the simulation, interpolated between `previous` and `state` by `alpha`
+    // `dt` is the time since the last update in seconds, without `smoothing` corrections snap
+    pub(super) fn update(
+        &mut self,
+        previous: &RenderState,
+        state: &GameState,
+        alpha: f32,
+        dt: f32,
+        smoothing: bool,
+    ) {
+        let decay = (-dt / SMOOTHING_TIME).exp();
+        for i in 0..state.num_players {
+            let ((x, y), rotation) = previous.interpolate(state, i, alpha);
+            if self.corrected {
+                let (shown_x, shown_y) = self.positions[i];
+                let (offset_x, offset_y) = (shown_x - x, shown_y - y);
+                let close =
+                    offset_x * offset_x + offset_y * offset_y < SNAP_DISTANCE * SNAP_DISTANCE;
+                self.offsets[i] = match close {
+                    true => (offset_x, offset_y),
+                    false => (0.0, 0.0),
+                };
+                // along the shorter arc, rotations wrap around at 2 PI
+                self.rotation_offsets[i] = match close {
+                    true => (self.rotations[i] - rotation + PI).rem_euclid(2.0 * PI) - PI,
+                    false => 0.0,
+                };
+            }
+            if !smoothing {
+                self.offsets[i] = (0.0, 0.0);
+                self.rotation_offsets[i] = 0.0;
+            }
+            let (offset_x, offset_y) = &mut self.offsets[i];
+            *offset_x *= decay;
+            *offset_y *= decay;
+            self.rotation_offsets[i] *= decay;
+            self.positions[i] = (x + *offset_x, y + *offset_y);
+            self.rotations[i] = rotation + self.rotation_offsets[i];
+
+            // a hit a rollback took back still flashed, that is only cosmetic
+            if state.hull[i] < self.hull[i] {
+                self.flashes[i] = FLASH_DURATION;
+            }
+            self.flashes[i] = (self.flashes[i] - dt).max(0.0);
+            self.hull[i] = state.hull[i];
+        }
+        self.corrected = false;
+    }
+
+    // how much of the flash of ship `i` is left, from 1 right after the hit down to 0
+    pub(super) fn flash(&self, i: usize) -> f32 {
+        self.flashes[i] / FLASH_DURATION
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{MovementModel, Tuning};
+
+    #[test]
+    fn corrections_are_eased_out_instead_of_snapping() {
+        let tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        let mut state = GameState::new(2, tuning, 0);
+        let mut visual = VisualState::new(&state);
+        let (x, y) = state.positions[0];
+
+        // a rollback moved the first ship a little and respawned the second one far away
+        state.positions[0] = (x + 30.0, y);
+        state.positions[1].0 += 2.0 * SNAP_DISTANCE;
+        state.hull[0] -= 1;
+        let previous = RenderState::new(&state);
+        visual.mark_corrected();
+        visual.update(&previous, &state, 1.0, 0.0, true);
+        assert!((visual.positions[0].0 - x).abs() < 1e-3);
+        assert_eq!(visual.positions[1], state.positions[1]);
+        assert_eq!(visual.flash(0), 1.0);
+
+        for _ in 0..60 {
+            visual.update(&previous, &state, 1.0, 1.0 / 60.0, true);
+        }
+        assert!((visual.positions[0].0 - (x + 30.0)).abs() < 0.1);
+        assert_eq!(visual.flash(0), 0.0);
+
+        // without smoothing, the next correction snaps
+        state.positions[0].0 = x;
+        visual.mark_corrected();
+        visual.update(&RenderState::new(&state), &state, 1.0, 0.0, false);
+        assert_eq!(visual.positions[0].0, x);
+    }
+}
@@ -0,0 +1,431 @@
+use backroll_transport::Peer;
+use bevy_tasks::TaskPool;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    block_list::BlockList,
+    color::PlayerColor,
+    game::Frame,
+    identity::{PeerId, PEER_ID_LEN},
+    transport::wrap_peer,
+    Error,
+};
+
+// the first byte of every message tells whether it is meant for backroll or the side channel
+const SESSION: u8 = 0;
+const PROFILE: u8 = 1;
+const SLOW_MOTION: u8 = 2;
+// debug requests are sent this many times, they aren't acknowledged and udp may lose them
+const REQUEST_COPIES: usize = 3;
+
+// profiles are sent again until both sides know each other's, since udp may lose them
+const RESEND_INTERVAL: Duration = Duration::from_millis(250);
+// peers that never answer are given up on after this long
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+pub const MAX_NAME_LEN: usize = 16;
+
+// Profile is what players tell each other about themselves when they connect
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub id: PeerId,
+    pub name: String,
+    // the colors of all players of a peer, in the order they appear in the player list
+    pub colors: Vec<PlayerColor>,
+    // where the peer lists its own players, to catch peers that don't agree on the player order
+    pub order: PlayerOrder,
+    // the seed of the match, only the one of the host counts, everyone else plays with it
+    pub seed: u64,
+    // frames simulated per second, peers at another rate would run at another speed
+    pub tick_rate: u32,
+    // the checksum of the gameplay options, e.g. peers with another ship size would desync
+    pub tuning: u64,
+}
+
+impl Profile {
+    // whether the peer of this profile can play with us, `addr` is where it is in `players` and
+    // `local` is our own profile
+    pub fn check(&self, players: &[String], addr: &str, local: &Profile) -> Result<(), Error> {
+        // peers listing themselves at the same position would each play the other's inputs
+        let expected = PlayerOrder::of(players, addr);
+        if self.order != expected {
+            return Err(Error::PlayerOrderMismatch {
+                name: self.name.clone(),
+                expected,
+                reported: self.order.clone(),
+            });
+        }
+        if self.tick_rate != local.tick_rate {
+            return Err(Error::TickRateMismatch {
+                name: self.name.clone(),
+                local: local.tick_rate,
+                remote: self.tick_rate,
+            });
+        }
+        // the tick rate is part of the tuning too, so it is checked first to say what to change
+        if self.tuning != local.tuning {
+            return Err(Error::TuningMismatch {
+                name: self.name.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+// PlayerOrder is where a peer's own players are in its `--players`, counting from 0
+// backroll tells players apart by their position, so all peers have to list them in the same order
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerOrder {
+    pub num_players: usize,
+    pub positions: Vec<usize>,
+}
+
+impl PlayerOrder {
+    // the positions of the players sharing `addr` in `players`
+    pub fn of(players: &[String], addr: &str) -> Self {
+        Self {
+            num_players: players.len(),
+            positions: (0..players.len()).filter(|i| players[*i] == addr).collect(),
+        }
+    }
+}
+
+// e.g. "players 1 and 3 of 4", counting from 1 like the players do
+impl fmt::Display for PlayerOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let positions: Vec<String> = self.positions.iter().map(|i| (i + 1).to_string()).collect();
+        match positions.split_last() {
+            Some((last, [])) => write!(f, "player {}", last)?,
+            Some((last, rest)) => write!(f, "players {} and {}", rest.join(", "), last)?,
+            None => write!(f, "no players")?,
+        }
+        write!(f, " of {}", self.num_players)
+    }
+}
+
+// Handshake is the profile of a remote peer once it arrived, and whether they got ours
+#[derive(Default)]
+struct Handshake {
+    remote: Option<Profile>,
+    acknowledged: bool,
+    // the last slow motion the peer asked for, as the frame it starts on and its factor
+    slow_motion: Option<(Frame, u32)>,
+    // muted peers are still played against, but everything else they send is dropped
+    muted: bool,
+}
+
+// SideChannel talks to a remote peer next to the session, the profile of the peer shows up once
+// the handshake is done
+#[derive(Clone)]
+pub struct SideChannel {
+    handshake: Arc<Mutex<Handshake>>,
+    network: Peer,
+}
+
+impl SideChannel {
+    pub fn profile(&self) -> Option<Profile> {
+        self.handshake.lock().ok()?.remote.clone()
+    }
+
+    // asks the peer to advance only every `factor`th tick from `frame` on
+    pub fn send_slow_motion(&self, frame: Frame, factor: u32) {
+        let mut message = vec![SLOW_MOTION];
+        message.extend_from_slice(&frame.to_le_bytes());
+        message.extend_from_slice(&factor.to_le_bytes());
+        for _ in 0..REQUEST_COPIES {
+            let _ = self.network.send(message.clone().into_boxed_slice());
+        }
+    }
+
+    // the slow motion the peer asked for since the last call
+    pub fn take_slow_motion(&self) -> Option<(Frame, u32)> {
+        self.handshake.lock().ok()?.slow_motion.take()
+    }
+
+    // blocked peers are muted as soon as their profile arrives
+    pub fn is_muted(&self) -> bool {
+        self.handshake.lock().is_ok_and(|handshake| handshake.muted)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        if let Ok(mut handshake) = self.handshake.lock() {
+            handshake.muted = muted;
+            handshake.slow_motion = None;
+        }
+    }
+}
+
+// wraps the peer of a remote player to exchange profiles with them, starting right away so they
+// are usually known before backroll finishes synchronizing
+// the returned peer is handed to backroll
+pub fn wrap(
+    pool: &TaskPool,
+    network: Peer,
+    local: &Profile,
+    block_list: Arc<BlockList>,
+) -> (Peer, SideChannel) {
+    let channel = SideChannel {
+        handshake: Arc::default(),
+        network: network.clone(),
+    };
+    let local = Profile {
+        id: local.id,
+        name: sanitize(&local.name),
+        colors: local.colors.clone(),
+        order: local.order.clone(),
+        seed: local.seed,
+        tick_rate: local.tick_rate,
+        tuning: local.tuning,
+    };
+
+    let received = channel.handshake.clone();
+    let sender = network.clone();
+    let reply = local.clone();
+    let session = wrap_peer(
+        pool,
+        network.clone(),
+        |message| Some([&[SESSION], message].concat().into_boxed_slice()),
+        move |message| match message.split_first()? {
+            (&SESSION, payload) => Some(payload.into()),
+            (&PROFILE, payload) => {
+                // malformed profiles are dropped, the peer sends its profile again
+                let (acknowledged, profile) = decode_profile(payload).ok()?;
+                if let Ok(mut handshake) = received.lock() {
+                    let first = handshake.remote.is_none();
+                    handshake.muted |= block_list.contains(&profile.id);
+                    handshake.remote = Some(profile);
+                    handshake.acknowledged |= acknowledged;
+                    // answer right away, so they learn that we got their profile
+                    if first {
+                        let _ = sender.send(encode_profile(&reply, true));
+                    }
+                }
+                None
+            }
+            (&SLOW_MOTION, payload) => {
+                let frame = Frame::from_le_bytes(payload.get(..4)?.try_into().ok()?);
+                let factor = u32::from_le_bytes(payload.get(4..8)?.try_into().ok()?);
+                if let Ok(mut handshake) = received.lock() {
+                    if !handshake.muted {
+                        handshake.slow_motion = Some((frame, factor));
+                    }
+                }
+                None
+            }
+            _ => None,
+        },
+    );
+
+    let sent = channel.handshake.clone();
+    thread::spawn(move || {
+        let start = Instant::now();
+        while network.is_connected() && start.elapsed() < HANDSHAKE_TIMEOUT {
+            let (known, acknowledged) = match sent.lock() {
+                Ok(handshake) => (handshake.remote.is_some(), handshake.acknowledged),
+                Err(_) => break,
+            };
+            if known && acknowledged {
+                break;
+            }
+            let _ = network.send(encode_profile(&local, known));
+            thread::sleep(RESEND_INTERVAL);
+        }
+    });
+    (session, channel)
+}
+
+// names are cut to a length that fits above a ship, without control characters
+pub fn sanitize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+// a profile message tells whether we already know the profile of the receiver, followed by the
+// peer id, the number of colors, the colors, the player order, the seed, the tick rate, the
+// checksum of the gameplay options and the name
+// the player order is the number of players, the number of positions and the positions
+fn encode_profile(profile: &Profile, acknowledged: bool) -> Box<[u8]> {
+    let mut message = vec![PROFILE, acknowledged as u8];
+    message.extend_from_slice(&profile.id.0);
+    message.push(profile.colors.len() as u8);
+    for color in profile.colors.iter() {
+        message.extend_from_slice(&color.0);
+    }
+    message.push(profile.order.num_players as u8);
+    message.push(profile.order.positions.len() as u8);
+    message.extend(profile.order.positions.iter().map(|i| *i as u8));
+    message.extend_from_slice(&profile.seed.to_le_bytes());
+    message.extend_from_slice(&profile.tick_rate.to_le_bytes());
+    message.extend_from_slice(&profile.tuning.to_le_bytes());
+    message.extend_from_slice(profile.name.as_bytes());
+    message.into_boxed_slice()
+}
+
+fn decode_profile(payload: &[u8]) -> Result<(bool, Profile), Error> {
+    let truncated = || Error::MalformedProfile("it is truncated");
+    let (&acknowledged, payload) = payload.split_first().ok_or_else(truncated)?;
+    let (id, payload) = payload
+        .split_first_chunk::<PEER_ID_LEN>()
+        .ok_or_else(truncated)?;
+    let (&num_colors, payload) = payload.split_first().ok_or_else(truncated)?;
+    let (colors, payload) = payload
+        .split_at_checked(3 * num_colors as usize)
+        .ok_or_else(truncated)?;
+    let colors = colors
+        .chunks_exact(3)
+        .map(|rgb| PlayerColor([rgb[0], rgb[1], rgb[2]]))
+        .collect();
+    let [num_players, num_positions, payload @ ..] = payload else {
+        return Err(truncated());
+    };
+    let (positions, payload) = payload
+        .split_at_checked(*num_positions as usize)
+        .ok_or_else(truncated)?;
+    let (seed, payload) = payload.split_first_chunk::<8>().ok_or_else(truncated)?;
+    let (tick_rate, payload) = payload.split_first_chunk::<4>().ok_or_else(truncated)?;
+    let (tuning, name) = payload.split_first_chunk::<8>().ok_or_else(truncated)?;
+    let order = PlayerOrder {
+        num_players: *num_players as usize,
+        positions: positions.iter().map(|i| *i as usize).collect(),
+    };
+    let name =
+        std::str::from_utf8(name).map_err(|_| Error::MalformedProfile("the name isn't utf-8"))?;
+    let name = sanitize(name);
+    let profile = Profile {
+        id: PeerId(*id),
+        name,
+        colors,
+        order,
+        seed: u64::from_le_bytes(*seed),
+        tick_rate: u32::from_le_bytes(*tick_rate),
+        tuning: u64::from_le_bytes(*tuning),
+    };
+    Ok((acknowledged != 0, profile))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_are_sanitized() {
+        let profile = Profile {
+            id: PeerId([7; PEER_ID_LEN]),
+            name: "Ada\u{7}Lovelace of the Analytical Engine".to_owned(),
+            colors: vec![PlayerColor([1, 2, 3]), PlayerColor([4, 5, 6])],
+            order: PlayerOrder {
+                num_players: 4,
+                positions: vec![1, 3],
+            },
+            seed: 0x0123_4567_89ab_cdef,
+            tick_rate: 120,
+            tuning: 0xfedc_ba98_7654_3210,
+        };
+        let message = encode_profile(&profile, true);
+        assert_eq!(message[0], PROFILE);
+        let (acknowledged, decoded) = decode_profile(&message[1..]).unwrap();
+        assert!(acknowledged);
+        assert_eq!(decoded.id, profile.id);
+        assert_eq!(decoded.name, "AdaLovelace of t");
+        assert_eq!(decoded.colors, profile.colors);
+        assert_eq!(decoded.order, profile.order);
+        assert_eq!(decoded.seed, profile.seed);
+        assert_eq!(decoded.tick_rate, profile.tick_rate);
+        assert_eq!(decoded.tuning, profile.tuning);
+        let malformed = |payload: &[u8]| match decode_profile(payload) {
+            Err(Error::MalformedProfile(reason)) => reason,
+            _ => panic!("decoded a malformed profile"),
+        };
+        assert_eq!(malformed(&[0; 3]), "it is truncated");
+        let mut invalid_name = vec![0; 24 + PEER_ID_LEN];
+        invalid_name.push(0xff);
+        assert_eq!(malformed(&invalid_name), "the name isn't utf-8");
+        let mut truncated = vec![0; 1 + PEER_ID_LEN];
+        truncated.extend_from_slice(&[2, 1, 2, 3]);
+        assert_eq!(malformed(&truncated), "it is truncated");
+    }
+
+    #[test]
+    fn player_orders_read_like_the_player_list() {
+        let players: Vec<String> = ["localhost", "10.0.0.2:7000", "localhost", "10.0.0.3:7000"]
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        let local = PlayerOrder::of(&players, "localhost");
+        assert_eq!(local.positions, [0, 2]);
+        assert_eq!(local.to_string(), "players 1 and 3 of 4");
+        assert_eq!(
+            PlayerOrder::of(&players, "10.0.0.3:7000").to_string(),
+            "player 4 of 4"
+        );
+    }
+
+    #[test]
+    fn profiles_that_cant_play_together_are_refused() {
+        let players: Vec<String> = ["localhost", "10.0.0.2:7000"]
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        let local = Profile {
+            id: PeerId([1; PEER_ID_LEN]),
+            name: "Ada".to_owned(),
+            colors: vec![PlayerColor([1, 2, 3])],
+            order: PlayerOrder::of(&players, "localhost"),
+            seed: 1,
+            tick_rate: 60,
+            tuning: 7,
+        };
+        // the remote peer lists the players the other way around, so it is player 2 for us
+        let remote = Profile {
+            id: PeerId([2; PEER_ID_LEN]),
+            name: "Grace".to_owned(),
+            order: PlayerOrder {
+                num_players: 2,
+                positions: vec![1],
+            },
+            ..local.clone()
+        };
+        assert!(remote.check(&players, "10.0.0.2:7000", &local).is_ok());
+
+        let swapped = Profile {
+            order: PlayerOrder {
+                num_players: 2,
+                positions: vec![0],
+            },
+            ..remote.clone()
+        };
+        assert!(matches!(
+            swapped.check(&players, "10.0.0.2:7000", &local),
+            Err(Error::PlayerOrderMismatch { .. })
+        ));
+        let faster = Profile {
+            tick_rate: 120,
+            tuning: 8,
+            ..remote.clone()
+        };
+        assert!(matches!(
+            faster.check(&players, "10.0.0.2:7000", &local),
+            Err(Error::TickRateMismatch {
+                local: 60,
+                remote: 120,
+                ..
+            })
+        ));
+        let other_options = Profile {
+            tuning: 8,
+            ..remote
+        };
+        assert!(matches!(
+            other_options.check(&players, "10.0.0.2:7000", &local),
+            Err(Error::TuningMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,364 @@
+// runs two complete sessions in one process, connected over loopback, to check that rollbacks
+// keep both peers in sync
+use backroll::*;
+use backroll_transport::Peer;
+use bevy_tasks::TaskPool;
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    game::{Frame, Game, MovementModel, PlayerInput, Tuning, PREDICTION_WINDOW},
+    rng::Rng,
+    rollback_game::{advance_session, FixedStep, RollbackGame},
+    transport::{simulate_network, NetworkConditions},
+    BackrollConfig, Error,
+};
+
+// the fuzzer picks new buttons once every this many frames on average
+const FUZZ_INPUT_CHANGE: u32 = 8;
+// how often the thread receiving from a socket checks whether its session is still there
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+// larger than any message backroll sends
+const MAX_MESSAGE_LEN: usize = 2048;
+
+// LoopbackSocket is a udp socket on a loopback port picked by the system, so any number of
+// sessions, tests and fuzzers can run at the same time without fighting over ports
+pub struct LoopbackSocket(UdpSocket);
+
+impl LoopbackSocket {
+    pub fn bind() -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))?;
+        socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+        Ok(Self(socket))
+    }
+
+    // the address the system bound the socket to, the other peer sends here
+    pub fn addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+
+    // moves messages between the socket and the returned peer until the session drops the peer
+    fn connect(self, pool: &TaskPool, remote: SocketAddr) -> io::Result<Peer> {
+        let (peer, transport) = Peer::create_unbounded();
+        let receiver = self.0.try_clone()?;
+        let incoming = transport.clone();
+        thread::spawn(move || {
+            let mut buffer = [0; MAX_MESSAGE_LEN];
+            while incoming.is_connected() {
+                // timeouts and errors only lead back to checking the session
+                if let Ok((len, from)) = receiver.recv_from(&mut buffer) {
+                    if from == remote && incoming.send(buffer[..len].into()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        let sender = self.0;
+        pool.spawn(async move {
+            while let Ok(message) = transport.recv().await {
+                // udp may lose messages anyway, backroll sends them again
+                let _ = sender.send_to(&message, remote);
+            }
+        })
+        .detach();
+        Ok(peer)
+    }
+}
+
+// LoopbackPeer is one of two complete sessions running in the same process, without a window
+pub struct LoopbackPeer {
+    session: P2PSession<BackrollConfig>,
+    pub game: Game,
+    local_handle: PlayerHandle,
+}
+
+impl LoopbackPeer {
+    // plays `local_player` of two over `socket`, the other player is at `remote`
+    // `latency` is added to every message in both directions
+    pub fn start(
+        pool: &TaskPool,
+        socket: LoopbackSocket,
+        remote: SocketAddr,
+        local_player: usize,
+        latency: Duration,
+    ) -> Result<Self, Error> {
+        let mut builder = P2PSession::<BackrollConfig>::build();
+        let mut local_handle = None;
+        let mut socket = Some(socket);
+        for player in 0..2 {
+            if player == local_player {
+                local_handle = Some(builder.add_player(Player::Local));
+            } else {
+                let mut peer = socket
+                    .take()
+                    .expect("there is only one remote player")
+                    .connect(pool, remote)?;
+                if !latency.is_zero() {
+                    let conditions = NetworkConditions {
+                        latency,
+                        ..Default::default()
+                    };
+                    peer = simulate_network(peer, conditions, local_player as u64);
+                }
+                builder.add_player(Player::Remote(peer));
+            }
+        }
+        let tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        Ok(Self {
+            session: builder.start(pool.clone())?,
+            game: Game::new(2, tuning, 0),
+            local_handle: local_handle.expect("the local player was added"),
+        })
+    }
+
+    // does what an iteration of the main loop does, without rendering
+    // `buttons_pressed` is asked for the buttons of the next frame
+    pub fn step(&mut self, buttons_pressed: impl FnOnce(Frame) -> u8) {
+        // peers step in turns without a clock, so there is no time to sync
+        self.game.handle_commands(self.session.poll());
+        let buttons_pressed = buttons_pressed(self.game.frame() + 1);
+        let input = PlayerInput { buttons_pressed };
+        // fails until the peers are synchronized
+        let _ = advance_session(
+            &mut self.game,
+            &self.session,
+            &[self.local_handle],
+            &[input],
+        );
+    }
+}
+
+// two peers in the same process, connected over a pair of fresh loopback sockets
+pub fn loopback_pair(pool: &TaskPool, latency: Duration) -> Result<[LoopbackPeer; 2], Error> {
+    let sockets = [LoopbackSocket::bind()?, LoopbackSocket::bind()?];
+    let addrs = [sockets[0].addr()?, sockets[1].addr()?];
+    let [first, second] = sockets;
+    Ok([
+        LoopbackPeer::start(pool, first, addrs[1], 0, latency)?,
+        LoopbackPeer::start(pool, second, addrs[0], 1, latency)?,
+    ])
+}
+
+// plays random inputs on two sessions over loopback until their periodic checksums differ,
+// or `frames` are reached, then writes the state of both peers to `desync_<frame>_p<n>.json` in `dump_dir`
+pub fn fuzz(
+    seed: u64,
+    latency: Duration,
+    frames: Option<Frame>,
+    dump_dir: &Path,
+) -> Result<(), Error> {
+    let pool = TaskPool::new();
+    let mut peers = loopback_pair(&pool, latency)?;
+    for peer in peers.iter_mut() {
+        peer.game.set_dump_dir(dump_dir.to_owned());
+    }
+    let mut rngs = [Rng::new(seed), Rng::new(seed.wrapping_add(1))];
+    let mut buttons = [0u8; 2];
+    let mut last_compared = 0;
+    let start = Instant::now();
+
+    loop {
+        for (i, peer) in peers.iter_mut().enumerate() {
+            let (rng, buttons) = (&mut rngs[i], &mut buttons[i]);
+            peer.step(|_| {
+                if rng.next_u32() % FUZZ_INPUT_CHANGE == 0 {
+                    *buttons = (rng.next_u32() % 16) as u8;
+                }
+                *buttons
+            });
+        }
+
+        // periodic checksums can only be compared once their frame can't be rolled back anymore
+        let (first, second) = (&peers[0].game, &peers[1].game);
+        let (frame, checksum) = first.periodic_checksum();
+        let confirmed = first.frame().min(second.frame()) - PREDICTION_WINDOW as Frame;
+        if frame > last_compared && frame == second.periodic_checksum().0 && frame <= confirmed {
+            last_compared = frame;
+            if checksum != second.periodic_checksum().1 {
+                for (i, peer) in peers.iter().enumerate() {
+                    peer.game
+                        .write_periodic_dump(&format!("desync_{}_p{}.json", frame, i + 1));
+                }
+                let cause = if first.periodic_input_hash() == second.periodic_input_hash() {
+                    "the inputs agree, so the simulation diverged"
+                } else {
+                    "the peers disagree on the inputs"
+                };
+                return Err(Error::Desync { frame, cause });
+            }
+            println!(
+                "frame {}: checksums match, {:.0}s elapsed",
+                frame,
+                start.elapsed().as_secs_f32()
+            );
+            if frames.is_some_and(|frames| frame >= frames) {
+                return Ok(());
+            }
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// plays `frames` on two sessions over loopback in real time, with `latency` added to every
+// message and bots steering both ships, then reports the frame times and the simulation profile
+// of both peers
+// unlike `bench`, it measures the whole stack, so it shows what build settings do to a real match
+pub fn bench_netplay(frames: Frame, latency: Duration) -> Result<(), Error> {
+    let pool = TaskPool::new();
+    let mut peers = loopback_pair(&pool, latency)?;
+    // every peer ticks on its own clock, like it would on its own machine
+    let mut steps = [FixedStep::default(), FixedStep::default()];
+    let mut frame_times: [Vec<Duration>; 2] = Default::default();
+
+    // the other peer may still need our last inputs, so both keep going until both are done
+    while peers.iter().any(|peer| peer.game.frame() < frames) {
+        for (i, peer) in peers.iter_mut().enumerate() {
+            steps[i].accumulate();
+            while steps[i].tick(peer.game.tick_duration()) {
+                peer.game.tick();
+                // both ships are connected, so the bots chase each other
+                let buttons = peer.game.state().bot_input(i, &[Some(0); 2]);
+                let start = Instant::now();
+                peer.step(|_| buttons);
+                frame_times[i].push(start.elapsed());
+            }
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    for (i, (peer, frame_times)) in peers.iter().zip(frame_times.iter_mut()).enumerate() {
+        if frame_times.is_empty() {
+            continue;
+        }
+        frame_times.sort();
+        let percentile = |p: usize| frame_times[(frame_times.len() - 1) * p / 100];
+        let total: Duration = frame_times.iter().sum();
+        let average = total
+            .checked_div(frame_times.len() as u32)
+            .unwrap_or_default();
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        println!("Peer {}, {} frames", i + 1, peer.game.frame());
+        println!(
+            "Frame time: {:.3} ms avg, {:.3} ms p50, {:.3} ms p99, {:.3} ms max",
+            ms(average),
+            ms(percentile(50)),
+            ms(percentile(99)),
+            ms(percentile(100))
+        );
+        peer.game.print_profile();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{scripted_input, CHECKSUM_PERIOD};
+    use std::{
+        collections::BTreeMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    const FRAMES: Frame = 1000;
+    const TIMEOUT: Duration = Duration::from_secs(60);
+    const THREADED_FRAMES: Frame = 5 * CHECKSUM_PERIOD;
+    // the threaded peers keep still for this many frames at the end, backroll predicts the inputs
+    // it hasn't received yet to stay the same, so the end states can't be mispredicted
+    const IDLE_FRAMES: Frame = 2 * PREDICTION_WINDOW as Frame;
+
+    #[test]
+    fn sessions_stay_in_sync() {
+        let pool = TaskPool::new();
+        let mut peers = loopback_pair(&pool, Duration::ZERO).unwrap();
+        // the last periodic checksum seen for each frame, rollbacks overwrite mispredicted ones
+        let mut checksums = [BTreeMap::new(), BTreeMap::new()];
+
+        let start = Instant::now();
+        while peers.iter().any(|peer| peer.game.frame() < FRAMES) {
+            assert!(
+                start.elapsed() < TIMEOUT,
+                "sessions didn't reach frame {}",
+                FRAMES
+            );
+            for (i, peer) in peers.iter_mut().enumerate() {
+                if peer.game.frame() < FRAMES {
+                    peer.step(|frame| scripted_input(frame, i, FRAMES).unwrap_or(0));
+                }
+                let (frame, checksum) = peer.game.periodic_checksum();
+                checksums[i].insert(frame, checksum);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // the checksums of the last frames might still be based on mispredicted inputs
+        let confirmed = FRAMES - PREDICTION_WINDOW as Frame;
+        let [first, second] = checksums.map(|checksums| {
+            checksums
+                .range(1..confirmed)
+                .map(|(frame, checksum)| (*frame, *checksum))
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(first.len(), (confirmed / CHECKSUM_PERIOD) as usize);
+        assert_eq!(first, second, "periodic checksums differ");
+    }
+
+    // like a real match, every peer runs its session on its own thread and only the network
+    // connects them
+    #[test]
+    fn threaded_sessions_end_in_identical_states() {
+        let pool = TaskPool::new();
+        let finished = Arc::new(AtomicUsize::new(0));
+        let sockets = [
+            LoopbackSocket::bind().unwrap(),
+            LoopbackSocket::bind().unwrap(),
+        ];
+        let addrs = [sockets[0].addr().unwrap(), sockets[1].addr().unwrap()];
+        let threads: Vec<_> = sockets
+            .into_iter()
+            .enumerate()
+            .map(|(player, socket)| {
+                let (pool, finished) = (pool.clone(), finished.clone());
+                let remote = addrs[1 - player];
+                thread::spawn(move || {
+                    let mut peer =
+                        LoopbackPeer::start(&pool, socket, remote, player, Duration::ZERO).unwrap();
+                    let start = Instant::now();
+                    while peer.game.frame() < THREADED_FRAMES {
+                        assert!(start.elapsed() < TIMEOUT, "peer {} got stuck", player);
+                        peer.step(|frame| match frame <= THREADED_FRAMES - IDLE_FRAMES {
+                            true => scripted_input(frame, player, THREADED_FRAMES).unwrap_or(0),
+                            false => 0,
+                        });
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    // the other peer may still need our last inputs
+                    finished.fetch_add(1, Ordering::SeqCst);
+                    while finished.load(Ordering::SeqCst) < addrs.len() {
+                        assert!(start.elapsed() < TIMEOUT, "the other peer got stuck");
+                        peer.game.handle_commands(peer.session.poll());
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    let state = bincode::serialize(peer.game.state()).unwrap();
+                    (peer.game.last_confirmed(), state)
+                })
+            })
+            .collect();
+        let ends: Vec<_> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        // both stopped on the same frame, with the same inputs confirmed up to the same frame
+        let (confirmed, _) = ends[0].0.unwrap();
+        assert_eq!(confirmed, THREADED_FRAMES - PREDICTION_WINDOW as Frame);
+        assert_eq!(ends[0].0, ends[1].0, "the confirmed inputs differ");
+        assert!(ends[0].1 == ends[1].1, "the end states differ");
+    }
+}
@@ -0,0 +1,70 @@
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use crate::Error;
+
+pub const PEER_ID_LEN: usize = 16;
+
+// PeerId tells installations of the game apart across sessions, it is picked at random on the
+// first launch and sent to the other peers in the handshake
+// unlike addresses, it stays the same when a player moves to another network
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct PeerId(pub [u8; PEER_ID_LEN]);
+
+impl PeerId {
+    // reads the id of this installation, or picks one and writes it if there is none yet
+    pub fn load_or_create(path: &Path) -> Result<Self, Error> {
+        if path.exists() {
+            let id = fs::read_to_string(path).map_err(Error::read(path))?;
+            return id.trim().parse().map_err(Error::corrupt(path, "peer id"));
+        }
+        let mut id = [0; PEER_ID_LEN];
+        OsRng.fill_bytes(&mut id);
+        let id = PeerId(id);
+        fs::write(path, id.to_string()).map_err(Error::write(path))?;
+        Ok(id)
+    }
+
+    // the first characters, enough to tell the players someone met apart
+    pub fn short(&self) -> String {
+        self.to_string()[..8].to_owned()
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl FromStr for PeerId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid peer id: {}", s);
+        if s.len() != 2 * PEER_ID_LEN || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let mut id = [0; PEER_ID_LEN];
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(PeerId(id))
+    }
+}
+
+impl From<PeerId> for String {
+    fn from(id: PeerId) -> Self {
+        id.to_string()
+    }
+}
+
+impl TryFrom<String> for PeerId {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
@@ -0,0 +1,78 @@
+use serde::Serialize;
+use std::{collections::VecDeque, fs::File, io::BufWriter, path::Path};
+
+use crate::{
+    game::{Frame, FrameInputs, FPS},
+    Error,
+};
+
+// ten minutes of play at the default tick rate, older frames are dropped
+pub const INPUT_LOG_FRAMES: usize = 10 * 60 * FPS as usize;
+
+// InputLog keeps the confirmed inputs of all players for the last frames of the session, so a
+// netcode bug can be reported with the exact inputs that led up to it
+// unlike replays, it holds no states and works without recording, it is only written on demand
+pub struct InputLog {
+    capacity: usize,
+    frames: VecDeque<FrameInputs>,
+}
+
+// ExportedInputs is the file written by `InputLog::export`
+#[derive(Serialize)]
+struct ExportedInputs<'a> {
+    // `None` if nothing was confirmed yet
+    first_frame: Option<Frame>,
+    frames: Vec<&'a FrameInputs>,
+}
+
+impl InputLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            frames: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, confirmed: FrameInputs) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(confirmed);
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = &FrameInputs> {
+        self.frames.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub fn export(&self, path: &Path) -> Result<(), Error> {
+        let exported = ExportedInputs {
+            first_frame: self.frames.front().map(|frame| frame.frame),
+            frames: self.frames().collect(),
+        };
+        let file = File::create(path).map_err(Error::write(path))?;
+        serde_json::to_writer(BufWriter::new(file), &exported).map_err(Error::encode("input log"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_frames() {
+        let mut log = InputLog::new(3);
+        for frame in 1..=5 {
+            log.record(FrameInputs {
+                frame,
+                buttons_pressed: vec![Some(frame as u8), None],
+                input_hash: 0,
+            });
+        }
+        let frames: Vec<_> = log.frames().map(|inputs| inputs.frame).collect();
+        assert_eq!(frames, [3, 4, 5]);
+    }
+}
@@ -0,0 +1,123 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::{
+    game::{Frame, INPUT_DOWN, INPUT_LEFT, INPUT_RIGHT, INPUT_TOGGLE_THRUST, INPUT_UP},
+    vote::{VoteAction, INPUT_VOTE_MASK},
+    Error,
+};
+
+// the letters used for each button in script files
+const BUTTON_LETTERS: [(u8, char); 5] = [
+    (INPUT_UP, 'U'),
+    (INPUT_DOWN, 'D'),
+    (INPUT_LEFT, 'L'),
+    (INPUT_RIGHT, 'R'),
+    (INPUT_TOGGLE_THRUST, 'T'),
+];
+// vote actions share the reserved vote bits, so at most one of them can be pressed
+const VOTE_LETTERS: [(VoteAction, char); 6] = [
+    (VoteAction::Yes, 'Y'),
+    (VoteAction::No, 'N'),
+    (VoteAction::CallKick, 'K'),
+    (VoteAction::CallSkipMap, 'S'),
+    (VoteAction::CallEndMatch, 'E'),
+    (VoteAction::Rematch, 'M'),
+];
+
+// InputScript holds the buttons of the local players for each frame
+// scripts are plain text, so they can be written and edited by hand: every line holds a frame
+// followed by the buttons of each local player, e.g. `120 UL -` or `# a comment`
+#[derive(Default)]
+pub struct InputScript {
+    frames: BTreeMap<Frame, Vec<u8>>,
+}
+
+impl InputScript {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut script = Self::default();
+        let text = fs::read_to_string(path).map_err(Error::read(path))?;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| Error::InvalidLine {
+                kind: "input script",
+                number: number + 1,
+                line: line.to_owned(),
+                reason: reason.to_owned(),
+            };
+            let mut fields = line.split_whitespace();
+            let frame: Frame = fields
+                .next()
+                .and_then(|frame| frame.parse().ok())
+                .ok_or_else(|| invalid("it has to start with a frame"))?;
+            let buttons = fields
+                .map(|field| parse_buttons(field).ok_or_else(|| invalid("unknown button")))
+                .collect::<Result<_, _>>()?;
+            script.frames.insert(frame, buttons);
+        }
+        Ok(script)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut text = String::new();
+        for (frame, buttons) in &self.frames {
+            text.push_str(&frame.to_string());
+            for buttons_pressed in buttons {
+                text.push(' ');
+                text.push_str(&format_buttons(*buttons_pressed));
+            }
+            text.push('\n');
+        }
+        fs::write(path, text).map_err(Error::write(path))
+    }
+
+    // records the buttons of all local players for `frame`
+    pub fn record(&mut self, frame: Frame, buttons: Vec<u8>) {
+        self.frames.insert(frame, buttons);
+    }
+
+    // returns the scripted buttons of a local player, `None` once the script is over
+    // frames left out in the middle of a script have no buttons pressed
+    pub fn buttons(&self, frame: Frame, local_index: usize) -> Option<u8> {
+        let (last_frame, _) = self.frames.iter().next_back()?;
+        if frame > *last_frame {
+            return None;
+        }
+        let buttons = self.frames.get(&frame).and_then(|b| b.get(local_index));
+        Some(buttons.copied().unwrap_or(0))
+    }
+}
+
+pub fn parse_buttons(field: &str) -> Option<u8> {
+    if field == "-" {
+        return Some(0);
+    }
+    field.chars().try_fold(0, |buttons_pressed, c| {
+        if let Some((action, _)) = VOTE_LETTERS.iter().find(|(_, letter)| *letter == c) {
+            if buttons_pressed & INPUT_VOTE_MASK != 0 {
+                return None;
+            }
+            return Some(buttons_pressed | action.input());
+        }
+        let (button, _) = BUTTON_LETTERS.iter().find(|(_, letter)| *letter == c)?;
+        Some(buttons_pressed | button)
+    })
+}
+
+fn format_buttons(buttons_pressed: u8) -> String {
+    if buttons_pressed == 0 {
+        return "-".to_owned();
+    }
+    let vote_letter = VoteAction::from_input(buttons_pressed).and_then(|action| {
+        let (_, letter) = VOTE_LETTERS.iter().find(|(a, _)| *a == action)?;
+        Some(*letter)
+    });
+    BUTTON_LETTERS
+        .iter()
+        .filter(|(button, _)| buttons_pressed & button != 0)
+        .map(|(_, letter)| *letter)
+        .chain(vote_letter)
+        .collect()
+}
@@ -0,0 +1,58 @@
+// the game, the netcode around it and all modes of the game, the binary on top only parses the
+// command line and starts one of them
+pub mod arena;
+pub mod bench;
+pub mod block_list;
+pub mod color;
+pub mod compression;
+pub mod config;
+pub mod debug_panel;
+pub mod debugger;
+pub mod encryption;
+pub mod error;
+pub mod frame_delay;
+pub mod frame_graph;
+pub mod game;
+pub mod handshake;
+pub mod harness;
+pub mod identity;
+pub mod input_log;
+pub mod input_script;
+pub mod menu;
+pub mod meteor;
+pub mod metrics;
+pub mod netplay;
+#[cfg(unix)]
+pub mod observer;
+pub mod opponents;
+pub mod pacing;
+pub mod paths;
+pub mod platformer;
+pub mod pong;
+pub mod port_mapping;
+pub mod practice;
+pub mod profile;
+pub mod replay;
+pub mod rng;
+pub mod rollback_game;
+pub mod rollback_stats;
+pub mod scenario;
+pub mod setup;
+pub mod slow_motion;
+#[cfg(unix)]
+pub mod snapshot;
+pub mod state_diff;
+pub mod synctest;
+pub mod toast;
+pub mod transport;
+pub mod update;
+pub mod upload;
+pub mod vote;
+pub mod win_condition;
+
+pub use error::Error;
+use game::Game;
+use rollback_game::RollbackConfig;
+
+// BackrollConfig is the config of sessions of the box game
+pub type BackrollConfig = RollbackConfig<Game>;
@@ -1,31 +1,144 @@
-mod game;
-
-use backroll::*;
-use backroll_transport_udp::{UdpConnectionConfig, UdpManager};
-use bevy_tasks::TaskPool;
-use game::{Game, GameState, PlayerInput, FPS};
+#[cfg(unix)]
+use backroll_test::observer;
+use backroll_test::{
+    bench,
+    block_list::{self, BlockList},
+    config,
+    game::{self, Frame},
+    harness,
+    netplay::{self, GameOpt, PlayOpt},
+    opponents,
+    paths::Paths,
+    practice, replay, scenario, setup, state_diff, synctest, Error,
+};
 use macroquad::prelude::*;
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    time::{Duration, Instant},
+    path::{Path, PathBuf},
+    time::Duration,
 };
 use structopt::StructOpt;
 
+// Cli holds the options shared by all subcommands
 #[derive(StructOpt)]
-struct Opt {
-    #[structopt(short, long)]
-    local_port: u16,
-    #[structopt(short, long)]
-    players: Vec<String>,
+struct Cli {
+    // keeps replays and dumps next to the executable instead of the platform's data directory
+    #[structopt(long, global = true)]
+    portable: bool,
+    #[structopt(subcommand)]
+    command: Opt,
 }
 
-pub struct BackrollConfig;
-
-impl Config for BackrollConfig {
-    type Input = PlayerInput;
-    type State = GameState;
+#[derive(StructOpt)]
+enum Opt {
+    /// plays a match with local and remote players, runs the setup first on the first launch
+    Play(Box<PlayOpt>),
+    /// asks for your name and controls and checks the connectivity, then writes the config
+    Setup,
+    /// renders only the HUD of a match streamed to an observer socket (unix only),
+    /// on a chroma key background for streaming software
+    Spectate {
+        #[structopt(parse(from_os_str))]
+        observer: PathBuf,
+        /// shows the match this many frames behind, which hides hiccups of the stream and keeps
+        /// viewers from relaying what happens live
+        #[structopt(long, default_value = "0")]
+        spectator_delay: Frame,
+        /// percentage the playback speeds up by at most when it falls behind, 0 to never speed up
+        #[structopt(long, default_value = "10")]
+        spectator_catchup: f32,
+    },
+    /// plays back a replay file
+    Replay {
+        #[structopt(parse(from_os_str))]
+        replay: PathBuf,
+    },
+    /// races against the first ship of a replay offline, it is shown as a ghost
+    Race {
+        #[structopt(parse(from_os_str))]
+        replay: PathBuf,
+    },
+    /// plays offline against a ghost ship looping inputs you recorded with R
+    Practice {
+        /// input script the ghost loops, the last recorded loop is written back to it on exit
+        #[structopt(long, parse(from_os_str))]
+        script: Option<PathBuf>,
+        #[structopt(flatten)]
+        game: GameOpt,
+    },
+    /// simulates headlessly with a rollback on every frame and checks that nothing changes
+    Synctest {
+        #[structopt(long, default_value = "4")]
+        players: usize,
+        #[structopt(long, default_value = "3000")]
+        frames: Frame,
+        /// number of frames rolled back on every frame
+        #[structopt(long, default_value = "8")]
+        check_distance: usize,
+        #[structopt(flatten)]
+        game: GameOpt,
+    },
+    /// measures advancing, saving, checksumming and serializing game states with criterion,
+    /// build with `--features rkyv` to include rkyv
+    Bench {
+        /// only runs the benchmarks whose name matches this regex, e.g. `advance`
+        filter: Option<String>,
+        /// compares with a baseline saved earlier and reports what got slower
+        #[structopt(long)]
+        baseline: Option<String>,
+        /// saves the results as a baseline to compare later runs with
+        #[structopt(long)]
+        save_baseline: Option<String>,
+    },
+    /// plays two bot-driven sessions over loopback in real time and reports their frame times and
+    /// rollback costs, to compare build profiles on a whole match
+    BenchNetplay {
+        #[structopt(long, default_value = "1800")]
+        frames: Frame,
+        /// milliseconds added to every message in both directions
+        #[structopt(long, default_value = "80")]
+        latency: u64,
+    },
+    /// lists the players you played against, most recent first
+    Opponents,
+    /// mutes an opponent in every future session, by the start of the id `opponents` lists
+    Block { id: String },
+    /// takes an opponent off the block list again
+    Unblock { id: String },
+    /// writes the part of a replay between two frames into a new, smaller replay
+    Trim {
+        #[structopt(parse(from_os_str))]
+        replay: PathBuf,
+        start_frame: Frame,
+        end_frame: Frame,
+        /// defaults to the replay path with the frame range appended
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// runs scenario files headlessly and reports every expectation that isn't met
+    Scenario {
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// prints every value that differs between two desync dumps or serialized game states,
+    /// relative paths that don't exist are looked up in the dumps directory
+    Diff {
+        #[structopt(parse(from_os_str))]
+        left: PathBuf,
+        #[structopt(parse(from_os_str))]
+        right: PathBuf,
+    },
+    /// plays random inputs on two sessions over loopback until their checksums differ
+    Fuzz {
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+        /// milliseconds added to every message, to provoke longer rollbacks
+        #[structopt(long, default_value = "0")]
+        latency: u64,
+        /// stops after this many frames instead of running until a desync
+        #[structopt(long)]
+        frames: Option<Frame>,
+    },
 }
-
 /// returns a window config for macroquad to use
 fn window_conf() -> Conf {
     Conf {
@@ -39,72 +152,137 @@ fn window_conf() -> Conf {
 }
 
 #[macroquad::main(window_conf)]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // bevy task pool
-    let pool = TaskPool::new();
-
-    // read cmd line arguments
-    let opt = Opt::from_args();
-    let mut local_handle = PlayerHandle(0);
-    let num_players = opt.players.len();
-    assert!(num_players > 0);
-
-    // udp socket
-    let listen_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), opt.local_port);
-    let socket = UdpManager::bind(pool.clone(), listen_addr)?;
-
-    // create a backroll session
-    let mut sess_builder = P2PSession::<BackrollConfig>::build().with_frame_delay(0);
-
-    // add players
-    for player_addr in opt.players.iter() {
-        // local player
-        if player_addr == "localhost" {
-            local_handle = sess_builder.add_player(Player::Local);
-        } else {
-            // remote players
-            let peer = socket.connect(UdpConnectionConfig::unbounded(player_addr.parse()?));
-            sess_builder.add_player(Player::Remote(peer));
-        }
+async fn main() {
+    if let Err(e) = run(Cli::from_args()).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
+}
 
-    let sess = sess_builder.start(pool)?;
-
-    // Create a new box game
-    let mut game = Game::new(num_players);
-
-    // time variables for tick rate
-    let mut last_update = Instant::now();
-    let mut accumulator = Duration::ZERO;
-    let fps_delta = 1. / FPS;
-
-    loop {
-        game.handle_commands(sess.poll());
-        // get delta time from last iteration and accumulate it
-        let delta = Instant::now().duration_since(last_update);
-        accumulator = accumulator.saturating_add(delta);
-        last_update = Instant::now();
-
-        // if enough time is accumulated, we run a frame
-        while accumulator.as_secs_f32() > fps_delta {
-            // decrease accumulator
-            accumulator = accumulator.saturating_sub(Duration::from_secs_f32(fps_delta));
-
-            if game.should_wait() {
-                game.wait()
-            } else {
-                let local_input = game.local_input(local_handle);
-                match sess.add_local_input(local_handle, local_input) {
-                    Ok(_) => {
-                        let cmds = sess.advance_frame();
-                        game.handle_commands(cmds);
-                    }
-                    Err(e) => println!("{e}"),
+async fn run(cli: Cli) -> Result<(), Error> {
+    let paths = Paths::new(cli.portable)?;
+    match cli.command {
+        Opt::Play(opt) => {
+            let config_path = paths.config()?;
+            let config = match config::Config::load(&config_path)? {
+                Some(config) => config,
+                None => setup::run(&config_path)?,
+            };
+            warn_on_failed_self_test();
+            netplay::play(*opt, &paths, &config).await
+        }
+        Opt::Setup => setup::run(&paths.config()?).map(|_| ()),
+        Opt::Spectate {
+            observer,
+            spectator_delay,
+            spectator_catchup,
+        } => spectate(&observer, spectator_delay, spectator_catchup / 100.0).await,
+        Opt::Replay { replay } => {
+            warn_on_failed_self_test();
+            replay::play(&paths.find_replay(&replay)).await
+        }
+        Opt::Race { replay } => {
+            warn_on_failed_self_test();
+            replay::race(&paths.find_replay(&replay)).await
+        }
+        Opt::Practice { script, game } => {
+            let config = config::Config::load(&paths.config()?)?;
+            practice::run(game.state(2), config, script.as_deref()).await
+        }
+        Opt::Synctest {
+            players,
+            frames,
+            check_distance,
+            game,
+        } => synctest::run(game.state(players), frames, check_distance),
+        Opt::Opponents => {
+            let block_list = BlockList::load(&paths.block_list()?)?;
+            opponents::print(&paths.opponents()?, &block_list)
+        }
+        Opt::Block { id } => {
+            block_list::set_blocked(&paths.block_list()?, &paths.opponents()?, &id, true)
+        }
+        Opt::Unblock { id } => {
+            block_list::set_blocked(&paths.block_list()?, &paths.opponents()?, &id, false)
+        }
+        Opt::Bench {
+            filter,
+            baseline,
+            save_baseline,
+        } => {
+            bench::run(filter, baseline, save_baseline);
+            Ok(())
+        }
+        Opt::BenchNetplay { frames, latency } => {
+            harness::bench_netplay(frames, Duration::from_millis(latency))
+        }
+        Opt::Trim {
+            replay,
+            start_frame,
+            end_frame,
+            output,
+        } => {
+            let replay = paths.find_replay(&replay);
+            let output = match output {
+                Some(output) => paths.replay_to_write(&output)?,
+                None => {
+                    let mut output = replay.clone().into_os_string();
+                    output.push(format!(".{}-{}", start_frame, end_frame));
+                    output.into()
+                }
+            };
+            replay::trim(&replay, start_frame, end_frame, &output)
+        }
+        Opt::Scenario { paths } => {
+            let mut failed = 0;
+            for path in &paths {
+                let failures = scenario::Scenario::load(path)?.run();
+                let result = if failures.is_empty() { "ok" } else { "FAILED" };
+                println!("{}: {}", path.display(), result);
+                for failure in &failures {
+                    println!("    {}", failure);
                 }
+                failed += !failures.is_empty() as usize;
             }
+            if failed > 0 {
+                return Err(Error::ScenariosFailed {
+                    failed,
+                    total: paths.len(),
+                });
+            }
+            Ok(())
+        }
+        Opt::Diff { left, right } => {
+            state_diff::run(&paths.find_dump(&left), &paths.find_dump(&right))?;
+            Ok(())
         }
+        Opt::Fuzz {
+            seed,
+            latency,
+            frames,
+        } => harness::fuzz(
+            seed,
+            Duration::from_millis(latency),
+            frames,
+            &paths.dumps()?,
+        ),
+    }
+}
 
-        game.render();
-        next_frame().await;
+// a build simulating differently than everyone else's would desync right away
+fn warn_on_failed_self_test() {
+    if let Err(e) = game::determinism_self_test() {
+        eprintln!("!!! DETERMINISM SELF TEST FAILED: {} !!!", e);
+        eprintln!("!!! this build will desync from other peers, check the compiler flags !!!");
     }
 }
+
+#[cfg(unix)]
+async fn spectate(path: &Path, delay: Frame, catch_up: f32) -> Result<(), Error> {
+    observer::run_overlay(path, delay, catch_up).await
+}
+
+#[cfg(not(unix))]
+async fn spectate(_path: &Path, _delay: Frame, _catch_up: f32) -> Result<(), Error> {
+    Err(Error::Unsupported("spectating"))
+}
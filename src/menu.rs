@@ -0,0 +1,160 @@
+use macroquad::prelude::*;
+
+use crate::{
+    config::ControlScheme,
+    game::{WINDOW_HEIGHT, WINDOW_WIDTH},
+};
+
+const ITEM_HEIGHT: f32 = 36.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MenuItem {
+    Resume,
+    Controls,
+    // mutes or unmutes a remote player
+    Mute(usize),
+    Leave,
+}
+
+// MenuAction is what the player picked in the menu
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MenuAction {
+    NextControls,
+    ToggleMute(usize),
+    Leave,
+}
+
+// Menu is opened with escape during a session
+// it only covers the game, the caller keeps polling and advancing the session while it is open so
+// the other peers don't see this player as disconnected, the local players just stand still
+#[derive(Default)]
+pub struct Menu {
+    open: bool,
+    selected: usize,
+    remote_players: Vec<usize>,
+}
+
+impl Menu {
+    // `remote_players` can be muted from the menu
+    pub fn new(remote_players: Vec<usize>) -> Self {
+        Self {
+            remote_players,
+            ..Default::default()
+        }
+    }
+
+    fn items(&self) -> Vec<MenuItem> {
+        let mut items = vec![MenuItem::Resume, MenuItem::Controls];
+        items.extend(
+            self.remote_players
+                .iter()
+                .map(|player| MenuItem::Mute(*player)),
+        );
+        items.push(MenuItem::Leave);
+        items
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn update(&mut self) -> Option<MenuAction> {
+        let mut action = None;
+        for key in [KeyCode::Escape, KeyCode::Up, KeyCode::Down, KeyCode::Enter] {
+            if is_key_pressed(key) {
+                action = action.or(self.press(key));
+            }
+        }
+        action
+    }
+
+    fn press(&mut self, key: KeyCode) -> Option<MenuAction> {
+        if key == KeyCode::Escape {
+            self.open = !self.open;
+            self.selected = 0;
+            return None;
+        }
+        if !self.open {
+            return None;
+        }
+        let items = self.items();
+        match key {
+            KeyCode::Up => self.selected = (self.selected + items.len() - 1) % items.len(),
+            KeyCode::Down => self.selected = (self.selected + 1) % items.len(),
+            KeyCode::Enter => match items[self.selected] {
+                MenuItem::Resume => self.open = false,
+                MenuItem::Controls => return Some(MenuAction::NextControls),
+                MenuItem::Mute(player) => return Some(MenuAction::ToggleMute(player)),
+                MenuItem::Leave => return Some(MenuAction::Leave),
+            },
+            _ => {}
+        }
+        None
+    }
+
+    // `muted` are the remote players that are muted right now
+    pub fn draw(&self, controls: ControlScheme, muted: &[usize]) {
+        if !self.open {
+            return;
+        }
+        draw_rectangle(
+            0.0,
+            0.0,
+            WINDOW_WIDTH,
+            WINDOW_HEIGHT,
+            Color::new(0.0, 0.0, 0.0, 0.6),
+        );
+        let items = self.items();
+        let top = (WINDOW_HEIGHT - ITEM_HEIGHT * items.len() as f32) / 2.0;
+        for (i, item) in items.iter().enumerate() {
+            let text = match item {
+                MenuItem::Resume => "Resume".to_owned(),
+                MenuItem::Controls => format!("Controls: {:?}", controls),
+                MenuItem::Mute(player) if muted.contains(player) => {
+                    format!("Unmute P{}", player + 1)
+                }
+                MenuItem::Mute(player) => format!("Mute P{}", player + 1),
+                MenuItem::Leave => "Leave the match".to_owned(),
+            };
+            let color = if i == self.selected { YELLOW } else { WHITE };
+            let width = measure_text(&text, None, 30, 1.0).width;
+            let y = top + ITEM_HEIGHT * (i as f32 + 1.0);
+            draw_text(&text, (WINDOW_WIDTH - width) / 2.0, y, 30.0, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_only_navigate_while_open() {
+        let mut menu = Menu::default();
+        assert_eq!(menu.press(KeyCode::Enter), None);
+        assert_eq!(menu.press(KeyCode::Escape), None);
+        assert!(menu.is_open());
+        assert_eq!(menu.press(KeyCode::Down), None);
+        assert_eq!(menu.press(KeyCode::Enter), Some(MenuAction::NextControls));
+        assert_eq!(menu.press(KeyCode::Up), None);
+        assert_eq!(menu.press(KeyCode::Up), None);
+        assert_eq!(menu.press(KeyCode::Enter), Some(MenuAction::Leave));
+        assert_eq!(menu.press(KeyCode::Up), None);
+        assert_eq!(menu.press(KeyCode::Up), None);
+        assert_eq!(menu.press(KeyCode::Enter), None);
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn remote_players_can_be_muted() {
+        let mut menu = Menu::new(vec![1, 3]);
+        menu.press(KeyCode::Escape);
+        menu.press(KeyCode::Down);
+        menu.press(KeyCode::Down);
+        assert_eq!(menu.press(KeyCode::Enter), Some(MenuAction::ToggleMute(1)));
+        menu.press(KeyCode::Down);
+        assert_eq!(menu.press(KeyCode::Enter), Some(MenuAction::ToggleMute(3)));
+        menu.press(KeyCode::Down);
+        assert_eq!(menu.press(KeyCode::Enter), Some(MenuAction::Leave));
+    }
+}
@@ -0,0 +1,177 @@
+use bevy_tasks::TaskPool;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{Tuning, WINDOW_HEIGHT, WINDOW_WIDTH},
+    rng::Rng,
+};
+
+const MIN_RADIUS: f32 = 10.0;
+const MAX_RADIUS: f32 = 25.0;
+// in pixels per second
+const MIN_FALL_SPEED: f32 = 180.0;
+const MAX_FALL_SPEED: f32 = 420.0;
+const MAX_DRIFT: f32 = 120.0;
+
+// Meteor flies across the arena in a straight line and knocks away ships it hits
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct Meteor {
+    pub position: (f32, f32),
+    // in pixels per frame
+    pub velocity: (f32, f32),
+    pub radius: f32,
+}
+
+impl Meteor {
+    // spawns a meteor just above the arena, all values come from the shared `rng`
+    pub fn spawn(rng: &mut Rng, tuning: &Tuning) -> Self {
+        let radius = MIN_RADIUS + rng.next_f32() * (MAX_RADIUS - MIN_RADIUS);
+        let x = rng.next_f32() * WINDOW_WIDTH;
+        let vel_x = (rng.next_f32() * 2.0 - 1.0) * MAX_DRIFT;
+        let vel_y = MIN_FALL_SPEED + rng.next_f32() * (MAX_FALL_SPEED - MIN_FALL_SPEED);
+        Self {
+            position: (x, -radius),
+            velocity: (tuning.per_frame(vel_x), tuning.per_frame(vel_y)),
+            radius,
+        }
+    }
+
+    // moves the meteor, returns false once it left the arena
+    pub fn advance(&mut self) -> bool {
+        let (x, y) = self.position;
+        let (vel_x, vel_y) = self.velocity;
+        self.position = (x + vel_x, y + vel_y);
+        let (x, y) = self.position;
+        y - self.radius < WINDOW_HEIGHT && x + self.radius > 0.0 && x - self.radius < WINDOW_WIDTH
+    }
+
+    // whether the meteor touched a ship of `radius` that moved from `from` to `to` while the
+    // meteor moved from `start` to where it is now, anywhere along the way and not only where both
+    // ended up, fast meteors would otherwise pass through ships between two frames
+    // only uses basic arithmetic and a clamp, which IEEE 754 defines exactly, so all peers get the
+    // same result
+    pub fn hits(&self, start: (f32, f32), from: (f32, f32), to: (f32, f32), radius: f32) -> bool {
+        let (x, y) = self.position;
+        // the meteor relative to the ship, at the start of the frame and how it moved from there
+        let (start_x, start_y) = (start.0 - from.0, start.1 - from.1);
+        let (move_x, move_y) = (x - to.0 - start_x, y - to.1 - start_y);
+        // the closest the two got, clamped to the frame
+        let length_sq = move_x * move_x + move_y * move_y;
+        let t = match length_sq > 0.0 {
+            true => (-(start_x * move_x + start_y * move_y) / length_sq).clamp(0.0, 1.0),
+            false => 0.0,
+        };
+        let (dx, dy) = (start_x + move_x * t, start_y + move_y * t);
+        let distance = self.radius + radius;
+        dx * dx + dy * dy < distance * distance
+    }
+
+    pub fn draw(&self) {
+        let (x, y) = self.position;
+        draw_circle(x, y, self.radius, BROWN);
+    }
+}
+
+// moves all meteors in `slots` one after the other and clears the ones that left the arena
+pub fn advance_all(slots: &mut [Option<Meteor>]) {
+    for slot in slots.iter_mut() {
+        if slot.as_mut().is_some_and(|meteor| !meteor.advance()) {
+            *slot = None;
+        }
+    }
+}
+
+// like `advance_all`, but moves `chunk` meteors at a time on `pool`
+// the pool hands back the chunks in the order they were spawned in, no matter which of them
+// finished first, so the slots end up exactly like after `advance_all`
+// a shower holds at most `MAX_METEORS`, far too few to pay for spawning tasks on every frame, so
+// the simulation sticks to `advance_all`, `bench` measures both
+pub fn advance_parallel(pool: &TaskPool, slots: &mut [Option<Meteor>], chunk: usize) {
+    let advanced = pool.scope(|scope| {
+        for chunk in slots.chunks(chunk) {
+            scope.spawn(async move {
+                let mut chunk = chunk.to_vec();
+                advance_all(&mut chunk);
+                chunk
+            });
+        }
+    });
+    for (slot, advanced) in slots.iter_mut().zip(advanced.into_iter().flatten()) {
+        *slot = advanced;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{MovementModel, MAX_METEORS};
+
+    #[test]
+    fn parallel_meteors_advance_like_serial_ones() {
+        let pool = TaskPool::new();
+        let tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        let mut rng = Rng::new(316);
+        let mut slots = [None; MAX_METEORS];
+        for frame in 0..10_000 {
+            // random slots fill up, so the chunks hold any mix of meteors and empty slots
+            for slot in slots.iter_mut() {
+                if slot.is_none() && rng.next_f32() < 0.3 {
+                    let mut meteor = Meteor::spawn(&mut rng, &tuning);
+                    meteor.position.1 = rng.next_f32() * WINDOW_HEIGHT;
+                    *slot = Some(meteor);
+                }
+            }
+            let chunk = 1 + rng.next_u32() as usize % MAX_METEORS;
+            let mut serial = slots;
+            advance_all(&mut serial);
+            advance_parallel(&pool, &mut slots, chunk);
+            assert_eq!(serial, slots, "diverged on frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn fast_meteors_cant_pass_through_ships() {
+        let ship = (300.0, 400.0);
+        // far faster than any meteor, it starts above the ship and ends below it
+        let meteor = |(x, y): (f32, f32), velocity: (f32, f32)| Meteor {
+            position: (x + velocity.0, y + velocity.1),
+            velocity,
+            radius: MIN_RADIUS,
+        };
+        let hits = |start, velocity, from, to| meteor(start, velocity).hits(start, from, to, 15.0);
+        assert!(hits((300.0, 100.0), (0.0, 10_000.0), ship, ship));
+        assert!(hits((-5_000.0, 390.0), (10_000.0, 0.0), ship, ship));
+        // passing beside the ship, or stopping short of it, misses
+        assert!(!hits((330.0, 100.0), (0.0, 10_000.0), ship, ship));
+        assert!(!hits((300.0, 100.0), (0.0, 250.0), ship, ship));
+
+        // a ship racing across the path of the meteor is hit too
+        let still = (0.0, 0.0);
+        assert!(hits(
+            (300.0, 100.0),
+            still,
+            (-5_000.0, 100.0),
+            (5_000.0, 100.0)
+        ));
+        assert!(!hits(
+            (300.0, 100.0),
+            still,
+            (-5_000.0, 200.0),
+            (5_000.0, 200.0)
+        ));
+
+        // a meteor that just spawned hasn't moved yet, it only sweeps from where it spawned
+        let spawned = Meteor {
+            position: (300.0, 100.0),
+            velocity: (0.0, 10_000.0),
+            radius: MIN_RADIUS,
+        };
+        assert!(!spawned.hits(spawned.position, (300.0, 0.0), (300.0, 0.0), 15.0));
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+// clients that don't send their request within this time are dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Values {
+    frame: i64,
+    rollbacks: u64,
+    frame_time: Duration,
+    pacing_jitter: Duration,
+    mirror_mismatches: u64,
+    // indexed by player handle, `None` for local and disconnected players
+    pings: Vec<Option<Duration>>,
+}
+
+// Metrics holds the session metrics served to Prometheus, updated by the game loop
+#[derive(Default)]
+pub struct Metrics {
+    values: Mutex<Values>,
+}
+
+impl Metrics {
+    // serves the metrics at `http://<host>:<port>/metrics` from a background thread
+    pub fn serve(port: u16) -> io::Result<Arc<Self>> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        let metrics = Arc::new(Self::default());
+        let served = Arc::clone(&metrics);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = served.respond(stream) {
+                    println!("Failed to serve metrics: {}", e);
+                }
+            }
+        });
+        Ok(metrics)
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Values)) {
+        if let Ok(mut values) = self.values.lock() {
+            update(&mut values);
+        }
+    }
+
+    pub fn set_frame(&self, frame: i32) {
+        self.update(|values| values.frame = frame as i64);
+    }
+
+    pub fn add_rollback(&self) {
+        self.update(|values| values.rollbacks += 1);
+    }
+
+    pub fn set_frame_time(&self, frame_time: Duration) {
+        self.update(|values| values.frame_time = frame_time);
+    }
+
+    pub fn set_pacing_jitter(&self, jitter: Duration) {
+        self.update(|values| values.pacing_jitter = jitter);
+    }
+
+    pub fn add_mirror_mismatch(&self) {
+        self.update(|values| values.mirror_mismatches += 1);
+    }
+
+    pub fn set_ping(&self, player: usize, ping: Option<Duration>) {
+        self.update(|values| {
+            if values.pings.len() <= player {
+                values.pings.resize(player + 1, None);
+            }
+            values.pings[player] = ping;
+        });
+    }
+
+    fn respond(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+        let response = match request_line.split_whitespace().nth(1) {
+            Some("/metrics") => {
+                let body = self.render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            }
+            _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_owned(),
+        };
+        (&stream).write_all(response.as_bytes())
+    }
+
+    // formats all metrics in the Prometheus text exposition format
+    fn render(&self) -> String {
+        let Ok(values) = self.values.lock() else {
+            return String::new();
+        };
+        let mut text = String::new();
+        write_metric(
+            &mut text,
+            "frame",
+            "gauge",
+            "The current simulation frame.",
+            [(String::new(), values.frame as f64)],
+        );
+        write_metric(
+            &mut text,
+            "rollbacks_total",
+            "counter",
+            "States loaded to correct mispredictions.",
+            [(String::new(), values.rollbacks as f64)],
+        );
+        write_metric(
+            &mut text,
+            "frame_time_seconds",
+            "gauge",
+            "Simulation and render time of the last rendered frame.",
+            [(String::new(), values.frame_time.as_secs_f64())],
+        );
+        write_metric(
+            &mut text,
+            "frame_pacing_jitter_seconds",
+            "gauge",
+            "Smoothed variation of the time between rendered frames.",
+            [(String::new(), values.pacing_jitter.as_secs_f64())],
+        );
+        write_metric(
+            &mut text,
+            "mirror_mismatches_total",
+            "counter",
+            "Frames where the mirrored simulation diverged.",
+            [(String::new(), values.mirror_mismatches as f64)],
+        );
+        let pings = values
+            .pings
+            .iter()
+            .enumerate()
+            .filter_map(|(player, ping)| {
+                let labels = format!("{{player=\"{}\"}}", player + 1);
+                ping.map(|ping| (labels, ping.as_secs_f64()))
+            });
+        write_metric(
+            &mut text,
+            "ping_seconds",
+            "gauge",
+            "Round trip time to a remote player.",
+            pings,
+        );
+        text
+    }
+}
+
+// writes a metric family, `samples` pairs the label set of every sample with its value
+fn write_metric(
+    text: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, f64)>,
+) {
+    let _ = writeln!(text, "# HELP box_game_{} {}", name, help);
+    let _ = writeln!(text, "# TYPE box_game_{} {}", name, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(text, "box_game_{}{} {}", name, labels, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_all_samples() {
+        let metrics = Metrics::default();
+        metrics.add_rollback();
+        metrics.add_rollback();
+        metrics.set_ping(1, Some(Duration::from_millis(40)));
+        let text = metrics.render();
+        assert!(text.contains("\nbox_game_rollbacks_total 2\n"));
+        assert!(text.contains("\nbox_game_ping_seconds{player=\"2\"} 0.04\n"));
+        // local players don't have a ping
+        assert!(!text.contains("player=\"1\""));
+    }
+}
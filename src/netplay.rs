@@ -0,0 +1,787 @@
+use backroll::*;
+use bevy_tasks::TaskPool;
+use macroquad::prelude::*;
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+
+#[cfg(unix)]
+use crate::observer;
+use crate::{
+    arena::MapRotation,
+    block_list::BlockList,
+    color::PlayerColor,
+    compression, config, debugger,
+    encryption::SessionKey,
+    frame_delay::FrameDelayTuner,
+    game::{
+        self, Game, GameState, MovementModel, PlayerInput, Tuning, FPS, MAX_LOCAL_PLAYERS,
+        MAX_PLAYERS,
+    },
+    handshake::{self, SideChannel},
+    identity::PeerId,
+    input_script::InputScript,
+    menu::{Menu, MenuAction},
+    metrics, opponents, pacing,
+    paths::Paths,
+    platformer::Platformer,
+    pong::{Pong, PONG_PLAYERS},
+    port_mapping::PortMapping,
+    rng,
+    rollback_game::{self, advance_session, FixedStep, RollbackConfig, RollbackGame},
+    slow_motion::SlowMotion,
+    transport::{
+        self, bound_peer, simulate_network, Connections, DropStats, NetworkConditions, Transport,
+    },
+    update::UpdateCheck,
+    upload::{self, Endpoint},
+    win_condition::WinConditionId,
+    Error,
+};
+
+// GameOpt holds the gameplay rules, all peers have to pick the same ones
+#[derive(StructOpt)]
+pub struct GameOpt {
+    #[structopt(long, default_value = "inertia", possible_values = &["inertia", "tank"])]
+    movement_model: MovementModel,
+    // scales the ship size
+    #[structopt(long, default_value = "1.0", parse(try_from_str = game::parse_ship_scale))]
+    ship_scale: f32,
+    // seeds all randomized gameplay, in a match the host, who plays player 1, picks a random one
+    // unless it is given and sends it to everyone else
+    #[structopt(long)]
+    seed: Option<u64>,
+    // arenas successive rounds are played in, e.g. `open,pillars,corridors`
+    #[structopt(long, default_value = "open")]
+    maps: MapRotation,
+    // seconds after which a round ends and the next arena of the rotation is played
+    #[structopt(long)]
+    round_length: Option<u32>,
+    // the match is over once a player won the majority of this many rounds
+    // players can vote for a rematch afterwards
+    #[structopt(long)]
+    best_of: Option<u32>,
+    // decides when a round is won
+    #[structopt(long, default_value = "endless", possible_values = &["endless", "score-limit", "last-ship-standing", "king-of-the-hill", "race", "hunt"])]
+    win_condition: WinConditionId,
+    // meteor showers periodically rain down on the arena
+    #[structopt(long)]
+    meteor_showers: bool,
+    // disconnected players are controlled by a bot
+    #[structopt(long)]
+    bot_takeover: bool,
+    // ships can be controlled right away instead of after a 3-2-1 countdown
+    #[structopt(long)]
+    no_countdown: bool,
+    // frames simulated per second, all speeds and durations of the match follow it
+    #[structopt(long, default_value = "60", possible_values = &["30", "60", "120"])]
+    tick_rate: u32,
+}
+
+impl GameOpt {
+    fn tuning(&self) -> Tuning {
+        let mut tuning = Tuning::new(self.movement_model, self.ship_scale);
+        tuning.bot_takeover = self.bot_takeover;
+        tuning.rotation = self.maps;
+        tuning.meteor_showers = self.meteor_showers;
+        tuning.win_condition = self.win_condition;
+        tuning.best_of = self.best_of.unwrap_or(0);
+        tuning.countdown = !self.no_countdown;
+        tuning.tick_rate = self.tick_rate;
+        tuning.round_frames = self
+            .round_length
+            .map_or(0, |seconds| tuning.frames(seconds as f32));
+        tuning
+    }
+
+    // games without anyone to agree with play the same match every time unless `--seed` is given
+    pub fn state(&self, num_players: usize) -> GameState {
+        self.seeded_state(num_players, self.seed.unwrap_or(0))
+    }
+
+    fn seeded_state(&self, num_players: usize, seed: u64) -> GameState {
+        GameState::new(num_players, self.tuning(), seed)
+    }
+}
+
+// Demo is the game played in a session, all peers have to pick the same one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Demo {
+    Box,
+    // two paddles and a ball, the smallest game worth rolling back
+    Pong,
+    // running and jumping between platforms, with collisions resolved every frame
+    Platformer,
+}
+
+impl FromStr for Demo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Self::Box),
+            "pong" => Ok(Self::Pong),
+            "platformer" => Ok(Self::Platformer),
+            _ => Err(format!("unknown game: {}", s)),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+pub struct PlayOpt {
+    #[structopt(short, long, required_unless = "time-travel")]
+    local_port: Option<u16>,
+    #[structopt(short, long)]
+    players: Vec<String>,
+    // the other games only play, without the menu, debug keys or recordings of the box game
+    #[structopt(long = "game", default_value = "box", possible_values = &["box", "pong", "platformer"])]
+    demo: Demo,
+    #[structopt(flatten)]
+    game: GameOpt,
+    // shown above your ship to everyone, defaults to the name picked in the setup
+    #[structopt(long)]
+    name: Option<String>,
+    // colors of the local players in order, by name or as hex like `#ff8800`, a color too close
+    // to the one of an earlier player is replaced
+    #[structopt(long)]
+    color: Vec<PlayerColor>,
+    // listens on ipv6, which is picked automatically if any peer has an ipv6 address
+    // ipv4 peers can still connect where the system allows dual-stack sockets
+    #[structopt(long)]
+    ipv6: bool,
+    // all peers have to pick the same transport, tcp works on networks blocking udp
+    #[structopt(long, default_value = "udp", possible_values = &["udp", "tcp"])]
+    transport: Transport,
+    // encrypts and authenticates all traffic with a key derived from this passphrase,
+    // all peers have to pick the same one
+    #[structopt(long)]
+    session_key: Option<String>,
+    // compresses large messages with lz4, all peers have to pick the same setting
+    #[structopt(long)]
+    compress: bool,
+    // limits the messages queued per remote player, further messages are dropped and counted
+    #[structopt(long)]
+    queue_size: Option<usize>,
+    // simulates a bad network to every remote player, to reproduce rollbacks on one machine
+    // milliseconds added to every message in each direction, and up to how many more at random
+    #[structopt(long, default_value = "0")]
+    sim_latency: u64,
+    #[structopt(long, default_value = "0")]
+    sim_jitter: u64,
+    // percentages of the messages that are lost, or held back until later ones overtook them
+    #[structopt(long, default_value = "0")]
+    sim_loss: f32,
+    #[structopt(long, default_value = "0")]
+    sim_reorder: f32,
+    // picks which messages are lost or delayed, the same seed loses the same messages
+    #[structopt(long, default_value = "0")]
+    sim_seed: u64,
+    // delays local inputs by this many frames, instead of picking a delay from the ping to the
+    // worst peer at the start of the session
+    #[structopt(long)]
+    frame_delay: Option<usize>,
+    // bounds of the automatically picked frame delay
+    #[structopt(long, default_value = "0")]
+    min_frame_delay: usize,
+    #[structopt(long, default_value = "4")]
+    max_frame_delay: usize,
+    // milliseconds without messages after which a peer is disconnected
+    #[structopt(long)]
+    disconnect_timeout: Option<u64>,
+    // milliseconds without messages after which a peer is reported as interrupted
+    #[structopt(long)]
+    disconnect_notify: Option<u64>,
+    // runs a mirrored simulation locally to detect player order dependent bugs
+    #[structopt(long)]
+    mirror_check: bool,
+    // records the match into a replay file, written when the window is closed
+    #[structopt(long, parse(from_os_str))]
+    record: Option<PathBuf>,
+    // writes the inputs of the local players to a script file, written when the window is closed
+    #[structopt(long, parse(from_os_str))]
+    record_inputs: Option<PathBuf>,
+    // takes the inputs of the local players from a script file instead of the keyboard
+    #[structopt(long, parse(from_os_str))]
+    input_script: Option<PathBuf>,
+    // plays offline with local players only, pausing with P allows scrubbing through the last frames
+    #[structopt(long)]
+    time_travel: bool,
+    // unix socket streaming confirmed frames and network stats as JSON lines
+    #[cfg(unix)]
+    #[structopt(long, parse(from_os_str))]
+    observer: Option<PathBuf>,
+    // observers connecting while this many are connected are turned away, which keeps the time
+    // and bandwidth spent on them bounded
+    #[cfg(unix)]
+    #[structopt(long, default_value = "8")]
+    max_observers: usize,
+    // serves session metrics for Prometheus at http://<host>:<port>/metrics
+    #[structopt(long)]
+    metrics_port: Option<u16>,
+    // in debug builds, F4 lets all peers advance only every this many ticks
+    #[structopt(long, default_value = "4")]
+    slow_motion_factor: u32,
+    // skips asking the router to forward the local port over UPnP
+    #[structopt(long)]
+    no_upnp: bool,
+    // lowers the niceness of the game thread, so background applications disturb the frame
+    // pacing less, usually needs root or CAP_SYS_NICE
+    #[structopt(long)]
+    high_priority: bool,
+    // keeps the game thread on this core
+    #[structopt(long)]
+    pin_core: Option<usize>,
+    // offers to post the replay and stats of the match to this http url when the window is closed
+    #[structopt(long)]
+    upload_url: Option<Endpoint>,
+    // asks this http url for the latest version on startup and shows a banner if this build is
+    // outdated, the check runs in the background
+    #[structopt(long)]
+    update_url: Option<Endpoint>,
+}
+
+pub async fn play(opt: PlayOpt, paths: &Paths, config: &config::Config) -> Result<(), Error> {
+    // bevy task pool
+    let pool = TaskPool::new();
+
+    if opt.time_travel {
+        if opt.players.iter().any(|addr| addr != "localhost") {
+            return Err(Error::InvalidOptions(
+                "--time-travel only works with local players",
+            ));
+        }
+        let num_players = opt.players.len().clamp(1, MAX_LOCAL_PLAYERS);
+        return debugger::run(num_players, opt.game.state(num_players)).await;
+    }
+    // failing to get either is no reason to stop, the jitter in the metrics shows if it matters
+    if opt.high_priority {
+        if let Err(e) = pacing::raise_priority() {
+            println!("Failed to raise the priority: {}", e);
+        }
+    }
+    if let Some(core) = opt.pin_core {
+        if let Err(e) = pacing::pin_to_core(core) {
+            println!("Failed to pin the game thread: {}", e);
+        }
+    }
+    // the other demos count in whole pixels per frame, so their speed can't follow the tick rate
+    if opt.demo != Demo::Box && opt.game.tick_rate != FPS as u32 {
+        return Err(Error::InvalidOptions(
+            "--tick-rate only applies to the box game",
+        ));
+    }
+    match opt.demo {
+        Demo::Box => {}
+        Demo::Pong if opt.players.len() > PONG_PLAYERS => {
+            return Err(Error::InvalidOptions("pong is played by at most 2 players"))
+        }
+        Demo::Pong => return play_demo::<Pong>(&opt, &pool, paths, config, ()).await,
+        Demo::Platformer if opt.players.len() > MAX_PLAYERS => {
+            return Err(Error::InvalidOptions(
+                "the platformer is played by at most 4 players",
+            ))
+        }
+        Demo::Platformer => return play_demo::<Platformer>(&opt, &pool, paths, config, ()).await,
+    }
+    let num_players = opt.players.len();
+    let Connected {
+        _connections,
+        sess,
+        local_port,
+        local_handles,
+        remote_handles,
+        drop_stats,
+        side_channels,
+        local_profile,
+    } = connect::<Game>(&opt, &pool, paths, config)?;
+    let Some(seed) = match_seed(&local_profile, &side_channels).await? else {
+        return Ok(());
+    };
+    let mut frame_delay_tuner =
+        (opt.frame_delay.is_none() && !remote_handles.is_empty()).then(|| {
+            let tick = opt.game.tuning().tick_duration();
+            FrameDelayTuner::new(opt.min_frame_delay, opt.max_frame_delay, num_players, tick)
+        });
+
+    // Create a new box game
+    let mut game = Game::from_state(opt.game.seeded_state(num_players, seed));
+    if !remote_handles.is_empty() {
+        game.start_session();
+    }
+    game.set_dump_dir(paths.dumps()?);
+    game.set_control_scheme(config.controls);
+    game.set_toggle_thrust(config.toggle_thrust);
+    for (handle, color) in local_handles.iter().zip(&local_profile.colors) {
+        game.set_name(handle.0, local_profile.name.clone());
+        game.set_color(handle.0, *color);
+    }
+    if opt.mirror_check {
+        game.enable_mirror_check();
+    }
+    if opt.record.is_some() || opt.upload_url.is_some() {
+        game.start_recording();
+    }
+    #[cfg(unix)]
+    if let Some(path) = &opt.observer {
+        game.set_observer(observer::Observer::bind(path, opt.max_observers)?);
+    }
+    if let Some(port) = opt.metrics_port {
+        game.set_metrics(metrics::Metrics::serve(port)?);
+    }
+    let input_script = opt
+        .input_script
+        .as_deref()
+        .map(InputScript::load)
+        .transpose()?;
+    let mut recorded_inputs = opt.record_inputs.as_ref().map(|_| InputScript::default());
+    game.warm_up();
+    game::cache_glyphs();
+    let mut update_check = opt.update_url.clone().map(UpdateCheck::start);
+    let mut port_mapping = (!opt.no_upnp && !remote_handles.is_empty())
+        .then(|| PortMapping::start(local_port, opt.transport));
+    let mut last_stats_update = Instant::now();
+    let mut pending_profiles = side_channels.clone();
+    // the peers whose profile arrived, several remote players can share one
+    let mut met = BTreeMap::new();
+    let mut slow_motion = SlowMotion::default();
+    let mut menu = Menu::new(remote_handles.iter().map(|handle| handle.0).collect());
+    let mut controls = config.controls;
+
+    let mut step = FixedStep::default();
+
+    prevent_quit();
+    while !is_quit_requested() {
+        game.handle_commands(sess.poll());
+        match menu.update() {
+            Some(MenuAction::NextControls) => {
+                controls = controls.next();
+                game.set_control_scheme(controls);
+            }
+            Some(MenuAction::ToggleMute(player)) => {
+                let channel = side_channels.iter().find(|(handle, ..)| handle.0 == player);
+                if let Some((_, _, side_channel)) = channel {
+                    let muted = !side_channel.is_muted();
+                    side_channel.set_muted(muted);
+                    match side_channel.profile() {
+                        Some(profile) if !muted => game.set_name(player, profile.name),
+                        _ => game.clear_name(player),
+                    }
+                }
+            }
+            Some(MenuAction::Leave) => break,
+            None => {}
+        }
+        step.accumulate();
+        // the session keeps running while the menu is open, the local players just stand still
+        let keyboard: Vec<PlayerInput> = (0..local_handles.len())
+            .map(|i| match menu.is_open() {
+                true => PlayerInput { buttons_pressed: 0 },
+                false => game.local_input(i),
+            })
+            .collect();
+
+        // if enough time is accumulated, we run a frame
+        // ticks take a little longer or shorter while backroll asks to sync the time with the others
+        while step.tick(game.tick_duration()) {
+            game.tick();
+            if !slow_motion.tick(game.frame()) {
+                continue;
+            }
+
+            // scripted inputs take precedence over the keyboard until the script is over
+            let frame = game.frame() + 1;
+            let inputs: Vec<PlayerInput> = (0..local_handles.len())
+                .map(
+                    |i| match input_script.as_ref().and_then(|s| s.buttons(frame, i)) {
+                        Some(buttons_pressed) => PlayerInput { buttons_pressed },
+                        None => keyboard[i],
+                    },
+                )
+                .collect();
+            // the frame can only be advanced once the inputs of all local players are in
+            match advance_session(&mut game, &sess, &local_handles, &inputs) {
+                Ok(_) => {
+                    if let Some(recorded_inputs) = &mut recorded_inputs {
+                        let buttons = inputs.iter().map(|i| i.buttons_pressed).collect();
+                        recorded_inputs.record(frame, buttons);
+                    }
+                }
+                Err(e) => println!("{e}"),
+            }
+        }
+
+        // measure the pings at the start of the session and pick a frame delay from them
+        if let (Some(tuner), true) = (&mut frame_delay_tuner, sess.is_synchronized()) {
+            for handle in remote_handles.iter() {
+                if let Ok(stats) = sess.network_stats(*handle) {
+                    tuner.sample(handle.0, stats.ping);
+                }
+            }
+            if let Some((delay, ping)) = tuner.poll() {
+                println!(
+                    "Frame delay set to {} for a ping of {} ms to the worst peer",
+                    delay,
+                    ping.as_millis()
+                );
+                for handle in local_handles.iter() {
+                    if let Err(e) = sess.set_frame_delay(*handle, delay) {
+                        println!("Failed to set the frame delay: {}", e);
+                    }
+                }
+                frame_delay_tuner = None;
+            }
+        }
+
+        // update network stats once per second
+        if last_stats_update.elapsed() >= Duration::from_secs(1) {
+            last_stats_update = Instant::now();
+            for handle in remote_handles.iter() {
+                if let Ok(stats) = sess.network_stats(*handle) {
+                    game.update_network_stats(*handle, &stats);
+                }
+            }
+            for (handle, stats) in drop_stats.iter() {
+                let sent = stats.sent.load(Ordering::Relaxed);
+                let received = stats.received.load(Ordering::Relaxed);
+                game.update_dropped_messages(*handle, sent, received);
+            }
+        }
+
+        // the names and colors of remote players arrive during the handshake
+        let mut mismatch = None;
+        pending_profiles.retain(|(handle, remote_index, side_channel)| {
+            let Some(profile) = side_channel.profile() else {
+                return true;
+            };
+            if let Err(e) = profile.check(&opt.players, &opt.players[handle.0], &local_profile) {
+                mismatch = Some(e);
+            }
+            met.insert(profile.id, profile.name.clone());
+            // the names of muted players are the one thing they could say
+            if !side_channel.is_muted() {
+                game.set_name(handle.0, profile.name);
+            }
+            if let Some(color) = profile.colors.get(*remote_index) {
+                game.set_color(handle.0, *color);
+            }
+            false
+        });
+        if let Some(mismatch) = mismatch {
+            return Err(mismatch);
+        }
+
+        // F4 toggles slow motion on all peers, to watch a bug unfold together
+        if cfg!(debug_assertions) && is_key_pressed(KeyCode::F4) {
+            let (frame, factor) =
+                slow_motion.toggle(game.frame(), opt.slow_motion_factor, &game.state().tuning);
+            for (_, _, side_channel) in side_channels.iter() {
+                side_channel.send_slow_motion(frame, factor);
+            }
+        }
+        for (_, _, side_channel) in side_channels.iter() {
+            if let Some((frame, factor)) = side_channel.take_slow_motion() {
+                slow_motion.schedule(frame, factor);
+            }
+        }
+
+        // F5 to F8 disconnect the remote player with that number, to see how everyone else copes
+        for (i, key) in [KeyCode::F5, KeyCode::F6, KeyCode::F7, KeyCode::F8]
+            .into_iter()
+            .enumerate()
+        {
+            let handle = remote_handles.iter().find(|handle| handle.0 == i);
+            if let (true, Some(handle)) = (is_key_pressed(key), handle) {
+                if let Err(e) = sess.disconnect_player(*handle) {
+                    println!("Failed to disconnect player {}: {}", i + 1, e);
+                }
+            }
+        }
+        // F2 starts the match over with a fresh seed, only while nobody else depends on our frames
+        if remote_handles.is_empty() && is_key_pressed(KeyCode::F2) {
+            game.restart(rng::time_seed());
+            if let Some(recorded_inputs) = &mut recorded_inputs {
+                *recorded_inputs = InputScript::default();
+            }
+        }
+        game.handle_debug_keys();
+        // interpolate between the last two simulated frames with the leftover time
+        let alpha = step.alpha(game.tick_duration());
+        let render_start = Instant::now();
+        game.render(alpha);
+        if let Some(update_check) = &mut update_check {
+            update_check.draw();
+        }
+        if let Some(port_mapping) = &mut port_mapping {
+            port_mapping.draw();
+        }
+        slow_motion.draw();
+        step.draw_catch_up();
+        if menu.is_open() {
+            let muted: Vec<usize> = side_channels
+                .iter()
+                .filter(|(_, _, side_channel)| side_channel.is_muted())
+                .map(|(handle, ..)| handle.0)
+                .collect();
+            menu.draw(controls, &muted);
+        }
+        game.finish_frame(render_start.elapsed());
+        next_frame().await;
+    }
+
+    game.print_profile();
+    if !met.is_empty() {
+        if let Err(e) = opponents::remember(&paths.opponents()?, &met) {
+            println!("Failed to remember the opponents: {}", e);
+        }
+    }
+    let stats = game.match_stats();
+    let replay = game.take_recording();
+    if let (Some(path), Some(replay)) = (&opt.record, &replay) {
+        let path = paths.replay_to_write(path)?;
+        replay.save(&path)?;
+        game.take_net_log().save(&path)?;
+        println!("Saved the replay to {}", path.display());
+    }
+    if let (Some(endpoint), Some(replay)) = (&opt.upload_url, &replay) {
+        if upload::ask_consent(endpoint)? {
+            match upload::upload(endpoint, &replay.to_bytes()?, &stats) {
+                Ok(()) => println!("Uploaded the match to {}", endpoint),
+                Err(e) => println!("Failed to upload the match: {}", e),
+            }
+        }
+    }
+    if let (Some(path), Some(recorded_inputs)) = (&opt.record_inputs, &recorded_inputs) {
+        recorded_inputs.save(path)?;
+    }
+    Ok(())
+}
+
+// plays a session of a demo game with nothing around it, a missing opponent is played by the game
+async fn play_demo<G: RollbackGame>(
+    opt: &PlayOpt,
+    pool: &TaskPool,
+    paths: &Paths,
+    config: &config::Config,
+    rules: G::Rules,
+) -> Result<(), Error> {
+    let Connected {
+        _connections,
+        sess,
+        local_handles,
+        side_channels,
+        local_profile,
+        ..
+    } = connect::<G>(opt, pool, paths, config)?;
+    // demos have no loop of their own to check profiles in as they arrive, so they wait for all
+    for (handle, _, side_channel) in side_channels.iter() {
+        let text = "Waiting for the other players...";
+        let Some(profile) = wait_for_profile(handle.0, side_channel, text).await? else {
+            return Ok(());
+        };
+        profile.check(&opt.players, &opt.players[handle.0], &local_profile)?;
+    }
+    let Some(seed) = match_seed(&local_profile, &side_channels).await? else {
+        return Ok(());
+    };
+    let mut game = G::new(opt.players.len(), rules, seed);
+    rollback_game::run(&mut game, &sess, &local_handles).await;
+    Ok(())
+}
+
+// the seed of the match, picked by the host and sent to everyone else with its profile
+// the other peers wait for it before the first frame, the handshake runs next to the session, so
+// it doesn't hold up synchronizing
+async fn match_seed(
+    local_profile: &handshake::Profile,
+    side_channels: &[(PlayerHandle, usize, SideChannel)],
+) -> Result<Option<u64>, Error> {
+    let Some((_, _, host)) = side_channels.iter().find(|(handle, ..)| handle.0 == 0) else {
+        return Ok(Some(local_profile.seed));
+    };
+    let text = "Waiting for the host to pick the seed...";
+    let profile = wait_for_profile(0, host, text).await?;
+    Ok(profile.map(|profile| profile.seed))
+}
+
+// shows `text` until the profile of `player` arrives over `side_channel`, None if the window is
+// closed first
+// the handshake gives up on peers that don't answer, so waiting any longer would never end
+async fn wait_for_profile(
+    player: usize,
+    side_channel: &SideChannel,
+    text: &str,
+) -> Result<Option<handshake::Profile>, Error> {
+    let start = Instant::now();
+    prevent_quit();
+    while !is_quit_requested() {
+        if let Some(profile) = side_channel.profile() {
+            return Ok(Some(profile));
+        }
+        if start.elapsed() >= handshake::HANDSHAKE_TIMEOUT {
+            return Err(Error::HandshakeTimeout { player });
+        }
+        clear_background(BLACK);
+        draw_text(text, 20.0, 40.0, 30.0, WHITE);
+        next_frame().await;
+    }
+    Ok(None)
+}
+
+// Connected is a session with all players added, along with what has to live as long as it
+struct Connected<G: RollbackGame> {
+    // the sockets have to outlive the session
+    _connections: Connections,
+    sess: P2PSession<RollbackConfig<G>>,
+    local_port: u16,
+    local_handles: Vec<PlayerHandle>,
+    remote_handles: Vec<PlayerHandle>,
+    drop_stats: Vec<(PlayerHandle, Arc<DropStats>)>,
+    side_channels: Vec<(PlayerHandle, usize, SideChannel)>,
+    local_profile: handshake::Profile,
+}
+
+// connects to the players of `--players` and starts a session of `G` with them, every game
+// played over the network goes through the same transports, encryption and handshake
+fn connect<G: RollbackGame>(
+    opt: &PlayOpt,
+    pool: &TaskPool,
+    paths: &Paths,
+    config: &config::Config,
+) -> Result<Connected<G>, Error> {
+    let local_port = opt
+        .local_port
+        .ok_or(Error::InvalidOptions("--local-port is required"))?;
+    let mut local_handles = Vec::new();
+    let mut remote_handles = Vec::new();
+    // messages dropped by bounded queues, per remote player
+    let mut drop_stats = Vec::new();
+    let num_players = opt.players.len();
+    if num_players == 0 {
+        return Err(Error::InvalidOptions("--players needs at least one player"));
+    }
+
+    // names are resolved once up front, ipv6 peers can only be reached from an ipv6 socket
+    let remote_addrs = opt
+        .players
+        .iter()
+        .map(|addr| match addr.as_str() {
+            "localhost" => Ok(None),
+            addr => transport::resolve(addr, opt.ipv6).map(Some),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let ipv6 = opt.ipv6 || remote_addrs.iter().flatten().any(SocketAddr::is_ipv6);
+    // udp socket or tcp listener
+    let listen_addr = transport::listen_addr(local_port, ipv6);
+    let connections = Connections::bind(opt.transport, pool.clone(), listen_addr)?;
+    // remote peers tell local players apart by the position of the first one in the player list
+    let session_key = opt.session_key.as_deref().map(SessionKey::from_passphrase);
+    let first_local_player = opt.players.iter().position(|addr| addr == "localhost");
+    let local_players: Vec<usize> = (0..opt.players.len())
+        .filter(|i| opt.players[*i] == "localhost")
+        .collect();
+    let local_profile = handshake::Profile {
+        id: PeerId::load_or_create(&paths.identity()?)?,
+        name: handshake::sanitize(opt.name.as_deref().unwrap_or(&config.name)),
+        colors: local_players
+            .iter()
+            .enumerate()
+            .map(|(local_index, player)| match opt.color.get(local_index) {
+                Some(color) => *color,
+                None => PlayerColor::default_for(*player),
+            })
+            .collect(),
+        order: handshake::PlayerOrder::of(&opt.players, "localhost"),
+        // only the seed of the host counts
+        seed: opt.game.seed.unwrap_or_else(rng::time_seed),
+        tick_rate: opt.game.tick_rate,
+        tuning: opt.game.tuning().checksum(),
+    };
+    let network_conditions = NetworkConditions {
+        latency: Duration::from_millis(opt.sim_latency),
+        jitter: Duration::from_millis(opt.sim_jitter),
+        loss: opt.sim_loss / 100.0,
+        reorder: opt.sim_reorder / 100.0,
+    };
+    let block_list = Arc::new(BlockList::load(&paths.block_list()?)?);
+    // side channels to remote players, their profiles are filled in by the handshake
+    let mut side_channels = Vec::new();
+
+    // create a backroll session
+    let frame_delay = opt.frame_delay.unwrap_or(opt.min_frame_delay);
+    let mut sess_builder = P2PSession::<RollbackConfig<G>>::build().with_frame_delay(frame_delay);
+    if let Some(timeout) = opt.disconnect_timeout {
+        sess_builder = sess_builder.with_disconnect_timeout(Duration::from_millis(timeout));
+    }
+    if let Some(notify_start) = opt.disconnect_notify {
+        sess_builder =
+            sess_builder.with_disconnect_notify_start(Duration::from_millis(notify_start));
+    }
+
+    // add players
+    for (i, player_addr) in opt.players.iter().enumerate() {
+        // local players, several of them can share the keyboard
+        if player_addr == "localhost" {
+            local_handles.push(sess_builder.add_player(Player::Local));
+        } else {
+            // remote players
+            let local_player = first_local_player
+                .ok_or(Error::InvalidOptions("at least one player has to be local"))?;
+            let addr =
+                remote_addrs[i].ok_or(Error::InvalidOptions("remote players need an address"))?;
+            let addr = transport::peer_addr(addr, ipv6);
+            let mut peer = connections.connect(local_player, i, addr, opt.queue_size);
+            if !network_conditions.is_perfect() {
+                let seed = opt.sim_seed.wrapping_add(i as u64);
+                peer = simulate_network(peer, network_conditions, seed);
+            }
+            if let Some(key) = &session_key {
+                peer = key.wrap(pool, peer, local_player, i);
+            }
+            // compressing has to happen before encrypting, encrypted data doesn't compress
+            if opt.compress {
+                peer = compression::wrap(pool, peer);
+            }
+            let mut stats = None;
+            if let Some(queue_size) = opt.queue_size {
+                let (bounded, drop_stats) = bound_peer(pool, peer, queue_size);
+                peer = bounded;
+                stats = Some(drop_stats);
+            }
+            let (introduced, side_channel) =
+                handshake::wrap(pool, peer, &local_profile, block_list.clone());
+            peer = introduced;
+            let handle = sess_builder.add_player(Player::Remote(peer));
+            remote_handles.push(handle);
+            // players sharing an address are the local players of that peer, in the same order
+            let remote_index = opt.players[..i]
+                .iter()
+                .filter(|addr| *addr == player_addr)
+                .count();
+            side_channels.push((handle, remote_index, side_channel));
+            if let Some(stats) = stats {
+                drop_stats.push((handle, stats));
+            }
+        }
+    }
+
+    if local_handles.len() > MAX_LOCAL_PLAYERS {
+        return Err(Error::TooManyLocalPlayers(MAX_LOCAL_PLAYERS));
+    }
+
+    Ok(Connected {
+        _connections: connections,
+        sess: sess_builder.start(pool.clone())?,
+        local_port,
+        local_handles,
+        remote_handles,
+        drop_stats,
+        side_channels,
+        local_profile,
+    })
+}
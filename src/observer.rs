@@ -0,0 +1,633 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    game::{
+        player_color, Frame, FrameInputs, GameState, FPS, INPUT_DOWN, INPUT_LEFT, INPUT_RIGHT,
+        INPUT_UP, WINDOW_HEIGHT, WINDOW_WIDTH,
+    },
+    snapshot::{Snapshot, SnapshotDecoder, SnapshotEncoder},
+    Error,
+};
+
+// a full state is streamed once per second, everything in between is delta encoded
+const KEYFRAME_INTERVAL: Frame = 60;
+// playback lagging this many seconds behind speeds up fully, less lag speeds it up less, so it eases
+// back into the pace of the game instead of jumping
+const CATCH_UP_DURATION: f32 = 1.0;
+
+// ObserverMessage is sent to observers as a single line of JSON
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ObserverMessage {
+    // sent by observers joining late, answered with a keyframe of the last confirmed state, after
+    // which the confirmed frames and snapshots following it can be applied
+    KeyframeRequest,
+    // a frame that can't be rolled back anymore
+    Frame {
+        frame: Frame,
+        // `null` for disconnected players
+        inputs: Vec<Option<u8>>,
+        // hash of these and all earlier inputs
+        input_hash: u64,
+    },
+    // the state after a confirmed frame
+    Snapshot {
+        snapshot: Snapshot,
+    },
+    NetworkStats {
+        player: usize,
+        ping_ms: u128,
+        send_queue_len: usize,
+        kbps_sent: u32,
+        local_frames_behind: i32,
+        remote_frames_behind: i32,
+    },
+}
+
+// Client is a connected observer, with what it sent that isn't a complete line yet
+struct Client {
+    stream: UnixStream,
+    received: Vec<u8>,
+}
+
+impl Client {
+    // reads what the client sent since the last call, returns false once it disconnected
+    fn receive(&mut self) -> bool {
+        let mut chunk = [0; 256];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return false,
+                Ok(n) => self.received.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    // takes the complete lines received so far
+    fn requests(&mut self) -> Vec<ObserverMessage> {
+        let mut requests = Vec::new();
+        while let Some(end) = self.received.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.received.drain(..=end).collect();
+            if let Ok(request) = serde_json::from_slice(&line) {
+                requests.push(request);
+            }
+        }
+        requests
+    }
+}
+
+// Observer streams live match data to external tools over a local unix socket
+// it never blocks the game, observers that can't keep up are dropped
+pub struct Observer {
+    listener: UnixListener,
+    clients: Vec<Client>,
+    // observers connecting while this many are connected are turned away
+    max_clients: usize,
+    // the state of the last confirmed frame
+    confirmed_state: Option<GameState>,
+    encoder: SnapshotEncoder,
+}
+
+impl Observer {
+    pub fn bind(path: &Path, max_clients: usize) -> io::Result<Self> {
+        // a socket file left over from an earlier run would make binding fail, anything else at
+        // the path is someone's file and stays
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} exists and isn't a socket", path.display()),
+                ))
+            }
+            Err(_) => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            clients: Vec::new(),
+            max_clients,
+            confirmed_state: None,
+            encoder: SnapshotEncoder::new(KEYFRAME_INTERVAL),
+        })
+    }
+
+    // sets the state the following confirmed frames are applied to
+    pub fn start(&mut self, state: GameState) {
+        self.confirmed_state = Some(state);
+    }
+
+    // sends a confirmed frame, followed by a snapshot of the state it results in
+    // both are serialized once and written to every observer in one go
+    pub fn send_confirmed(&mut self, confirmed: FrameInputs) {
+        // keyframes are answered before the frame, so the frame applies to them
+        self.answer_requests();
+        let Some(state) = &mut self.confirmed_state else {
+            return;
+        };
+        state.advance(&confirmed.buttons_pressed);
+        let snapshot = self.encoder.encode(state);
+        if self.clients.is_empty() {
+            return;
+        }
+        let frame = ObserverMessage::Frame {
+            frame: confirmed.frame,
+            inputs: confirmed.buttons_pressed,
+            input_hash: confirmed.input_hash,
+        };
+        let (Some(mut batch), Some(snapshot)) = (
+            to_line(&frame),
+            to_line(&ObserverMessage::Snapshot { snapshot }),
+        ) else {
+            return;
+        };
+        batch.extend_from_slice(&snapshot);
+        self.broadcast(&batch);
+    }
+
+    pub fn send(&mut self, message: &ObserverMessage) {
+        self.accept_clients();
+        if self.clients.is_empty() {
+            return;
+        }
+        if let Some(line) = to_line(message) {
+            self.broadcast(&line);
+        }
+    }
+
+    fn broadcast(&mut self, bytes: &[u8]) {
+        self.clients
+            .retain_mut(|client| client.stream.write_all(bytes).is_ok());
+    }
+
+    // accepts everyone who connected since the last message
+    fn accept_clients(&mut self) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            // closing the stream right away tells the observer it wasn't let in
+            if self.clients.len() >= self.max_clients {
+                println!(
+                    "Turned away an observer, {} are connected already",
+                    self.max_clients
+                );
+                continue;
+            }
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client {
+                    stream,
+                    received: Vec::new(),
+                });
+            }
+        }
+    }
+
+    // sends a keyframe of the last confirmed state to everyone who asked for one
+    fn answer_requests(&mut self) {
+        self.accept_clients();
+        let Some(state) = &self.confirmed_state else {
+            return;
+        };
+        let mut keyframe = None;
+        self.clients.retain_mut(|client| {
+            if !client.receive() {
+                return false;
+            }
+            let requests = client.requests();
+            if !requests
+                .iter()
+                .any(|request| matches!(request, ObserverMessage::KeyframeRequest))
+            {
+                return true;
+            }
+            let line = keyframe.get_or_insert_with(|| {
+                to_line(&ObserverMessage::Snapshot {
+                    snapshot: Snapshot::Keyframe(Box::new(state.clone())),
+                })
+            });
+            match line {
+                Some(line) => client.stream.write_all(line).is_ok(),
+                None => true,
+            }
+        });
+    }
+}
+
+fn to_line(message: &ObserverMessage) -> Option<Vec<u8>> {
+    match serde_json::to_vec(message) {
+        Ok(mut line) => {
+            line.push(b'\n');
+            Some(line)
+        }
+        Err(e) => {
+            println!("Failed to serialize observer message: {}", e);
+            None
+        }
+    }
+}
+
+// DelayBuffer holds back observer messages until the match is `delay` frames further, and plays
+// them back at the pace of the game in between, so hiccups shorter than the delay don't show
+struct DelayBuffer {
+    delay: Frame,
+    // the fraction playback speeds up by at most while it lags behind, e.g. after a hiccup
+    catch_up: f32,
+    // messages with the last frame received before them
+    messages: VecDeque<(Frame, ObserverMessage)>,
+    latest_frame: Frame,
+    // the frame playback is at, messages up to it are released
+    // it starts with the first frame, spectators may join in the middle of a match
+    playback_frame: Option<f32>,
+    // the pace of the game, taken from the first keyframe
+    tick_rate: f32,
+}
+
+impl DelayBuffer {
+    fn new(delay: Frame, catch_up: f32) -> Self {
+        Self {
+            delay,
+            catch_up,
+            messages: VecDeque::new(),
+            latest_frame: 0,
+            playback_frame: None,
+            tick_rate: FPS,
+        }
+    }
+
+    fn push(&mut self, message: ObserverMessage) {
+        if let ObserverMessage::Snapshot {
+            snapshot: Snapshot::Keyframe(state),
+        } = &message
+        {
+            self.tick_rate = state.tuning.tick_rate as f32;
+        }
+        if let ObserverMessage::Frame { frame, .. } = message {
+            self.playback_frame
+                .get_or_insert((frame - self.delay) as f32);
+            self.latest_frame = frame;
+        }
+        self.messages.push_back((self.latest_frame, message));
+    }
+
+    // advances the playback by `elapsed` and returns the messages it passed
+    fn release(&mut self, elapsed: Duration) -> Vec<ObserverMessage> {
+        let Some(playback_frame) = &mut self.playback_frame else {
+            return Vec::new();
+        };
+        let target = (self.latest_frame - self.delay) as f32;
+        let lag = (target - *playback_frame).max(0.0);
+        let catch_up_frames = CATCH_UP_DURATION * self.tick_rate;
+        let speed = 1.0 + self.catch_up * (lag / catch_up_frames).min(1.0);
+        *playback_frame =
+            (*playback_frame + elapsed.as_secs_f32() * self.tick_rate * speed).min(target);
+        let playback_frame = *playback_frame;
+        let mut released = Vec::new();
+        while let Some((frame, _)) = self.messages.front() {
+            if *frame as f32 > playback_frame {
+                break;
+            }
+            released.extend(self.messages.pop_front().map(|(_, message)| message));
+        }
+        released
+    }
+}
+
+// color keyed out by the streaming software
+const CHROMA_KEY: Color = Color::new(0.0, 1.0, 0.0, 1.0);
+
+// renders only the HUD of a match on a chroma key background, driven by an observer stream
+// meant to run as a separate window captured by streaming software
+// with a `delay`, the overlay shows the match that many frames behind
+// when it falls further behind, it plays up to `catch_up` faster until it is back
+pub async fn run_overlay(path: &Path, delay: Frame, catch_up: f32) -> Result<(), Error> {
+    let mut stream = UnixStream::connect(path)?;
+    // the match may have started long ago, the keyframe lets us start from the current frame
+    let request = to_line(&ObserverMessage::KeyframeRequest).expect("requests always encode");
+    stream.write_all(&request)?;
+    stream.set_nonblocking(true)?;
+
+    let mut buffer = Vec::new();
+    let mut connected = true;
+    let mut frame = 0;
+    let mut inputs: Vec<Option<u8>> = Vec::new();
+    let mut pings: Vec<Option<u128>> = Vec::new();
+    let mut decoder = SnapshotDecoder::default();
+    let mut state: Option<GameState> = None;
+    let mut delay_buffer = DelayBuffer::new(delay, catch_up);
+    let mut last_render = Instant::now();
+
+    loop {
+        // read everything that arrived since the last render
+        let mut chunk = [0; 4096];
+        while connected {
+            match stream.read(&mut chunk) {
+                Ok(0) => connected = false,
+                Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => connected = false,
+            }
+        }
+
+        // queue all complete lines
+        while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            match serde_json::from_slice(&line) {
+                Ok(message) => delay_buffer.push(message),
+                Err(e) => println!("Invalid observer message: {}", e),
+            }
+        }
+
+        // handle the messages that are due
+        for message in delay_buffer.release(last_render.elapsed()) {
+            match message {
+                ObserverMessage::Frame {
+                    frame: confirmed_frame,
+                    inputs: confirmed_inputs,
+                    ..
+                } => {
+                    frame = confirmed_frame;
+                    inputs = confirmed_inputs;
+                    pings.resize(inputs.len(), None);
+                }
+                ObserverMessage::NetworkStats {
+                    player, ping_ms, ..
+                } => {
+                    if player >= pings.len() {
+                        pings.resize(player + 1, None);
+                    }
+                    pings[player] = Some(ping_ms);
+                }
+                ObserverMessage::Snapshot { snapshot } => match decoder.decode(snapshot) {
+                    Ok(decoded) => state = Some(decoded.clone()),
+                    Err(e) => println!("Invalid snapshot: {}", e),
+                },
+                ObserverMessage::KeyframeRequest => {}
+            }
+        }
+        last_render = Instant::now();
+
+        render_overlay(frame, &inputs, &pings, connected);
+        if let Some(state) = &state {
+            render_minimap(state);
+        }
+        next_frame().await;
+    }
+}
+
+fn render_overlay(frame: Frame, inputs: &[Option<u8>], pings: &[Option<u128>], connected: bool) {
+    clear_background(CHROMA_KEY);
+
+    let status = if connected { "" } else { " (disconnected)" };
+    draw_text(
+        &format!("Frame {}{}", frame, status),
+        20.0,
+        40.0,
+        40.0,
+        WHITE,
+    );
+
+    for (i, input) in inputs.iter().enumerate() {
+        let y = 100.0 + 60.0 * i as f32;
+        let color = player_color(i);
+        draw_text(&format!("P{}", i + 1), 20.0, y + 30.0, 40.0, color);
+
+        // one box per button, filled while it is pressed
+        match input {
+            Some(buttons) => {
+                for (j, button) in [INPUT_UP, INPUT_LEFT, INPUT_DOWN, INPUT_RIGHT]
+                    .iter()
+                    .enumerate()
+                {
+                    let x = 80.0 + 45.0 * j as f32;
+                    if buttons & button != 0 {
+                        draw_rectangle(x, y, 40.0, 40.0, color);
+                    } else {
+                        draw_rectangle_lines(x, y, 40.0, 40.0, 3.0, color);
+                    }
+                }
+            }
+            None => draw_text("disconnected", 80.0, y + 30.0, 30.0, color),
+        }
+
+        if let Some(Some(ping)) = pings.get(i) {
+            draw_text(&format!("{} ms", ping), 280.0, y + 30.0, 30.0, WHITE);
+        }
+    }
+}
+
+// size of the minimap relative to the game window
+const MINIMAP_SCALE: f32 = 0.25;
+
+// shows the ship positions of the last confirmed state in the bottom left corner
+fn render_minimap(state: &GameState) {
+    let width = WINDOW_WIDTH * MINIMAP_SCALE;
+    let height = WINDOW_HEIGHT * MINIMAP_SCALE;
+    let (left, top) = (20.0, screen_height() - height - 20.0);
+    draw_rectangle(left, top, width, height, BLACK);
+    for i in 0..state.num_players {
+        let (x, y) = state.positions[i];
+        draw_circle(
+            left + x * MINIMAP_SCALE,
+            top + y * MINIMAP_SCALE,
+            4.0,
+            player_color(i),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{scripted_input, Game, MovementModel, Tuning, PREDICTION_WINDOW};
+    use std::collections::BTreeMap;
+
+    fn frame(frame: Frame) -> ObserverMessage {
+        ObserverMessage::Frame {
+            frame,
+            inputs: Vec::new(),
+            input_hash: 0,
+        }
+    }
+
+    #[test]
+    fn late_observers_get_a_keyframe_to_apply_the_next_frame_to() {
+        let path = std::env::temp_dir().join(format!("box_game_observer_{}", std::process::id()));
+        let mut observer = Observer::bind(&path, 1).unwrap();
+        let mut state = GameState::new(2, Tuning::new(MovementModel::Inertia, 1.0), 0);
+        for _ in 0..100 {
+            state.advance(&[Some(INPUT_UP), Some(INPUT_LEFT)]);
+        }
+        observer.start(state.clone());
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(&to_line(&ObserverMessage::KeyframeRequest).unwrap())
+            .unwrap();
+        observer.send_confirmed(FrameInputs {
+            frame: state.frame + 1,
+            buttons_pressed: vec![Some(INPUT_UP), None],
+            input_hash: 0,
+        });
+        drop(observer);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        let mut messages = received
+            .lines()
+            .map(|line| serde_json::from_str::<ObserverMessage>(line).unwrap());
+        let mut decoder = SnapshotDecoder::default();
+        let Some(ObserverMessage::Snapshot { snapshot }) = messages.next() else {
+            panic!("expected a keyframe first");
+        };
+        assert!(*decoder.decode(snapshot).unwrap() == state);
+        let Some(ObserverMessage::Frame { frame, inputs, .. }) = messages.next() else {
+            panic!("expected the confirmed frame after the keyframe");
+        };
+        assert_eq!(frame, state.frame + 1);
+        state.advance(&inputs);
+        let Some(ObserverMessage::Snapshot { snapshot }) = messages.next() else {
+            panic!("expected the snapshot of the confirmed frame");
+        };
+        assert!(*decoder.decode(snapshot).unwrap() == state);
+    }
+
+    #[test]
+    fn observers_beyond_the_limit_are_turned_away() {
+        let path = std::env::temp_dir().join(format!("box_game_limit_{}", std::process::id()));
+        let mut observer = Observer::bind(&path, 1).unwrap();
+        let mut clients = [
+            UnixStream::connect(&path).unwrap(),
+            UnixStream::connect(&path).unwrap(),
+        ];
+        observer.send(&frame(7));
+        drop(observer);
+        std::fs::remove_file(&path).unwrap();
+
+        let received = clients.each_mut().map(|client| {
+            let mut received = String::new();
+            client.read_to_string(&mut received).unwrap();
+            received.lines().count()
+        });
+        assert_eq!(received, [1, 0]);
+    }
+
+    // a spectator joining mid-match simulates on its own from the transferred state and the
+    // confirmed inputs, and has to arrive at the same states as the player
+    #[test]
+    fn spectators_simulate_in_sync_from_a_transferred_state() {
+        const PLAYERS: usize = 4;
+        const JOIN_FRAME: Frame = 300;
+        const FRAMES: Frame = JOIN_FRAME + 1000;
+        let path = std::env::temp_dir().join(format!("box_game_transfer_{}", std::process::id()));
+        let mut game = Game::new(PLAYERS, Tuning::new(MovementModel::Inertia, 1.0), 0);
+        game.set_observer(Observer::bind(&path, 1).unwrap());
+        let mut checksums = BTreeMap::new();
+        let mut client: Option<UnixStream> = None;
+        let mut received = Vec::new();
+        let mut spectator: Option<GameState> = None;
+        let mut compared = 0;
+
+        // the confirmed frames trail the game by the prediction window
+        while game.frame() < FRAMES + PREDICTION_WINDOW as Frame {
+            let frame = game.frame() + 1;
+            let inputs = (0..PLAYERS)
+                .map(|player| scripted_input(frame, player, FRAMES))
+                .collect();
+            game.advance_offline(inputs);
+            checksums.insert(game.frame(), game.state().checksum());
+
+            if game.frame() == JOIN_FRAME {
+                let mut stream = UnixStream::connect(&path).unwrap();
+                stream
+                    .write_all(&to_line(&ObserverMessage::KeyframeRequest).unwrap())
+                    .unwrap();
+                stream.set_nonblocking(true).unwrap();
+                client = Some(stream);
+            }
+            let Some(stream) = &mut client else {
+                continue;
+            };
+            let mut chunk = [0; 4096];
+            loop {
+                match stream.read(&mut chunk) {
+                    Ok(0) => panic!("the observer dropped the spectator"),
+                    Ok(n) => received.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+            while let Some(end) = received.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = received.drain(..=end).collect();
+                match serde_json::from_slice(&line).unwrap() {
+                    ObserverMessage::Snapshot {
+                        snapshot: Snapshot::Keyframe(state),
+                    } if spectator.is_none() => spectator = Some(*state),
+                    ObserverMessage::Frame { frame, inputs, .. } => {
+                        let state = spectator.as_mut().expect("the keyframe comes first");
+                        state.advance(&inputs);
+                        assert_eq!(state.frame, frame);
+                        assert_eq!(state.checksum(), checksums[&frame], "frame {}", frame);
+                        compared += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(compared >= 1000, "only {} frames were compared", compared);
+    }
+
+    #[test]
+    fn playback_eases_into_the_game_pace_when_catching_up() {
+        let mut buffer = DelayBuffer::new(0, 0.5);
+        buffer.push(frame(0));
+        buffer.push(frame(120));
+        // a second behind, playback runs at the full 1.5x
+        buffer.release(Duration::from_secs_f32(1.0 / FPS));
+        assert!((buffer.playback_frame.unwrap() - 1.5).abs() < 0.01);
+        // half a second behind, only at 1.25x
+        buffer.playback_frame = Some(90.0);
+        buffer.release(Duration::from_secs_f32(1.0 / FPS));
+        assert!((buffer.playback_frame.unwrap() - 91.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn delayed_frames_are_released_at_the_game_pace() {
+        let mut buffer = DelayBuffer::new(10, 0.1);
+        for i in 100..=120 {
+            buffer.push(frame(i));
+        }
+        let tick = Duration::from_secs_f32(1.0 / FPS);
+        // playback starts 10 frames behind the first frame and can't pass frame 110
+        assert_eq!(buffer.release(Duration::ZERO).len(), 0);
+        assert_eq!(buffer.release(tick * 5).len(), 0);
+        assert_eq!(buffer.release(tick * 100).len(), 11);
+        assert_eq!(buffer.release(tick * 100).len(), 0);
+        buffer.push(frame(121));
+        assert_eq!(buffer.release(tick).len(), 1);
+    }
+
+    #[test]
+    fn only_left_over_sockets_are_replaced() {
+        let path = std::env::temp_dir().join(format!("box_game_file_{}", std::process::id()));
+        std::fs::write(&path, "notes").unwrap();
+        assert!(Observer::bind(&path, 1).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "notes");
+        std::fs::remove_file(&path).unwrap();
+
+        drop(Observer::bind(&path, 1).unwrap());
+        assert!(Observer::bind(&path, 1).is_ok());
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{block_list::BlockList, identity::PeerId, Error};
+
+// Opponent is what is remembered about someone this installation played against
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Opponent {
+    // the name they used last, names can change between matches
+    pub name: String,
+    pub matches: u32,
+    // seconds since the unix epoch
+    pub last_played: u64,
+}
+
+// Opponents are the installations this one played against, keyed by their peer id
+#[derive(Default, Serialize, Deserialize)]
+pub struct Opponents {
+    opponents: BTreeMap<PeerId, Opponent>,
+}
+
+impl Opponents {
+    // an empty list if nobody was played against yet
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(path).map_err(Error::read(path))?;
+        serde_json::from_str(&json).map_err(Error::corrupt(path, "opponents list"))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self).map_err(Error::encode("opponents list"))?;
+        fs::write(path, json).map_err(Error::write(path))
+    }
+
+    // counts a match against `id`, `now` is in seconds since the unix epoch
+    pub fn record(&mut self, id: PeerId, name: &str, now: u64) {
+        let opponent = self.opponents.entry(id).or_insert_with(|| Opponent {
+            name: String::new(),
+            matches: 0,
+            last_played: 0,
+        });
+        opponent.name = name.to_owned();
+        opponent.matches += 1;
+        opponent.last_played = now;
+    }
+
+    // the opponents played against most recently first
+    pub fn recent(&self) -> Vec<(&PeerId, &Opponent)> {
+        let mut recent: Vec<_> = self.opponents.iter().collect();
+        recent.sort_by_key(|(_, opponent)| std::cmp::Reverse(opponent.last_played));
+        recent
+    }
+}
+
+// counts a match against everyone met in a session
+pub fn remember(path: &Path, met: &BTreeMap<PeerId, String>) -> Result<(), Error> {
+    let mut opponents = Opponents::load(path)?;
+    let now = now();
+    for (id, name) in met {
+        opponents.record(*id, name, now);
+    }
+    opponents.save(path)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// prints the opponents played against most recently first
+pub fn print(path: &Path, block_list: &BlockList) -> Result<(), Error> {
+    let opponents = Opponents::load(path)?;
+    let now = now();
+    for (id, opponent) in opponents.recent() {
+        let days = now.saturating_sub(opponent.last_played) / (24 * 60 * 60);
+        let last_played = match days {
+            0 => "today".to_owned(),
+            1 => "yesterday".to_owned(),
+            days => format!("{} days ago", days),
+        };
+        let blocked = if block_list.contains(id) {
+            ", blocked"
+        } else {
+            ""
+        };
+        println!(
+            "{:<16}  {}  {} matches, last {}{}",
+            opponent.name,
+            id.short(),
+            opponent.matches,
+            last_played,
+            blocked
+        );
+    }
+    if opponents.opponents.is_empty() {
+        println!("No opponents yet");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opponents_are_kept_by_id_across_name_changes() {
+        let (ada, bob) = (PeerId([1; 16]), PeerId([2; 16]));
+        let mut opponents = Opponents::default();
+        opponents.record(ada, "Ada", 100);
+        opponents.record(bob, "Bob", 200);
+        opponents.record(ada, "Countess", 300);
+        let json = serde_json::to_string(&opponents).unwrap();
+        let opponents: Opponents = serde_json::from_str(&json).unwrap();
+        let recent = opponents.recent();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(*recent[0].0, ada);
+        assert_eq!(recent[0].1.name, "Countess");
+        assert_eq!(recent[0].1.matches, 2);
+        assert_eq!(*recent[1].0, bob);
+    }
+}
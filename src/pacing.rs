@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+// the niceness the game thread asks for, negative values are scheduled before normal threads
+#[cfg(unix)]
+const HIGH_PRIORITY_NICENESS: i32 = -10;
+// jitter estimates move by this fraction of every new deviation, as in RTP
+const JITTER_GAIN: f64 = 1.0 / 16.0;
+
+// lets the scheduler prefer the calling thread over background applications
+// lowering the niceness usually needs root or CAP_SYS_NICE
+#[cfg(unix)]
+pub fn raise_priority() -> Result<(), Error> {
+    // with 0, linux only changes the calling thread, other systems the whole process
+    let result = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, HIGH_PRIORITY_NICENESS) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn raise_priority() -> Result<(), Error> {
+    Err(Error::Unsupported("raising the priority"))
+}
+
+// keeps the calling thread on one core, so it isn't moved around between cores mid-frame
+pub fn pin_to_core(core: usize) -> Result<(), Error> {
+    let failed = |reason: String| Error::PinToCore { core, reason };
+    let cores = core_affinity::get_core_ids()
+        .ok_or_else(|| failed("the cores can't be listed".to_owned()))?;
+    let id = *cores
+        .get(core)
+        .ok_or_else(|| failed(format!("there are only {} cores", cores.len())))?;
+    if !core_affinity::set_for_current(id) {
+        return Err(failed("the system refused".to_owned()));
+    }
+    Ok(())
+}
+
+// Jitter estimates how much the time between rendered frames varies, which is what background
+// applications stealing the cpu show up as
+#[derive(Default)]
+pub struct Jitter {
+    last_frame: Option<Instant>,
+    last_interval: Option<Duration>,
+    jitter: f64,
+}
+
+impl Jitter {
+    pub fn finish_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            self.add_interval(now - last_frame);
+        }
+    }
+
+    fn add_interval(&mut self, interval: Duration) {
+        if let Some(last_interval) = self.last_interval.replace(interval) {
+            let deviation = (interval.as_secs_f64() - last_interval.as_secs_f64()).abs();
+            self.jitter += (deviation - self.jitter) * JITTER_GAIN;
+        }
+    }
+
+    pub fn get(&self) -> Duration {
+        Duration::from_secs_f64(self.jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter_grows_with_uneven_frames() {
+        let mut jitter = Jitter::default();
+        for _ in 0..100 {
+            jitter.add_interval(Duration::from_millis(16));
+        }
+        assert_eq!(jitter.get(), Duration::ZERO);
+        for i in 0..100 {
+            jitter.add_interval(Duration::from_millis(if i % 2 == 0 { 10 } else { 22 }));
+        }
+        let millis = jitter.get().as_secs_f64() * 1000.0;
+        assert!((11.0..12.0).contains(&millis), "{}", millis);
+    }
+}
@@ -0,0 +1,96 @@
+use directories::ProjectDirs;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::Error;
+
+// Paths are the directories the game keeps its files in, the platform's data directory
+// (e.g. `~/.local/share/box_game` or `%APPDATA%\box_game\data`) or, with `--portable`, the
+// directory of the executable
+pub struct Paths {
+    root: PathBuf,
+}
+
+impl Paths {
+    pub fn new(portable: bool) -> Result<Self, Error> {
+        let root = if portable {
+            let exe = std::env::current_exe()?;
+            exe.parent()
+                .ok_or(Error::NoDataDirectory("the executable has no directory"))?
+                .to_owned()
+        } else {
+            ProjectDirs::from("", "", "box_game")
+                .ok_or(Error::NoDataDirectory(
+                    "there is no home directory, try --portable",
+                ))?
+                .data_dir()
+                .to_owned()
+        };
+        Ok(Self { root })
+    }
+
+    fn dir(&self, name: &str) -> Result<PathBuf, Error> {
+        let dir = self.root.join(name);
+        fs::create_dir_all(&dir).map_err(Error::write(&dir))?;
+        Ok(dir)
+    }
+
+    // a file directly in the root directory
+    fn file(&self, name: &str) -> Result<PathBuf, Error> {
+        fs::create_dir_all(&self.root).map_err(Error::write(&self.root))?;
+        Ok(self.root.join(name))
+    }
+
+    // the settings written by the setup
+    pub fn config(&self) -> Result<PathBuf, Error> {
+        self.file("config.json")
+    }
+
+    // the peer id of this installation
+    pub fn identity(&self) -> Result<PathBuf, Error> {
+        self.file("peer_id")
+    }
+
+    // the players this installation played against
+    pub fn opponents(&self) -> Result<PathBuf, Error> {
+        self.file("opponents.json")
+    }
+
+    // the peers this installation never wants to hear from
+    pub fn block_list(&self) -> Result<PathBuf, Error> {
+        self.file("blocked.json")
+    }
+
+    // desync dumps and state snapshots
+    pub fn dumps(&self) -> Result<PathBuf, Error> {
+        self.dir("dumps")
+    }
+
+    // where a replay named on the command line is written, relative paths end up in the replays
+    // directory
+    pub fn replay_to_write(&self, path: &Path) -> Result<PathBuf, Error> {
+        if path.is_absolute() {
+            return Ok(path.to_owned());
+        }
+        Ok(self.dir("replays")?.join(path))
+    }
+
+    // where a replay named on the command line is read from, relative paths that don't exist are
+    // looked up in the replays directory
+    pub fn find_replay(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() || path.exists() {
+            return path.to_owned();
+        }
+        self.root.join("replays").join(path)
+    }
+
+    // where a dump named on the command line is read from, like replays from the dumps directory
+    pub fn find_dump(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() || path.exists() {
+            return path.to_owned();
+        }
+        self.root.join("dumps").join(path)
+    }
+}
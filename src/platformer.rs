@@ -0,0 +1,334 @@
+// a third game on the same netcode: players run and jump between platforms to collect coins
+// ships glide through open space, here every frame resolves collisions with solid platforms, so
+// a misprediction can put a player on another platform entirely
+// everything is measured in subpixels and simulated with integers only, so peers can't disagree
+use backroll::{Event, GameInput};
+use macroquad::prelude::*;
+
+use crate::{
+    game::{
+        player_color, Fnv1a64, Frame, FrameInputs, PlayerInput, CONTROL_SCHEMES, INPUT_LEFT,
+        INPUT_RIGHT, INPUT_UP, MAX_LOCAL_PLAYERS, MAX_PLAYERS, WINDOW_WIDTH,
+    },
+    rng::Rng,
+    rollback_game::RollbackGame,
+};
+
+const SUBPIXELS: i32 = 16;
+const COURT_WIDTH: i32 = WINDOW_WIDTH as i32 * SUBPIXELS;
+const PLAYER_SIZE: i32 = 32 * SUBPIXELS;
+const COIN_SIZE: i32 = 16 * SUBPIXELS;
+// coins float this high above their platform
+const COIN_HEIGHT: i32 = 8 * SUBPIXELS;
+const RUN_SPEED: i32 = 4 * SUBPIXELS;
+const GRAVITY: i32 = SUBPIXELS / 2;
+const JUMP_SPEED: i32 = 13 * SUBPIXELS;
+// nothing moves further than a player is high and a platform is thick in one frame, so players
+// can't pass through platforms
+const MAX_FALL_SPEED: i32 = 14 * SUBPIXELS;
+
+// x, y, width and height of the platforms in pixels, the first one is the floor
+const PLATFORMS: [(i32, i32, i32, i32); 7] = [
+    (0, 760, 600, 40),
+    (60, 620, 160, 16),
+    (380, 620, 160, 16),
+    (220, 480, 160, 16),
+    (40, 340, 140, 16),
+    (420, 340, 140, 16),
+    (230, 200, 140, 16),
+];
+
+// Rect is an axis aligned rectangle in subpixels
+#[derive(Clone, Copy)]
+struct Rect {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Rect {
+    fn platform(i: usize) -> Self {
+        let (x, y, width, height) = PLATFORMS[i];
+        Self {
+            x: x * SUBPIXELS,
+            y: y * SUBPIXELS,
+            width: width * SUBPIXELS,
+            height: height * SUBPIXELS,
+        }
+    }
+
+    fn player((x, y): (i32, i32)) -> Self {
+        Self {
+            x,
+            y,
+            width: PLAYER_SIZE,
+            height: PLAYER_SIZE,
+        }
+    }
+
+    fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.x + other.width
+            && self.x + self.width > other.x
+            && self.y < other.y + other.height
+            && self.y + self.height > other.y
+    }
+
+    fn draw(&self, color: Color) {
+        let pixels = |value: i32| value as f32 / SUBPIXELS as f32;
+        draw_rectangle(
+            pixels(self.x),
+            pixels(self.y),
+            pixels(self.width),
+            pixels(self.height),
+            color,
+        );
+    }
+}
+
+// PlatformerState is everything the simulation depends on
+#[derive(Clone, PartialEq)]
+pub struct PlatformerState {
+    pub frame: Frame,
+    pub num_players: usize,
+    // top left corners of the players, and how far they move every frame
+    pub positions: [(i32, i32); MAX_PLAYERS],
+    pub velocities: [(i32, i32); MAX_PLAYERS],
+    // players standing on a platform can jump
+    pub grounded: [bool; MAX_PLAYERS],
+    pub scores: [u32; MAX_PLAYERS],
+    // the platform the coin floats above, never the floor
+    pub coin: usize,
+    rng: Rng,
+}
+
+impl PlatformerState {
+    // all peers have to start with the same `seed` to stay in sync
+    pub fn new(num_players: usize, seed: u64) -> Self {
+        let floor = Rect::platform(0);
+        let mut state = Self {
+            frame: 0,
+            num_players,
+            positions: [(0, floor.y - PLAYER_SIZE); MAX_PLAYERS],
+            velocities: [(0, 0); MAX_PLAYERS],
+            grounded: [true; MAX_PLAYERS],
+            scores: [0; MAX_PLAYERS],
+            coin: 0,
+            rng: Rng::new(seed),
+        };
+        // players start spread out over the floor
+        for (i, (x, _)) in state.positions.iter_mut().enumerate() {
+            *x = (100 + 120 * i as i32) * SUBPIXELS;
+        }
+        state.move_coin();
+        state
+    }
+
+    // `inputs` holds the pressed buttons of every player, `None` for disconnected players
+    pub fn advance(&mut self, inputs: &[Option<u8>]) {
+        self.frame += 1;
+        for i in 0..self.num_players {
+            // missing and disconnected players stand still
+            let buttons = inputs.get(i).copied().flatten().unwrap_or(0);
+            self.advance_player(i, buttons);
+        }
+        // players are moved in order, but they don't touch each other, so the order doesn't matter
+        let coin = self.coin_rect();
+        let collected: Vec<usize> = (0..self.num_players)
+            .filter(|i| Rect::player(self.positions[*i]).overlaps(&coin))
+            .collect();
+        for i in collected.iter() {
+            self.scores[*i] += 1;
+        }
+        if !collected.is_empty() {
+            self.move_coin();
+        }
+    }
+
+    // hashes every field, the state is small enough to not bother about the order
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = Fnv1a64::new();
+        hasher.write(&self.frame.to_le_bytes());
+        hasher.write(&(self.num_players as u64).to_le_bytes());
+        for i in 0..MAX_PLAYERS {
+            let (x, y) = self.positions[i];
+            let (velocity_x, velocity_y) = self.velocities[i];
+            for value in [x, y, velocity_x, velocity_y] {
+                hasher.write(&value.to_le_bytes());
+            }
+            hasher.write(&[self.grounded[i] as u8]);
+            hasher.write(&self.scores[i].to_le_bytes());
+        }
+        hasher.write(&(self.coin as u64).to_le_bytes());
+        hasher.write(&self.rng.state().to_le_bytes());
+        hasher.finish()
+    }
+
+    // moves a player first sideways and then up or down, pushing it out of the platforms it ran
+    // into along each axis
+    fn advance_player(&mut self, player: usize, buttons: u8) {
+        let (mut x, mut y) = self.positions[player];
+        let direction = (buttons & INPUT_RIGHT != 0) as i32 - (buttons & INPUT_LEFT != 0) as i32;
+        let velocity_x = direction * RUN_SPEED;
+        let mut velocity_y = (self.velocities[player].1 + GRAVITY).min(MAX_FALL_SPEED);
+        if buttons & INPUT_UP != 0 && self.grounded[player] {
+            velocity_y = -JUMP_SPEED;
+        }
+
+        x = (x + velocity_x).clamp(0, COURT_WIDTH - PLAYER_SIZE);
+        for platform in (0..PLATFORMS.len()).map(Rect::platform) {
+            if Rect::player((x, y)).overlaps(&platform) {
+                x = match velocity_x > 0 {
+                    true => platform.x - PLAYER_SIZE,
+                    false => platform.x + platform.width,
+                };
+            }
+        }
+
+        y += velocity_y;
+        let mut grounded = false;
+        for platform in (0..PLATFORMS.len()).map(Rect::platform) {
+            if Rect::player((x, y)).overlaps(&platform) {
+                if velocity_y > 0 {
+                    y = platform.y - PLAYER_SIZE;
+                    grounded = true;
+                } else {
+                    // bumped their head
+                    y = platform.y + platform.height;
+                }
+                velocity_y = 0;
+            }
+        }
+
+        self.positions[player] = (x, y);
+        self.velocities[player] = (velocity_x, velocity_y);
+        self.grounded[player] = grounded;
+    }
+
+    fn coin_rect(&self) -> Rect {
+        let platform = Rect::platform(self.coin);
+        Rect {
+            x: platform.x + (platform.width - COIN_SIZE) / 2,
+            y: platform.y - COIN_HEIGHT - COIN_SIZE,
+            width: COIN_SIZE,
+            height: COIN_SIZE,
+        }
+    }
+
+    // puts the coin above another platform than the one it was above
+    fn move_coin(&mut self) {
+        let others: Vec<usize> = (1..PLATFORMS.len()).filter(|i| *i != self.coin).collect();
+        self.coin = others[self.rng.next_u32() as usize % others.len()];
+    }
+}
+
+// Platformer plays a `PlatformerState` in a session
+pub struct Platformer {
+    state: PlatformerState,
+    // the state before the last frame, rendering interpolates between the two
+    previous: PlatformerState,
+}
+
+impl RollbackGame for Platformer {
+    type State = PlatformerState;
+    // there is nothing to agree on besides the seed
+    type Rules = ();
+
+    fn new(num_players: usize, _rules: (), seed: u64) -> Self {
+        let state = PlatformerState::new(num_players.min(MAX_PLAYERS), seed);
+        Self {
+            previous: state.clone(),
+            state,
+        }
+    }
+
+    fn advance(&mut self, inputs: GameInput<PlayerInput>) {
+        let inputs = FrameInputs::new(self.state.frame + 1, self.state.num_players, &inputs);
+        self.previous = self.state.clone();
+        self.state.advance(&inputs.buttons_pressed);
+    }
+
+    fn save(&mut self) -> (PlatformerState, u64) {
+        (self.state.clone(), self.state.checksum())
+    }
+
+    fn load(&mut self, state: PlatformerState) {
+        self.state = state;
+    }
+
+    fn render(&mut self, alpha: f32) {
+        let (previous, state) = (&self.previous, &self.state);
+        let lerp = |from: i32, to: i32| from + ((to - from) as f32 * alpha) as i32;
+        clear_background(BLACK);
+        for i in 0..PLATFORMS.len() {
+            Rect::platform(i).draw(GRAY);
+        }
+        state.coin_rect().draw(GOLD);
+        for i in 0..state.num_players {
+            let (x, y) = state.positions[i];
+            let (previous_x, previous_y) = previous.positions[i];
+            Rect::player((lerp(previous_x, x), lerp(previous_y, y))).draw(player_color(i));
+            let score = state.scores[i].to_string();
+            draw_text(&score, 20.0 + 60.0 * i as f32, 40.0, 40.0, player_color(i));
+        }
+    }
+
+    // players sharing the keyboard play with WASD, and with the arrow keys
+    fn local_input(&self, local_index: usize) -> PlayerInput {
+        let [up, left, _, right] = CONTROL_SCHEMES[local_index % MAX_LOCAL_PLAYERS];
+        let mut buttons_pressed = 0;
+        if is_key_down(up) {
+            buttons_pressed |= INPUT_UP;
+        }
+        if is_key_down(left) {
+            buttons_pressed |= INPUT_LEFT;
+        }
+        if is_key_down(right) {
+            buttons_pressed |= INPUT_RIGHT;
+        }
+        PlayerInput { buttons_pressed }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        println!("Event: {:?}", event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn players_collide_with_platforms_and_collect_coins() {
+        let mut state = PlatformerState::new(1, 0);
+        let floor = Rect::platform(0);
+        // dropped between the platforms, the player lands on the floor
+        state.positions[0] = (300 * SUBPIXELS, 550 * SUBPIXELS);
+        state.grounded[0] = false;
+        for _ in 0..60 {
+            state.advance(&[Some(0)]);
+        }
+        assert_eq!(state.positions[0].1, floor.y - PLAYER_SIZE);
+        assert!(state.grounded[0]);
+
+        // jumping right below a platform bumps into it from below
+        let platform = Rect::platform(1);
+        state.positions[0].0 = platform.x;
+        let mut highest = state.positions[0].1;
+        for _ in 0..60 {
+            state.advance(&[Some(INPUT_UP)]);
+            highest = highest.min(state.positions[0].1);
+        }
+        assert_eq!(highest, platform.y + platform.height);
+
+        // touching the coin scores and moves it somewhere else
+        let coin = state.coin;
+        let below = Rect::platform(coin);
+        state.positions[0] = (state.coin_rect().x, below.y - PLAYER_SIZE);
+        state.velocities[0] = (0, 0);
+        state.advance(&[Some(0)]);
+        assert_eq!(state.scores[0], 1);
+        assert_ne!(state.coin, coin);
+        assert_ne!(state.coin, 0);
+    }
+}
@@ -0,0 +1,317 @@
+// a second game on the same netcode: two paddles and a ball, small enough to read in one go and
+// to compare rollbacks of a tiny state with the ones of the box game
+// everything is measured in subpixels and simulated with integers only, so peers can't disagree
+use backroll::{Event, GameInput};
+use macroquad::prelude::*;
+
+use crate::{
+    game::{
+        player_color, Fnv1a64, Frame, FrameInputs, PlayerInput, CONTROL_SCHEMES, FPS, INPUT_LEFT,
+        INPUT_RIGHT, MAX_LOCAL_PLAYERS, WINDOW_HEIGHT, WINDOW_WIDTH,
+    },
+    rng::Rng,
+    rollback_game::RollbackGame,
+};
+
+pub const PONG_PLAYERS: usize = 2;
+
+const SUBPIXELS: i32 = 16;
+const COURT_WIDTH: i32 = WINDOW_WIDTH as i32 * SUBPIXELS;
+const COURT_HEIGHT: i32 = WINDOW_HEIGHT as i32 * SUBPIXELS;
+const PADDLE_WIDTH: i32 = 90 * SUBPIXELS;
+const PADDLE_HEIGHT: i32 = 12 * SUBPIXELS;
+// distance of the paddles from the end of the court they defend
+const PADDLE_MARGIN: i32 = 40 * SUBPIXELS;
+const PADDLE_SPEED: i32 = 7 * SUBPIXELS;
+const BALL_SIZE: i32 = 12 * SUBPIXELS;
+const SERVE_SPEED: i32 = 5 * SUBPIXELS;
+// every return speeds the ball up a little, it never moves further than a paddle and the ball are
+// high in a frame, so it can't pass through a paddle
+const SPEEDUP: i32 = SUBPIXELS / 2;
+const MAX_BALL_SPEED: i32 = 14 * SUBPIXELS;
+// the sideways speed of a ball hit with the very edge of a paddle
+const MAX_SIDEWAYS_SPEED: i32 = 6 * SUBPIXELS;
+// the ball waits in the middle for a second before every serve
+const SERVE_FRAMES: Frame = FPS as Frame;
+
+// PongState is everything the simulation depends on
+// player 0 defends the bottom of the court and player 1 the top
+#[derive(Clone, PartialEq)]
+pub struct PongState {
+    pub frame: Frame,
+    pub num_players: usize,
+    // left edges of the paddles
+    pub paddles: [i32; PONG_PLAYERS],
+    // top left corner of the ball, and how far it moves every frame
+    pub ball: (i32, i32),
+    pub velocity: (i32, i32),
+    pub scores: [u32; PONG_PLAYERS],
+    // the ball is served on this frame
+    pub serve_frame: Frame,
+    rng: Rng,
+}
+
+impl PongState {
+    // all peers have to start with the same `seed` to stay in sync
+    pub fn new(num_players: usize, seed: u64) -> Self {
+        let mut state = Self {
+            frame: 0,
+            num_players,
+            paddles: [(COURT_WIDTH - PADDLE_WIDTH) / 2; PONG_PLAYERS],
+            ball: (0, 0),
+            velocity: (0, 0),
+            scores: [0; PONG_PLAYERS],
+            serve_frame: 0,
+            rng: Rng::new(seed),
+        };
+        let receiver = (state.rng.next_u32() % PONG_PLAYERS as u32) as usize;
+        state.serve(receiver);
+        state
+    }
+
+    // `inputs` holds the pressed buttons of every player, `None` for disconnected players
+    pub fn advance(&mut self, inputs: &[Option<u8>]) {
+        self.frame += 1;
+        for i in 0..PONG_PLAYERS {
+            // missing and disconnected players are played by following the ball
+            let direction = match inputs.get(i).copied().flatten() {
+                Some(buttons) => {
+                    (buttons & INPUT_RIGHT != 0) as i32 - (buttons & INPUT_LEFT != 0) as i32
+                }
+                None => self.follow_ball(i),
+            };
+            self.paddles[i] =
+                (self.paddles[i] + direction * PADDLE_SPEED).clamp(0, COURT_WIDTH - PADDLE_WIDTH);
+        }
+        if self.frame < self.serve_frame {
+            return;
+        }
+
+        self.ball.0 += self.velocity.0;
+        self.ball.1 += self.velocity.1;
+        // bounce off the side walls
+        if self.ball.0 < 0 {
+            self.ball.0 = -self.ball.0;
+            self.velocity.0 = -self.velocity.0;
+        } else if self.ball.0 > COURT_WIDTH - BALL_SIZE {
+            self.ball.0 = 2 * (COURT_WIDTH - BALL_SIZE) - self.ball.0;
+            self.velocity.0 = -self.velocity.0;
+        }
+        for i in 0..PONG_PLAYERS {
+            if self.hits(i) {
+                self.return_ball(i);
+            }
+        }
+
+        // a ball leaving the court is a point for the player at the other end
+        if self.ball.1 > COURT_HEIGHT {
+            self.scores[1] += 1;
+            self.serve(0);
+        } else if self.ball.1 + BALL_SIZE < 0 {
+            self.scores[0] += 1;
+            self.serve(1);
+        }
+    }
+
+    // hashes every field, the state is small enough to not bother about the order
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = Fnv1a64::new();
+        hasher.write(&self.frame.to_le_bytes());
+        hasher.write(&(self.num_players as u64).to_le_bytes());
+        for paddle in self.paddles {
+            hasher.write(&paddle.to_le_bytes());
+        }
+        for value in [self.ball.0, self.ball.1, self.velocity.0, self.velocity.1] {
+            hasher.write(&value.to_le_bytes());
+        }
+        for score in self.scores {
+            hasher.write(&score.to_le_bytes());
+        }
+        hasher.write(&self.serve_frame.to_le_bytes());
+        hasher.write(&self.rng.state().to_le_bytes());
+        hasher.finish()
+    }
+
+    // the top edge of the paddle of `player`
+    fn paddle_y(player: usize) -> i32 {
+        match player {
+            0 => COURT_HEIGHT - PADDLE_MARGIN - PADDLE_HEIGHT,
+            _ => PADDLE_MARGIN,
+        }
+    }
+
+    // puts the ball in the middle, it flies towards `receiver` once it is served
+    fn serve(&mut self, receiver: usize) {
+        self.ball = (
+            (COURT_WIDTH - BALL_SIZE) / 2,
+            (COURT_HEIGHT - BALL_SIZE) / 2,
+        );
+        // a random sideways drift, so rallies don't all start the same
+        let drift = (self.rng.next_u32() % 7) as i32 - 3;
+        let speed = if receiver == 0 {
+            SERVE_SPEED
+        } else {
+            -SERVE_SPEED
+        };
+        self.velocity = (drift * SUBPIXELS / 2, speed);
+        self.serve_frame = self.frame + SERVE_FRAMES;
+    }
+
+    // whether the ball flies into the paddle of `player`
+    fn hits(&self, player: usize) -> bool {
+        let towards = match player {
+            0 => self.velocity.1 > 0,
+            _ => self.velocity.1 < 0,
+        };
+        let (x, y) = (self.paddles[player], Self::paddle_y(player));
+        towards
+            && self.ball.0 < x + PADDLE_WIDTH
+            && self.ball.0 + BALL_SIZE > x
+            && self.ball.1 < y + PADDLE_HEIGHT
+            && self.ball.1 + BALL_SIZE > y
+    }
+
+    fn return_ball(&mut self, player: usize) {
+        let speed = (self.velocity.1.abs() + SPEEDUP).min(MAX_BALL_SPEED);
+        let y = Self::paddle_y(player);
+        // put the ball in front of the paddle, so it isn't hit again on the next frame
+        (self.velocity.1, self.ball.1) = match player {
+            0 => (-speed, y - BALL_SIZE),
+            _ => (speed, y + PADDLE_HEIGHT),
+        };
+        // hitting the ball off center sends it sideways
+        let offset = (self.ball.0 + BALL_SIZE / 2) - (self.paddles[player] + PADDLE_WIDTH / 2);
+        self.velocity.0 = offset * MAX_SIDEWAYS_SPEED / ((PADDLE_WIDTH + BALL_SIZE) / 2);
+    }
+
+    // -1 to move the paddle of `player` left towards the ball, 1 to move it right
+    fn follow_ball(&self, player: usize) -> i32 {
+        let distance = (self.ball.0 + BALL_SIZE / 2) - (self.paddles[player] + PADDLE_WIDTH / 2);
+        if distance.abs() < PADDLE_SPEED {
+            0
+        } else {
+            distance.signum()
+        }
+    }
+}
+
+// Pong plays a `PongState` in a session
+pub struct Pong {
+    state: PongState,
+    // the state before the last frame, rendering interpolates between the two
+    previous: PongState,
+}
+
+impl RollbackGame for Pong {
+    type State = PongState;
+    // there is nothing to agree on besides the seed
+    type Rules = ();
+
+    fn new(num_players: usize, _rules: (), seed: u64) -> Self {
+        let state = PongState::new(num_players.min(PONG_PLAYERS), seed);
+        Self {
+            previous: state.clone(),
+            state,
+        }
+    }
+
+    fn advance(&mut self, inputs: GameInput<PlayerInput>) {
+        let inputs = FrameInputs::new(self.state.frame + 1, self.state.num_players, &inputs);
+        self.previous = self.state.clone();
+        self.state.advance(&inputs.buttons_pressed);
+    }
+
+    fn save(&mut self) -> (PongState, u64) {
+        (self.state.clone(), self.state.checksum())
+    }
+
+    fn load(&mut self, state: PongState) {
+        self.state = state;
+    }
+
+    fn render(&mut self, alpha: f32) {
+        let lerp =
+            |from: i32, to: i32| (from as f32 + (to - from) as f32 * alpha) / SUBPIXELS as f32;
+        let (previous, state) = (&self.previous, &self.state);
+        clear_background(BLACK);
+        let middle = WINDOW_HEIGHT / 2.0;
+        draw_line(0.0, middle, WINDOW_WIDTH, middle, 2.0, DARKGRAY);
+
+        for i in 0..PONG_PLAYERS {
+            let x = lerp(previous.paddles[i], state.paddles[i]);
+            let y = PongState::paddle_y(i) as f32 / SUBPIXELS as f32;
+            let (width, height) = (PADDLE_WIDTH / SUBPIXELS, PADDLE_HEIGHT / SUBPIXELS);
+            draw_rectangle(x, y, width as f32, height as f32, player_color(i));
+            // scores are shown on the side of the court of their player
+            let score_y = match i {
+                0 => middle + 60.0,
+                _ => middle - 30.0,
+            };
+            let score = state.scores[i].to_string();
+            draw_text(&score, 20.0, score_y, 80.0, player_color(i));
+        }
+
+        let size = (BALL_SIZE / SUBPIXELS) as f32;
+        let x = lerp(previous.ball.0, state.ball.0);
+        let y = lerp(previous.ball.1, state.ball.1);
+        draw_rectangle(x, y, size, size, WHITE);
+    }
+
+    // players sharing the keyboard play with A and D, and with the arrow keys
+    fn local_input(&self, local_index: usize) -> PlayerInput {
+        let [_, left, _, right] = CONTROL_SCHEMES[local_index % MAX_LOCAL_PLAYERS];
+        let mut buttons_pressed = 0;
+        if is_key_down(left) {
+            buttons_pressed |= INPUT_LEFT;
+        }
+        if is_key_down(right) {
+            buttons_pressed |= INPUT_RIGHT;
+        }
+        PlayerInput { buttons_pressed }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        println!("Event: {:?}", event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // advances until the ball reaches one of the ends of the court
+    fn play_out(state: &mut PongState, inputs: &[Option<u8>]) {
+        let scores = state.scores;
+        while state.scores == scores {
+            state.advance(inputs);
+            assert!(state.frame < 10 * SERVE_FRAMES, "the ball got stuck");
+        }
+    }
+
+    #[test]
+    fn balls_are_returned_or_scored() {
+        let mut state = PongState::new(PONG_PLAYERS, 0);
+        // the ball flies straight at the middle of the bottom paddle and comes back
+        state.ball.0 = (COURT_WIDTH - BALL_SIZE) / 2;
+        state.velocity = (0, SERVE_SPEED);
+        while state.velocity.1 > 0 {
+            state.advance(&[Some(0), Some(0)]);
+        }
+        assert_eq!(state.velocity, (0, -SERVE_SPEED - SPEEDUP));
+
+        // the ball flies past the top paddle, which doesn't move
+        state.ball.0 = 0;
+        state.velocity = (0, -SERVE_SPEED);
+        play_out(&mut state, &[Some(0), Some(0)]);
+        assert_eq!(state.scores, [1, 0]);
+        // and is served to the player who missed it
+        assert!(state.velocity.1 < 0);
+        assert_eq!(state.serve_frame, state.frame + SERVE_FRAMES);
+
+        // the checksum covers the whole state
+        let mut other = state.clone();
+        assert_eq!(state.checksum(), other.checksum());
+        other.advance(&[None, None]);
+        assert_ne!(state.checksum(), other.checksum());
+    }
+}
@@ -0,0 +1,109 @@
+use igd_next::{Gateway, PortMappingProtocol, SearchOptions};
+use macroquad::prelude::*;
+use std::{
+    fmt::Display,
+    net::{SocketAddr, UdpSocket},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use crate::{transport::Transport, Error};
+
+// routers that don't answer within this time probably don't speak UPnP
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(3);
+// the mapping expires on its own after this many seconds if the game crashes, it is renewed
+// every time the game starts
+const LEASE_DURATION: u32 = 2 * 60 * 60;
+const DESCRIPTION: &str = "Box Game";
+
+// PortMapping asks the router to forward the local port of the game to this machine over UPnP,
+// so home users don't have to set up port forwarding themselves
+// the router is searched in the background, and the mapping is removed again when dropped
+pub struct PortMapping {
+    receiver: Receiver<(Gateway, SocketAddr)>,
+    mapped: Option<(Gateway, SocketAddr)>,
+    protocol: PortMappingProtocol,
+}
+
+impl PortMapping {
+    pub fn start(local_port: u16, transport: Transport) -> Self {
+        let protocol = match transport {
+            Transport::Udp => PortMappingProtocol::UDP,
+            Transport::Tcp => PortMappingProtocol::TCP,
+        };
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || match map_port(local_port, protocol) {
+            Ok((gateway, external)) => {
+                println!("Other players can connect to {}", external);
+                let _ = sender.send((gateway, external));
+            }
+            Err(e) => println!("Failed to map the port with UPnP: {}", e),
+        });
+        Self {
+            receiver,
+            mapped: None,
+            protocol,
+        }
+    }
+
+    // shows the external address below the checksums once the mapping exists
+    pub fn draw(&mut self) {
+        if let Ok(mapped) = self.receiver.try_recv() {
+            self.mapped = Some(mapped);
+        }
+        if let Some((_, external)) = &self.mapped {
+            let text = format!("Reachable at {}", external);
+            draw_text(&text, 20.0, 60.0, 20.0, GRAY);
+        }
+    }
+}
+
+impl Drop for PortMapping {
+    fn drop(&mut self) {
+        if let Ok(mapped) = self.receiver.try_recv() {
+            self.mapped = Some(mapped);
+        }
+        if let Some((gateway, external)) = &self.mapped {
+            if let Err(e) = gateway.remove_port(self.protocol, external.port()) {
+                println!("Failed to remove the port mapping: {}", e);
+            }
+        }
+    }
+}
+
+// maps the same port on the router to `local_port`, peers can then use the port they already know
+fn map_port(
+    local_port: u16,
+    protocol: PortMappingProtocol,
+) -> Result<(Gateway, SocketAddr), Error> {
+    // every step fails with its own error type
+    fn failed<E: Display>(step: &'static str) -> impl FnOnce(E) -> Error {
+        move |e| Error::PortMapping {
+            step,
+            reason: e.to_string(),
+        }
+    }
+    let gateway = igd_next::search_gateway(SearchOptions {
+        timeout: Some(SEARCH_TIMEOUT),
+        ..Default::default()
+    })
+    .map_err(failed("searching the router"))?;
+    // the router forwards to the address we reach it from
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    socket.connect(gateway.addr)?;
+    let local_addr = SocketAddr::new(socket.local_addr()?.ip(), local_port);
+    gateway
+        .add_port(
+            protocol,
+            local_port,
+            local_addr,
+            LEASE_DURATION,
+            DESCRIPTION,
+        )
+        .map_err(failed("adding the port"))?;
+    let external_ip = gateway
+        .get_external_ip()
+        .map_err(failed("asking for the external ip"))?;
+    let external = SocketAddr::new(external_ip, local_port);
+    Ok((gateway, external))
+}
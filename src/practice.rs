@@ -0,0 +1,182 @@
+use macroquad::prelude::*;
+use std::path::Path;
+
+use crate::{
+    config::Config,
+    game::{self, Frame, Game, GameState, Tuning},
+    input_script::InputScript,
+    rng,
+    rollback_game::FixedStep,
+    Error,
+};
+
+// the ship steered by the player and the ghost replaying their inputs
+const PLAYER: usize = 0;
+const GHOST: usize = 1;
+
+// GhostStart is where the ghost is put back to whenever its loop starts over
+#[derive(Clone, Copy)]
+struct GhostStart {
+    position: (f32, f32),
+    velocity: (f32, f32),
+    rotation: f32,
+    hull: u8,
+}
+
+impl GhostStart {
+    fn of(state: &GameState, player: usize) -> Self {
+        Self {
+            position: state.positions[player],
+            velocity: state.velocities[player],
+            rotation: state.rotations[player],
+            hull: state.hull[player],
+        }
+    }
+}
+
+// Practice is what is being done with the ghost loop
+enum Practice {
+    Idle,
+    // the inputs of the player since `start`, the ghost stands still meanwhile
+    Recording {
+        script: InputScript,
+        start: GhostStart,
+        frames: Frame,
+    },
+    // the ghost replays the script from `start`, `frame` is the position in the loop
+    Looping {
+        script: InputScript,
+        start: GhostStart,
+        frame: Frame,
+    },
+}
+
+// plays offline against a ghost ship that loops inputs you recorded, as a moving target
+// R starts and stops recording, a script loaded from `script_path` loops right away and the last
+// recorded loop is written back to it when the window is closed
+pub async fn run(
+    state: GameState,
+    config: Option<Config>,
+    script_path: Option<&Path>,
+) -> Result<(), Error> {
+    let mut game = Game::from_state(state);
+    if let Some(config) = config {
+        game.set_control_scheme(config.controls);
+        game.set_toggle_thrust(config.toggle_thrust);
+    }
+    game.warm_up();
+    game::cache_glyphs();
+    let mut practice = match script_path.filter(|path| path.exists()) {
+        Some(path) => Practice::Looping {
+            script: InputScript::load(path)?,
+            start: GhostStart::of(game.state(), GHOST),
+            frame: 0,
+        },
+        None => Practice::Idle,
+    };
+
+    // time variables for tick rate
+    let mut step = FixedStep::default();
+    let tick = game.state().tuning.tick_duration();
+
+    prevent_quit();
+    while !is_quit_requested() {
+        // F2 starts the match over with a fresh seed, a looping ghost starts its loop over with it
+        if is_key_pressed(KeyCode::F2) {
+            game.restart(rng::time_seed());
+            practice = match practice {
+                Practice::Looping { script, .. } => Practice::Looping {
+                    script,
+                    start: GhostStart::of(game.state(), GHOST),
+                    frame: 0,
+                },
+                _ => Practice::Idle,
+            };
+        }
+        if is_key_pressed(KeyCode::R) {
+            practice = match practice {
+                Practice::Recording { script, start, .. } => Practice::Looping {
+                    script,
+                    start,
+                    frame: 0,
+                },
+                _ => Practice::Recording {
+                    script: InputScript::default(),
+                    start: GhostStart::of(game.state(), PLAYER),
+                    frames: 0,
+                },
+            };
+        }
+
+        // get delta time from last iteration and accumulate it
+        step.accumulate();
+        let buttons_pressed = game.local_input(0).buttons_pressed;
+
+        // if enough time is accumulated, we run a frame
+        while step.tick(tick) {
+            let ghost_buttons = match &mut practice {
+                Practice::Idle => 0,
+                Practice::Recording { script, frames, .. } => {
+                    *frames += 1;
+                    script.record(*frames, vec![buttons_pressed]);
+                    0
+                }
+                Practice::Looping { script, frame, .. } => {
+                    *frame += 1;
+                    match script.buttons(*frame, 0) {
+                        Some(buttons) => buttons,
+                        None => {
+                            *frame = 1;
+                            script.buttons(*frame, 0).unwrap_or(0)
+                        }
+                    }
+                }
+            };
+            // every loop starts from the same spot, so it plays out the same way
+            if let Practice::Looping {
+                frame: 1, start, ..
+            } = &practice
+            {
+                reset_ghost(&mut game, start);
+            }
+            game.advance_offline(vec![Some(buttons_pressed), Some(ghost_buttons)]);
+        }
+
+        let alpha = step.alpha(tick);
+        game.render(alpha);
+        draw_status(&practice, &game.state().tuning);
+        step.draw_catch_up();
+        next_frame().await;
+    }
+
+    if let (Some(path), Practice::Looping { script, .. }) = (script_path, &practice) {
+        script.save(path)?;
+    }
+    Ok(())
+}
+
+fn reset_ghost(game: &mut Game, start: &GhostStart) {
+    let mut state = game.state().clone();
+    state.positions[GHOST] = start.position;
+    state.velocities[GHOST] = start.velocity;
+    state.rotations[GHOST] = start.rotation;
+    state.hull[GHOST] = start.hull;
+    game.load_state(state);
+}
+
+fn draw_status(practice: &Practice, tuning: &Tuning) {
+    let status = match practice {
+        Practice::Idle => {
+            "Practice: press R to record a loop for the ghost, F2 to restart".to_owned()
+        }
+        Practice::Recording { frames, .. } => format!(
+            "Recording {:.1}s, press R to let the ghost loop it",
+            tuning.seconds(*frames)
+        ),
+        Practice::Looping { frame, .. } => format!(
+            "Ghost looping at {:.1}s, press R to record a new loop",
+            tuning.seconds(*frame)
+        ),
+    };
+    draw_text(&status, 20.0, screen_height() - 60.0, 24.0, YELLOW);
+}
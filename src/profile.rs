@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+// time spent on one kind of work and how often it was done
+#[derive(Clone, Copy, Default)]
+struct Timing {
+    total: Duration,
+    count: u32,
+}
+
+impl Timing {
+    fn add(&mut self, duration: Duration) {
+        self.total += duration;
+        self.count += 1;
+    }
+}
+
+// SessionProfile sums up where the simulation time of a session went
+#[derive(Default)]
+pub struct SessionProfile {
+    advance: Timing,
+    resimulation: Timing,
+    save: Timing,
+    load: Timing,
+}
+
+impl SessionProfile {
+    pub fn add_advance(&mut self, duration: Duration, resimulation: bool) {
+        if resimulation {
+            self.resimulation.add(duration);
+        } else {
+            self.advance.add(duration);
+        }
+    }
+
+    pub fn add_save(&mut self, duration: Duration) {
+        self.save.add(duration);
+    }
+
+    pub fn add_load(&mut self, duration: Duration) {
+        self.load.add(duration);
+    }
+
+    // prints the share of each kind of work, to see which one is worth optimizing
+    pub fn print_report(&self) {
+        let timings = [
+            ("advance", self.advance),
+            ("resimulation", self.resimulation),
+            ("save", self.save),
+            ("load", self.load),
+        ];
+        let total: Duration = timings.iter().map(|(_, timing)| timing.total).sum();
+        if total.is_zero() {
+            return;
+        }
+        println!("Simulation time: {:.1} ms", total.as_secs_f64() * 1000.0);
+        for (name, timing) in timings {
+            let average = timing.total.checked_div(timing.count).unwrap_or_default();
+            println!(
+                "  {:<13}{:>5.1}% {:>8} times, {:>6.1} us each",
+                name,
+                timing.total.as_secs_f64() / total.as_secs_f64() * 100.0,
+                timing.count,
+                average.as_secs_f64() * 1_000_000.0
+            );
+        }
+        if self.resimulation.count > 0 {
+            println!(
+                "Resimulation throughput: {:.0} frames/s",
+                self.resimulation.count as f64 / self.resimulation.total.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,516 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    game::{Frame, Game, GameState, Tuning},
+    rollback_game::FixedStep,
+    Error,
+};
+
+// a full snapshot of the state is kept every `KEYFRAME_INTERVAL` frames
+const KEYFRAME_INTERVAL: Frame = 600;
+
+// Replay holds everything needed to resimulate a match: the state it started from and the
+// inputs of every frame after that
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct Replay {
+    pub initial_state: GameState,
+    // `inputs[i]` advances the game from frame `initial_state.frame + i` to the next one
+    pub inputs: Vec<Vec<Option<u8>>>,
+    // snapshots to start resimulating from without going back to the initial state
+    pub keyframes: Vec<GameState>,
+}
+
+impl Replay {
+    pub fn new(initial_state: GameState) -> Self {
+        Self {
+            initial_state,
+            inputs: Vec::new(),
+            keyframes: Vec::new(),
+        }
+    }
+
+    #[cfg(not(feature = "rkyv"))]
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let bytes = fs::read(path).map_err(Error::read(path))?;
+        bincode::deserialize(&bytes).map_err(Error::corrupt(path, "replay"))
+    }
+
+    #[cfg(not(feature = "rkyv"))]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        bincode::serialize(self).map_err(Error::encode("replay"))
+    }
+
+    // with rkyv, replays are stored in its archive format and validated when loaded
+    #[cfg(feature = "rkyv")]
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        // archives have to be aligned in memory
+        let mut bytes = rkyv::AlignedVec::new();
+        bytes.extend_from_slice(&fs::read(path).map_err(Error::read(path))?);
+        rkyv::from_bytes(&bytes).map_err(Error::corrupt(path, "replay"))
+    }
+
+    #[cfg(feature = "rkyv")]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let bytes = rkyv::to_bytes::<_, 4096>(self).map_err(Error::encode("replay"))?;
+        Ok(bytes.to_vec())
+    }
+
+    // replays are saved in the same format they are uploaded in
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, self.to_bytes()?).map_err(Error::write(path))
+    }
+
+    // records the inputs used to advance to `state`
+    // after a rollback, everything recorded for later frames was mispredicted and is thrown away
+    pub fn record(&mut self, inputs: &[Option<u8>], state: &GameState) {
+        self.truncate(state.frame - 1);
+        self.inputs.push(inputs.to_vec());
+        if state.frame % KEYFRAME_INTERVAL == 0 {
+            self.keyframes.push(state.clone());
+        }
+    }
+
+    // makes room for `frames` more frames, so recording doesn't allocate until they are used up
+    pub fn reserve(&mut self, frames: usize) {
+        self.inputs.reserve(frames);
+        self.keyframes
+            .reserve(frames / KEYFRAME_INTERVAL as usize + 1);
+    }
+
+    // throws away everything after `frame`
+    pub fn truncate(&mut self, frame: Frame) {
+        let len = (frame - self.initial_state.frame).max(0) as usize;
+        self.inputs.truncate(len);
+        self.keyframes.retain(|keyframe| keyframe.frame <= frame);
+    }
+
+    pub fn first_frame(&self) -> Frame {
+        self.initial_state.frame
+    }
+
+    pub fn last_frame(&self) -> Frame {
+        self.initial_state.frame + self.inputs.len() as Frame
+    }
+
+    // returns the inputs used to advance to `frame`
+    pub fn inputs_to(&self, frame: Frame) -> &[Option<u8>] {
+        &self.inputs[(frame - self.initial_state.frame - 1) as usize]
+    }
+
+    // returns the latest snapshot at or before `frame`
+    fn keyframe_before(&self, frame: Frame) -> &GameState {
+        self.keyframes
+            .iter()
+            .rev()
+            .find(|keyframe| keyframe.frame <= frame)
+            .unwrap_or(&self.initial_state)
+    }
+
+    // the part of the replay from the closest snapshot before `start_frame` to `end_frame`
+    fn trimmed(&self, start_frame: Frame, end_frame: Frame) -> Result<Self, Error> {
+        // replays of sessions joined late or restored from a snapshot don't start at frame 0
+        if start_frame < self.first_frame()
+            || start_frame > end_frame
+            || end_frame > self.last_frame()
+        {
+            return Err(Error::FramesOutOfRange {
+                start: start_frame,
+                end: end_frame,
+                first: self.first_frame(),
+                last: self.last_frame(),
+            });
+        }
+
+        let mut trimmed = Replay::new(self.keyframe_before(start_frame).clone());
+        let first = (trimmed.first_frame() - self.first_frame()) as usize;
+        let last = (end_frame - self.first_frame()) as usize;
+        trimmed.inputs = self.inputs[first..last].to_vec();
+        trimmed.keyframes = self
+            .keyframes
+            .iter()
+            .filter(|keyframe| {
+                keyframe.frame > trimmed.first_frame() && keyframe.frame <= end_frame
+            })
+            .cloned()
+            .collect();
+        Ok(trimmed)
+    }
+
+    // resimulates the replay from the closest snapshot up to `frame`
+    pub fn state_at(&self, frame: Frame) -> GameState {
+        let mut state = self.keyframe_before(frame).clone();
+        while state.frame < frame.min(self.last_frame()) {
+            state.advance(self.inputs_to(state.frame + 1));
+        }
+        state
+    }
+}
+
+// creates a smaller replay covering `start_frame` to `end_frame`
+// it starts at the closest snapshot before `start_frame`, so nothing has to be resimulated
+pub fn trim(path: &Path, start_frame: Frame, end_frame: Frame, output: &Path) -> Result<(), Error> {
+    let trimmed = Replay::load(path)?.trimmed(start_frame, end_frame)?;
+    trimmed.save(output)?;
+
+    println!(
+        "Wrote frames {} to {} into {}",
+        trimmed.first_frame(),
+        trimmed.last_frame(),
+        output.display()
+    );
+    Ok(())
+}
+
+// Annotation is a note attached to a frame of a replay
+#[derive(Serialize, Deserialize)]
+pub struct Annotation {
+    pub frame: Frame,
+    pub text: String,
+}
+
+// annotations are kept in a sidecar file next to the replay, sorted by frame
+fn annotations_path(replay_path: &Path) -> PathBuf {
+    let mut path = replay_path.as_os_str().to_owned();
+    path.push(".notes.json");
+    PathBuf::from(path)
+}
+
+fn load_annotations(path: &Path) -> Result<Vec<Annotation>, Error> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let file = File::open(path).map_err(Error::read(path))?;
+    serde_json::from_reader(file).map_err(Error::corrupt(path, "annotations file"))
+}
+
+fn save_annotations(path: &Path, annotations: &[Annotation]) -> Result<(), Error> {
+    let file = File::create(path).map_err(Error::write(path))?;
+    serde_json::to_writer_pretty(file, annotations).map_err(Error::encode("annotations"))
+}
+
+// NetSample is what the network was like on a frame of a recorded session
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NetSample {
+    pub frame: Frame,
+    // the worst ping to a remote peer
+    pub ping_ms: u32,
+    // how many frames the rollback that ended on this frame went back, 0 without one
+    pub rollback_depth: Frame,
+    // how many frames the slowest remote peer trails this one as backroll estimates it, the
+    // frames with confirmed inputs lag about as far behind
+    pub confirmed_lag: Frame,
+}
+
+// NetLog holds the network stats of every frame of a recorded session, they are kept in a second
+// sidecar file next to the replay, sorted by frame
+// a sample is only kept when something changed, it holds until the next one
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NetLog(Vec<NetSample>);
+
+impl NetLog {
+    pub fn record(&mut self, sample: NetSample) {
+        // resimulated frames replace what was recorded for them
+        let kept = self.0.partition_point(|s| s.frame < sample.frame);
+        self.0.truncate(kept);
+        let unchanged = self.0.last().is_some_and(|last| {
+            NetSample {
+                frame: last.frame,
+                ..sample
+            } == *last
+        });
+        if !unchanged {
+            self.0.push(sample);
+        }
+    }
+
+    // the conditions on `frame`, `None` before the first stats arrived
+    pub fn at(&self, frame: Frame) -> Option<&NetSample> {
+        let index = self.0.partition_point(|s| s.frame <= frame);
+        index.checked_sub(1).map(|i| &self.0[i])
+    }
+
+    // the deepest rollback on the frames after `start` up to `end`
+    pub fn deepest_rollback(&self, start: Frame, end: Frame) -> Frame {
+        let first = self.0.partition_point(|s| s.frame <= start);
+        let last = self.0.partition_point(|s| s.frame <= end);
+        let deepest = self.0[first..last].iter().map(|s| s.rollback_depth).max();
+        deepest.unwrap_or(0)
+    }
+
+    fn path(replay_path: &Path) -> PathBuf {
+        let mut path = replay_path.as_os_str().to_owned();
+        path.push(".net.json");
+        PathBuf::from(path)
+    }
+
+    // an empty log if the replay was recorded without one
+    pub fn load(replay_path: &Path) -> Result<Self, Error> {
+        let path = Self::path(replay_path);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(&path).map_err(Error::read(&path))?;
+        serde_json::from_reader(file).map_err(Error::corrupt(&path, "network stats file"))
+    }
+
+    // nothing is written for sessions without remote players
+    pub fn save(&self, replay_path: &Path) -> Result<(), Error> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let path = Self::path(replay_path);
+        let file = File::create(&path).map_err(Error::write(&path))?;
+        serde_json::to_writer(file, self).map_err(Error::encode("network stats"))
+    }
+}
+
+// formats a frame as the match time it happened at
+fn format_frame(frame: Frame, tuning: &Tuning) -> String {
+    let seconds = tuning.seconds(frame) as i32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+// plays back a replay
+// space pauses, n adds an annotation at the current frame, [ and ] jump between annotations
+pub async fn play(path: &Path) -> Result<(), Error> {
+    let replay = Replay::load(path)?;
+    let notes_path = annotations_path(path);
+    let mut annotations = load_annotations(&notes_path)?;
+    let net_log = NetLog::load(path)?;
+    let mut game = Game::from_state(replay.initial_state.clone());
+    let mut paused = false;
+    // text of the annotation currently being typed
+    let mut note_input: Option<String> = None;
+
+    // time variables for tick rate
+    let mut step = FixedStep::default();
+    let tick = replay.initial_state.tuning.tick_duration();
+
+    prevent_quit();
+    while !is_quit_requested() {
+        let frame = game.frame();
+
+        if let Some(text) = &mut note_input {
+            while let Some(c) = get_char_pressed() {
+                if !c.is_control() {
+                    text.push(c);
+                }
+            }
+            if is_key_pressed(KeyCode::Backspace) {
+                text.pop();
+            }
+            if is_key_pressed(KeyCode::Enter) {
+                let index = annotations.partition_point(|a| a.frame <= frame);
+                let text = text.clone();
+                annotations.insert(index, Annotation { frame, text });
+                save_annotations(&notes_path, &annotations)?;
+                note_input = None;
+            } else if is_key_pressed(KeyCode::Escape) {
+                note_input = None;
+            }
+        } else {
+            if is_key_pressed(KeyCode::Space) {
+                paused = !paused;
+            }
+            if is_key_pressed(KeyCode::N) {
+                paused = true;
+                note_input = Some(String::new());
+                // don't type the key that opened the note
+                while get_char_pressed().is_some() {}
+            }
+            let target = if is_key_pressed(KeyCode::LeftBracket) {
+                annotations.iter().rev().find(|a| a.frame < frame)
+            } else if is_key_pressed(KeyCode::RightBracket) {
+                annotations.iter().find(|a| a.frame > frame)
+            } else {
+                None
+            };
+            if let Some(annotation) = target {
+                game.load_state(replay.state_at(annotation.frame));
+            }
+        }
+
+        // get delta time from last iteration and accumulate it
+        step.accumulate();
+
+        // if enough time is accumulated, we run a frame
+        while step.tick(tick) {
+            if !paused && game.frame() < replay.last_frame() {
+                game.advance_offline(replay.inputs_to(game.frame() + 1).to_vec());
+            }
+        }
+
+        let alpha = if paused { 1.0 } else { step.alpha(tick) };
+        game.render(alpha);
+        render_overlay(&game, &replay, &annotations, &net_log, paused, &note_input);
+        step.draw_catch_up();
+        next_frame().await;
+    }
+    Ok(())
+}
+
+// lets the local player race against their ship from a replay, which is shown as a ghost
+// the local player controls the first ship, all other ships repeat their recorded inputs
+pub async fn race(path: &Path) -> Result<(), Error> {
+    let replay = Replay::load(path)?;
+    let mut game = Game::from_state(replay.initial_state.clone());
+    let mut ghost = replay.initial_state.clone();
+    game.set_race_ghost(&ghost);
+
+    // time variables for tick rate
+    let mut step = FixedStep::default();
+    let tick = replay.initial_state.tuning.tick_duration();
+
+    prevent_quit();
+    while !is_quit_requested() {
+        // get delta time from last iteration and accumulate it
+        step.accumulate();
+        let buttons_pressed = game.local_input(0).buttons_pressed;
+
+        // if enough time is accumulated, we run a frame
+        while step.tick(tick) {
+            let frame = game.frame() + 1;
+            let mut inputs = if frame <= replay.last_frame() {
+                replay.inputs_to(frame).to_vec()
+            } else {
+                // once the replay ends, the other ships stop moving
+                vec![Some(0); ghost.num_players]
+            };
+            inputs[0] = Some(buttons_pressed);
+            game.advance_offline(inputs);
+
+            if ghost.frame < replay.last_frame() {
+                ghost.advance(replay.inputs_to(ghost.frame + 1));
+            }
+        }
+
+        // the ghost is only drawn where it ended up, not on the frames in between
+        game.set_race_ghost(&ghost);
+        let alpha = step.alpha(tick);
+        game.render(alpha);
+        step.draw_catch_up();
+        next_frame().await;
+    }
+    Ok(())
+}
+
+fn render_overlay(
+    game: &Game,
+    replay: &Replay,
+    annotations: &[Annotation],
+    net_log: &NetLog,
+    paused: bool,
+    note_input: &Option<String>,
+) {
+    let frame = game.frame();
+    let bottom = screen_height();
+    let tuning = &replay.initial_state.tuning;
+
+    let status = if paused { "paused" } else { "playing" };
+    let progress_str = format!(
+        "Replay {} / {} ({})",
+        format_frame(frame - replay.first_frame(), tuning),
+        format_frame(replay.last_frame() - replay.first_frame(), tuning),
+        status
+    );
+    draw_text(&progress_str, 20.0, bottom - 60.0, 30.0, WHITE);
+
+    // the network of the recorded session, rollbacks are held for a second so they can be read
+    if let Some(sample) = net_log.at(frame) {
+        let rollback_depth = net_log.deepest_rollback(frame - tuning.frames(1.0), frame);
+        let net_str = format!(
+            "Ping {} ms, rollbacks up to {} frames, confirmed {} frames behind",
+            sample.ping_ms, rollback_depth, sample.confirmed_lag
+        );
+        let color = if rollback_depth > 0 { ORANGE } else { WHITE };
+        draw_text(&net_str, 20.0, bottom - 30.0, 20.0, color);
+    }
+
+    // show annotations for two seconds after their frame
+    let recent = annotations
+        .iter()
+        .filter(|a| a.frame <= frame && frame - a.frame < tuning.frames(2.0));
+    for (i, annotation) in recent.enumerate() {
+        let note_str = format!(
+            "[{}] {}",
+            format_frame(annotation.frame, tuning),
+            annotation.text
+        );
+        draw_text(&note_str, 20.0, 100.0 + 20.0 * i as f32, 30.0, YELLOW);
+    }
+
+    if let Some(text) = note_input {
+        let input_str = format!("Note: {}_", text);
+        draw_text(&input_str, 20.0, bottom - 80.0, 30.0, YELLOW);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::MovementModel;
+
+    #[test]
+    fn trimming_stays_within_the_replay() {
+        // a replay of a session joined on frame 1000
+        let mut state = GameState::new(2, Tuning::new(MovementModel::Inertia, 1.0), 0);
+        state.frame = 1000;
+        let mut replay = Replay::new(state.clone());
+        for _ in 0..800 {
+            state.advance(&[Some(0), Some(0)]);
+            replay.record(&[Some(0), Some(0)], &state);
+        }
+        for (start, end) in [(900, 1100), (10, 20), (1300, 1200), (1300, 1900)] {
+            assert!(matches!(
+                replay.trimmed(start, end),
+                Err(Error::FramesOutOfRange {
+                    first: 1000,
+                    last: 1800,
+                    ..
+                })
+            ));
+        }
+        let trimmed = replay.trimmed(1300, 1500).unwrap();
+        assert_eq!((trimmed.first_frame(), trimmed.last_frame()), (1200, 1500));
+        assert!(trimmed.state_at(1500) == replay.state_at(1500));
+    }
+
+    #[test]
+    fn net_logs_keep_changes_and_replace_resimulated_frames() {
+        let sample = |frame, ping_ms, rollback_depth| NetSample {
+            frame,
+            ping_ms,
+            rollback_depth,
+            confirmed_lag: 2,
+        };
+        let mut net_log = NetLog::default();
+        for frame in 1..=10 {
+            net_log.record(sample(frame, 80, 0));
+        }
+        net_log.record(sample(11, 90, 0));
+        assert_eq!(net_log.0.len(), 2);
+        assert_eq!(net_log.at(0), None);
+        assert_eq!(net_log.at(10), Some(&sample(1, 80, 0)));
+
+        // a rollback from frame 11 back to frame 8 resimulates the frames after it
+        for frame in 9..=11 {
+            let rollback_depth = if frame == 11 { 3 } else { 0 };
+            net_log.record(sample(frame, 80, rollback_depth));
+        }
+        net_log.record(sample(12, 80, 0));
+        assert_eq!(net_log.at(11), Some(&sample(11, 80, 3)));
+        assert_eq!(net_log.deepest_rollback(10, 12), 3);
+        assert_eq!(net_log.deepest_rollback(11, 12), 0);
+    }
+}
@@ -0,0 +1,238 @@
+// what a game needs to provide to be played in a backroll session, and the loop every such game
+// shares: polling the session, advancing it at a fixed rate and rendering between frames
+use backroll::{
+    command::{Command, Commands},
+    BackrollResult, Config, Event, GameInput, P2PSession, PlayerHandle,
+};
+use macroquad::prelude::*;
+use std::{
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
+
+use crate::game::{PlayerInput, FPS, WINDOW_WIDTH};
+
+// a machine that falls this far behind stops catching up on the time in between, e.g. after the
+// window was dragged or the process was suspended
+const MAX_ACCUMULATED: Duration = Duration::from_millis(250);
+// ticks run before rendering the next frame at most, when ticks take longer than they simulate,
+// catching up on every one of them would only fall further behind
+const MAX_CATCH_UP_TICKS: u32 = 8;
+// how long "catching up" stays on screen after the last frame that took several ticks, a single
+// frame of it would only flicker
+const CATCH_UP_INDICATOR: Duration = Duration::from_millis(500);
+
+// RollbackGame is a game backroll can roll back, the session loop only talks to it through this
+// all games exchange the same button inputs, so they can share the netcode around them
+pub trait RollbackGame: Sized + 'static {
+    // everything the simulation depends on, backroll saves and loads it for rollbacks
+    type State: Clone + Send + Sync + 'static;
+    // the rules all peers have to agree on before the match
+    type Rules;
+
+    // all peers have to start with the same `rules` and `seed` to stay in sync
+    fn new(num_players: usize, rules: Self::Rules, seed: u64) -> Self;
+    // advances the game by a frame with the inputs of all players
+    fn advance(&mut self, inputs: GameInput<PlayerInput>);
+    // returns the state to save and its checksum, which backroll compares between peers
+    fn save(&mut self) -> (Self::State, u64);
+    // goes back to a saved state
+    fn load(&mut self, state: Self::State);
+    // `alpha` is the fraction of a tick that passed since the last simulated frame
+    fn render(&mut self, alpha: f32);
+    // the buttons the local player `local_index` presses, players sharing the keyboard count
+    // from 0
+    fn local_input(&self, local_index: usize) -> PlayerInput;
+
+    // players connecting, synchronizing and dropping, games that don't show it ignore it
+    fn handle_event(&mut self, _event: Event) {}
+
+    // the time the next tick takes, games syncing their time with the other peers stretch it
+    fn tick_duration(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / FPS)
+    }
+
+    // called once for every tick that passed
+    fn tick(&mut self) {}
+
+    fn handle_commands(&mut self, cmds: Commands<RollbackConfig<Self>>) {
+        for cmd in cmds.into_iter() {
+            match cmd {
+                Command::Save(save) => {
+                    let (state, checksum) = self.save();
+                    save.save_with_hash(state, checksum);
+                }
+                Command::Load(load) => self.load(load.load()),
+                Command::AdvanceFrame(inputs) => self.advance(inputs),
+                Command::Event(event) => self.handle_event(event),
+            }
+        }
+    }
+}
+
+// RollbackConfig tells backroll what peers exchange and what is saved for rollbacks in a game
+pub struct RollbackConfig<G>(PhantomData<fn() -> G>);
+
+impl<G: RollbackGame> Config for RollbackConfig<G> {
+    type Input = PlayerInput;
+    type State = G::State;
+}
+
+// FixedStep turns the time between rendered frames into ticks of the simulation, the time left
+// over is carried to the next frame and interpolated over
+// time it can't catch up on is dropped, the game runs slower instead of freezing
+// however many ticks a frame takes, it is rendered once, loops read the keyboard once per frame
+// too, macroquad only updates it in `next_frame`
+pub struct FixedStep {
+    last_update: Instant,
+    accumulator: Duration,
+    // ticks taken since the last `accumulate`
+    ticks: u32,
+    // when a rendered frame last took more than one tick
+    last_catch_up: Option<Instant>,
+}
+
+impl Default for FixedStep {
+    fn default() -> Self {
+        Self {
+            last_update: Instant::now(),
+            accumulator: Duration::ZERO,
+            ticks: 0,
+            last_catch_up: None,
+        }
+    }
+}
+
+impl FixedStep {
+    // adds the time that passed since the last call, up to `MAX_ACCUMULATED`
+    pub fn accumulate(&mut self) {
+        let now = Instant::now();
+        if self.ticks > 1 {
+            self.last_catch_up = Some(now);
+        }
+        self.accumulator = self
+            .accumulator
+            .saturating_add(now.duration_since(self.last_update))
+            .min(MAX_ACCUMULATED);
+        self.last_update = now;
+        self.ticks = 0;
+    }
+
+    // whether a frame took several ticks within the last `CATCH_UP_INDICATOR`
+    pub fn is_catching_up(&self) -> bool {
+        self.last_catch_up
+            .is_some_and(|last_catch_up| last_catch_up.elapsed() < CATCH_UP_INDICATOR)
+    }
+
+    // shows that the game is running several ticks per frame, e.g. after a hitch
+    pub fn draw_catch_up(&self) {
+        if !self.is_catching_up() {
+            return;
+        }
+        let text = "catching up";
+        let width = measure_text(text, None, 20, 1.0).width;
+        draw_text(text, (WINDOW_WIDTH - width) / 2.0, 20.0, 20.0, GRAY);
+    }
+
+    // takes a tick of `duration` from the accumulated time, if enough of it is left
+    // after `MAX_CATCH_UP_TICKS`, the whole ticks left are dropped and only the fraction of a tick
+    // is carried over
+    pub fn tick(&mut self, duration: Duration) -> bool {
+        if self.accumulator <= duration {
+            return false;
+        }
+        if self.ticks == MAX_CATCH_UP_TICKS {
+            let leftover = self.accumulator.as_nanos() % duration.as_nanos();
+            self.accumulator = Duration::from_nanos(leftover as u64);
+            return false;
+        }
+        self.accumulator -= duration;
+        self.ticks += 1;
+        true
+    }
+
+    // the fraction of a tick of `duration` that is left over
+    pub fn alpha(&self, duration: Duration) -> f32 {
+        (self.accumulator.as_secs_f32() / duration.as_secs_f32()).min(1.0)
+    }
+}
+
+// adds the inputs of all local players and advances the session by a frame once they are in
+// fails until all peers are synchronized
+pub fn advance_session<G: RollbackGame>(
+    game: &mut G,
+    session: &P2PSession<RollbackConfig<G>>,
+    local_handles: &[PlayerHandle],
+    inputs: &[PlayerInput],
+) -> BackrollResult<()> {
+    local_handles
+        .iter()
+        .zip(inputs)
+        .try_for_each(|(handle, input)| session.add_local_input(*handle, *input))?;
+    game.handle_commands(session.advance_frame());
+    Ok(())
+}
+
+// plays a session of `game` until the window is closed, with nothing around it
+// games that need more, like the menu or the debug keys, build their loop from the parts above
+pub async fn run<G: RollbackGame>(
+    game: &mut G,
+    session: &P2PSession<RollbackConfig<G>>,
+    local_handles: &[PlayerHandle],
+) {
+    let mut step = FixedStep::default();
+    prevent_quit();
+    while !is_quit_requested() {
+        game.handle_commands(session.poll());
+        step.accumulate();
+        let inputs: Vec<PlayerInput> = (0..local_handles.len())
+            .map(|i| game.local_input(i))
+            .collect();
+        while step.tick(game.tick_duration()) {
+            game.tick();
+            if let Err(e) = advance_session(game, session, local_handles, &inputs) {
+                println!("{e}");
+            }
+        }
+        game.render(step.alpha(game.tick_duration()));
+        step.draw_catch_up();
+        next_frame().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_step_carries_the_leftover_time() {
+        let tick = Duration::from_millis(10);
+        let mut step = FixedStep {
+            accumulator: Duration::from_millis(25),
+            ..Default::default()
+        };
+        assert!(step.tick(tick));
+        assert!(step.tick(tick));
+        assert!(!step.tick(tick));
+        assert!((step.alpha(tick) - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn fixed_step_drops_the_time_it_cant_catch_up_on() {
+        let tick = Duration::from_millis(10);
+        let mut step = FixedStep::default();
+        step.last_update -= Duration::from_secs(5);
+        step.accumulate();
+        assert_eq!(step.accumulator, MAX_ACCUMULATED);
+
+        step.accumulator = tick * 20 + Duration::from_millis(5);
+        let ticks = std::iter::from_fn(|| step.tick(tick).then_some(())).count();
+        assert_eq!(ticks, MAX_CATCH_UP_TICKS as usize);
+        assert!((step.alpha(tick) - 0.5).abs() < 1e-3);
+        // the next rendered frame can catch up again
+        step.accumulator = tick * 2;
+        step.accumulate();
+        assert!(step.is_catching_up());
+        assert!(step.tick(tick));
+    }
+}
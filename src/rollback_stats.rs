@@ -0,0 +1,79 @@
+use crate::game::Frame;
+
+// RollbackStats counts the rollbacks of a session, how deep they went and how many frames had to
+// be simulated again because of them
+#[derive(Default)]
+pub struct RollbackStats {
+    rollbacks: u32,
+    max_depth: Frame,
+    total_depth: u64,
+    resimulated_frames: u64,
+    // the frame the game was at before the current rollback, frames up to it are resimulated
+    resimulating_to: Option<Frame>,
+}
+
+impl RollbackStats {
+    // a load from `from_frame` back to `to_frame` starts a rollback
+    pub fn add_load(&mut self, from_frame: Frame, to_frame: Frame) {
+        let depth = (from_frame - to_frame).max(0);
+        self.rollbacks += 1;
+        self.max_depth = self.max_depth.max(depth);
+        self.total_depth += depth as u64;
+        // a load during a rollback still has to resimulate to where the first one started
+        let target = self
+            .resimulating_to
+            .map_or(from_frame, |t| t.max(from_frame));
+        self.resimulating_to = Some(target);
+    }
+
+    // frames advanced until the game is back where the rollback started are resimulated
+    pub fn add_advance(&mut self, frame: Frame) {
+        let Some(target) = self.resimulating_to else {
+            return;
+        };
+        self.resimulated_frames += 1;
+        if frame >= target {
+            self.resimulating_to = None;
+        }
+    }
+
+    pub fn rollbacks(&self) -> u32 {
+        self.rollbacks
+    }
+
+    // a single line for the screen, `seconds` is how long the session ran
+    pub fn summary(&self, seconds: f32) -> String {
+        let minutes = seconds.max(f32::EPSILON) / 60.0;
+        let average = self.total_depth as f32 / self.rollbacks.max(1) as f32;
+        format!(
+            "Rollbacks: {} ({:.0}/min), depth {:.1} avg / {} max, {} frames resimulated",
+            self.rollbacks,
+            self.rollbacks as f32 / minutes,
+            average,
+            self.max_depth,
+            self.resimulated_frames
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_depths_and_resimulated_frames() {
+        let mut stats = RollbackStats::default();
+        stats.add_advance(10);
+        stats.add_load(10, 7);
+        for frame in 8..=10 {
+            stats.add_advance(frame);
+        }
+        stats.add_advance(11);
+        stats.add_load(11, 10);
+        stats.add_advance(11);
+        assert_eq!(stats.rollbacks(), 2);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.total_depth, 4);
+        assert_eq!(stats.resimulated_frames, 4);
+    }
+}
@@ -0,0 +1,297 @@
+use std::{fmt::Display, fs, path::Path, str::FromStr};
+
+use crate::{
+    arena::MapRotation,
+    game::{parse_ship_scale, Frame, GameState, MovementModel, Tuning},
+    input_script::parse_buttons,
+    win_condition::WinConditionId,
+    Error,
+};
+
+// values of a ship that can be checked
+const VALUES: [&str; 10] = [
+    "x",
+    "y",
+    "vel_x",
+    "vel_y",
+    "speed",
+    "rotation",
+    "score",
+    "hull",
+    "checkpoint",
+    "lap",
+];
+
+// `==` accepts values this close to the expected one
+const EPSILON: f32 = 0.001;
+
+// Scenario describes inputs for a headless game and what the state has to look like afterwards
+// scenarios are plain text files, one statement per line:
+//
+//   players 2                  number of players, defaults to 1
+//   movement_model tank        `inertia` (default) or `tank`
+//   ship_scale 2.0             defaults to 1.0
+//   seed 3                     defaults to 0
+//   bot_takeover               disconnected players are controlled by a bot
+//   maps pillars,open          arenas of successive rounds, defaults to `open`
+//   round_frames 600           length of a round, rounds never end by default
+//   best_of 3                  rounds of a match, matches never end by default
+//   meteor_showers             meteors periodically rain down on the arena
+//   win_condition score-limit  decides when a round is won, defaults to `endless`
+//   inputs 1-60 UL -           buttons of each player for a range of frames, `x` disconnects
+//   expect 60 P1 y <= 25.0     compares a value of a player's ship on a frame
+//
+// frames without inputs have no buttons pressed, lines starting with `#` are comments
+pub struct Scenario {
+    num_players: usize,
+    movement_model: MovementModel,
+    ship_scale: f32,
+    seed: u64,
+    bot_takeover: bool,
+    maps: MapRotation,
+    round_frames: Frame,
+    best_of: u32,
+    meteor_showers: bool,
+    win_condition: WinConditionId,
+    inputs: Vec<Inputs>,
+    expectations: Vec<Expectation>,
+}
+
+struct Inputs {
+    first_frame: Frame,
+    last_frame: Frame,
+    buttons: Vec<Option<u8>>,
+}
+
+struct Expectation {
+    line: usize,
+    frame: Frame,
+    player: usize,
+    value: String,
+    comparison: String,
+    expected: f32,
+}
+
+impl Expectation {
+    fn check(&self, state: &GameState) -> Result<(), String> {
+        let (x, y) = state.positions[self.player];
+        let (vel_x, vel_y) = state.velocities[self.player];
+        let actual = match self.value.as_str() {
+            "x" => x,
+            "y" => y,
+            "vel_x" => vel_x,
+            "vel_y" => vel_y,
+            "speed" => (vel_x * vel_x + vel_y * vel_y).sqrt(),
+            "rotation" => state.rotations[self.player],
+            "score" => state.scores[self.player] as f32,
+            "hull" => state.hull[self.player] as f32,
+            "checkpoint" => state.checkpoints[self.player] as f32,
+            "lap" => state.laps[self.player] as f32,
+            _ => unreachable!("values are checked when parsing"),
+        };
+        let passed = match self.comparison.as_str() {
+            "<" => actual < self.expected,
+            "<=" => actual <= self.expected,
+            "==" => (actual - self.expected).abs() <= EPSILON,
+            ">=" => actual >= self.expected,
+            ">" => actual > self.expected,
+            _ => unreachable!("comparisons are checked when parsing"),
+        };
+        if passed {
+            Ok(())
+        } else {
+            Err(format!(
+                "line {}: expected P{} {} {} {} on frame {}, but it is {}",
+                self.line,
+                self.player + 1,
+                self.value,
+                self.comparison,
+                self.expected,
+                self.frame,
+                actual
+            ))
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path).map_err(Error::read(path))?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut scenario = Self {
+            num_players: 1,
+            movement_model: MovementModel::Inertia,
+            ship_scale: 1.0,
+            seed: 0,
+            bot_takeover: false,
+            maps: MapRotation::default(),
+            round_frames: 0,
+            best_of: 0,
+            meteor_showers: false,
+            win_condition: WinConditionId::Endless,
+            inputs: Vec::new(),
+            expectations: Vec::new(),
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            scenario
+                .parse_line(number + 1, line)
+                .map_err(|reason| Error::InvalidLine {
+                    kind: "scenario",
+                    number: number + 1,
+                    line: line.to_owned(),
+                    reason,
+                })?;
+        }
+        Ok(scenario)
+    }
+
+    // returns why the line is invalid
+    fn parse_line(&mut self, number: usize, line: &str) -> Result<(), String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["players", num_players] => self.num_players = parse(num_players)?,
+            ["movement_model", model] => self.movement_model = parse(model)?,
+            ["ship_scale", scale] => self.ship_scale = parse_ship_scale(scale)?,
+            ["seed", seed] => self.seed = parse(seed)?,
+            ["bot_takeover"] => self.bot_takeover = true,
+            ["maps", maps] => self.maps = parse(maps)?,
+            ["round_frames", frames] => self.round_frames = parse(frames)?,
+            ["best_of", rounds] => self.best_of = parse(rounds)?,
+            ["meteor_showers"] => self.meteor_showers = true,
+            ["win_condition", condition] => self.win_condition = parse(condition)?,
+            ["inputs", frames, buttons @ ..] => {
+                let (first_frame, last_frame) = match frames.split_once('-') {
+                    Some((first, last)) => (parse(first)?, parse(last)?),
+                    None => (parse(frames)?, parse(frames)?),
+                };
+                let buttons = buttons
+                    .iter()
+                    .map(|field| match *field {
+                        "x" => Ok(None),
+                        _ => parse_buttons(field).map(Some).ok_or("unknown button"),
+                    })
+                    .collect::<Result<_, _>>()?;
+                self.inputs.push(Inputs {
+                    first_frame,
+                    last_frame,
+                    buttons,
+                });
+            }
+            ["expect", frame, player, value, comparison, expected] => {
+                let player = player
+                    .strip_prefix('P')
+                    .and_then(|p| p.parse::<usize>().ok())
+                    .filter(|p| (1..=self.num_players).contains(p))
+                    .ok_or("unknown player")?;
+                if !VALUES.contains(value) {
+                    return Err("unknown value".into());
+                }
+                if !["<", "<=", "==", ">=", ">"].contains(comparison) {
+                    return Err("unknown comparison".into());
+                }
+                self.expectations.push(Expectation {
+                    line: number,
+                    frame: parse(frame)?,
+                    player: player - 1,
+                    value: value.to_string(),
+                    comparison: comparison.to_string(),
+                    expected: parse(expected)?,
+                });
+            }
+            _ => return Err("unknown statement".into()),
+        }
+        Ok(())
+    }
+
+    // returns the buttons of all players on `frame`, the last matching `inputs` line wins
+    fn inputs(&self, frame: Frame) -> Vec<Option<u8>> {
+        let mut inputs = vec![Some(0); self.num_players];
+        for range in &self.inputs {
+            if (range.first_frame..=range.last_frame).contains(&frame) {
+                for (input, buttons) in inputs.iter_mut().zip(&range.buttons) {
+                    *input = *buttons;
+                }
+            }
+        }
+        inputs
+    }
+
+    // simulates the scenario and returns every expectation that wasn't met
+    pub fn run(&self) -> Vec<String> {
+        let mut tuning = Tuning::new(self.movement_model, self.ship_scale);
+        tuning.bot_takeover = self.bot_takeover;
+        tuning.rotation = self.maps;
+        tuning.round_frames = self.round_frames;
+        tuning.best_of = self.best_of;
+        tuning.meteor_showers = self.meteor_showers;
+        tuning.win_condition = self.win_condition;
+        let mut state = GameState::new(self.num_players, tuning, self.seed);
+        let last_frame = self
+            .expectations
+            .iter()
+            .map(|expectation| expectation.frame)
+            .max()
+            .unwrap_or(0);
+
+        let mut failures = Vec::new();
+        loop {
+            for expectation in self.expectations.iter() {
+                if expectation.frame == state.frame {
+                    failures.extend(expectation.check(&state).err());
+                }
+            }
+            if state.frame >= last_frame {
+                return failures;
+            }
+            state.advance(&self.inputs(state.frame + 1));
+        }
+    }
+}
+
+// parses a field of a scenario line, failures are described by the error of the field's type
+fn parse<T: FromStr>(field: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    field.parse().map_err(|e: T::Err| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // every file in `scenarios/` is a test case
+    #[test]
+    fn scenarios_pass() {
+        let dir: PathBuf = [env!("CARGO_MANIFEST_DIR"), "scenarios"].iter().collect();
+        let mut failures = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let scenario = Scenario::load(&path).unwrap();
+            for failure in scenario.run() {
+                failures.push(format!("{}: {}", path.display(), failure));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn invalid_lines_are_reported_with_their_number() {
+        let result = Scenario::parse("players 2\n\nexpect 60 P3 x == 1.0");
+        assert!(matches!(
+            result,
+            Err(Error::InvalidLine {
+                kind: "scenario",
+                number: 3,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    io::{self, BufRead, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::{Config, ControlScheme},
+    Error,
+};
+
+// a public STUN server, it tells us the address other peers see us at
+const STUN_SERVER: &str = "stun.l.google.com:19302";
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+const STUN_MAGIC_COOKIE: u32 = 0x2112_a442;
+const STUN_BINDING_REQUEST: u16 = 0x0001;
+const STUN_BINDING_RESPONSE: u16 = 0x0101;
+const STUN_MAPPED_ADDRESS: u16 = 0x0001;
+const STUN_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+// walks through the settings on the terminal, checks the connectivity and writes the config
+pub fn run(path: &Path) -> Result<Config, Error> {
+    println!("Welcome to Box Game! Let's set up a few things first.");
+    let name = prompt("Your name", "Player")?;
+    println!("Steer your ship with");
+    println!("  1) W A S D");
+    println!("  2) the arrow keys");
+    println!("  3) one hand: I J K L, vote with U and O, call votes with 8, 9 and 0");
+    let controls = match prompt("Pick one", "1")?.as_str() {
+        "2" => ControlScheme::Arrows,
+        "3" => ControlScheme::OneHanded,
+        _ => ControlScheme::Wasd,
+    };
+    let toggle_thrust = prompt(
+        "Tap thrust to turn it on and off instead of holding it? (y/n)",
+        "n",
+    )?
+    .eq_ignore_ascii_case("y");
+
+    println!("Checking the connectivity...");
+    match loopback_check() {
+        Ok(rtt) => println!("  loopback: ok ({} us)", rtt.as_micros()),
+        Err(e) => println!("  loopback: failed, {}", e),
+    }
+    match stun_check() {
+        Ok(address) => {
+            println!("  public address: {}", address);
+            println!(
+                "  other players connect to {}:<your --local-port>",
+                address.ip()
+            );
+            println!("  if they can't, forward that port to this machine in your router");
+        }
+        Err(e) => println!("  public address: unknown, {}", e),
+    }
+
+    let config = Config {
+        name,
+        controls,
+        toggle_thrust,
+    };
+    config.save(path)?;
+    println!("Saved the settings to {}", path.display());
+    println!("Start a match with e.g.");
+    println!("  play --local-port 7000 --players localhost <ip of the other player>:7000");
+    Ok(config)
+}
+
+// asks a question on the terminal, an empty answer picks the default
+fn prompt(question: &str, default: &str) -> io::Result<String> {
+    print!("{} [{}]: ", question, default);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_owned())
+}
+
+// sends a datagram to ourselves, this fails if a firewall blocks local udp traffic
+fn loopback_check() -> io::Result<Duration> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    socket.set_read_timeout(Some(CHECK_TIMEOUT))?;
+    let start = Instant::now();
+    socket.send_to(b"box game", socket.local_addr()?)?;
+    let mut buffer = [0; 16];
+    let (len, _) = socket.recv_from(&mut buffer)?;
+    if &buffer[..len] != b"box game" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "wrong datagram"));
+    }
+    Ok(start.elapsed())
+}
+
+// asks the STUN server for the public address of a udp socket
+fn stun_check() -> io::Result<SocketAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_read_timeout(Some(CHECK_TIMEOUT))?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut transaction = [0; 12];
+    transaction.copy_from_slice(&nanos.to_le_bytes()[..12]);
+    socket.send_to(&binding_request(&transaction), STUN_SERVER)?;
+    let mut buffer = [0; 512];
+    let (len, _) = socket.recv_from(&mut buffer)?;
+    parse_binding_response(&buffer[..len], &transaction).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid answer from the STUN server",
+        )
+    })
+}
+
+fn binding_request(transaction: &[u8; 12]) -> [u8; 20] {
+    let mut request = [0; 20];
+    request[0..2].copy_from_slice(&STUN_BINDING_REQUEST.to_be_bytes());
+    // no attributes, so the length stays 0
+    request[4..8].copy_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    request[8..20].copy_from_slice(transaction);
+    request
+}
+
+// reads the mapped address out of a binding response, preferring the xor-mapped one
+fn parse_binding_response(response: &[u8], transaction: &[u8; 12]) -> Option<SocketAddr> {
+    let header = response.get(..20)?;
+    if u16::from_be_bytes([header[0], header[1]]) != STUN_BINDING_RESPONSE
+        || header[4..8] != STUN_MAGIC_COOKIE.to_be_bytes()
+        || &header[8..20] != transaction
+    {
+        return None;
+    }
+    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let mut attributes = response.get(20..20 + len)?;
+    let mut mapped = None;
+    while attributes.len() >= 4 {
+        let kind = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let len = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let value = attributes.get(4..4 + len)?;
+        match kind {
+            STUN_XOR_MAPPED_ADDRESS => return parse_address(value, Some(header)),
+            STUN_MAPPED_ADDRESS => mapped = parse_address(value, None),
+            _ => {}
+        }
+        // attributes are padded to four bytes
+        attributes = attributes
+            .get((4 + len).div_ceil(4) * 4..)
+            .unwrap_or_default();
+    }
+    mapped
+}
+
+// xor-mapped addresses are xored with the magic cookie and the transaction id of the `header`
+fn parse_address(value: &[u8], header: Option<&[u8]>) -> Option<SocketAddr> {
+    let mut key = [0; 16];
+    if let Some(header) = header {
+        key.copy_from_slice(&header[4..20]);
+    }
+    let port = u16::from_be_bytes([value.get(2)? ^ key[0], value.get(3)? ^ key[1]]);
+    let ip: IpAddr = match value.get(1)? {
+        0x01 => {
+            let mut octets = [0; 4];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value.get(4 + i)? ^ key[i];
+            }
+            Ipv4Addr::from(octets).into()
+        }
+        0x02 => {
+            let mut octets = [0; 16];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value.get(4 + i)? ^ key[i];
+            }
+            Ipv6Addr::from(octets).into()
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_xor_mapped_addresses() {
+        let transaction = [7; 12];
+        let mut response = binding_request(&transaction).to_vec();
+        response[0..2].copy_from_slice(&STUN_BINDING_RESPONSE.to_be_bytes());
+        response[2..4].copy_from_slice(&12u16.to_be_bytes());
+        // 203.0.113.5:7000 xored with the magic cookie
+        response.extend_from_slice(&STUN_XOR_MAPPED_ADDRESS.to_be_bytes());
+        response.extend_from_slice(&8u16.to_be_bytes());
+        response.extend_from_slice(&[0, 0x01]);
+        response.extend_from_slice(&(7000 ^ 0x2112u16).to_be_bytes());
+        let ip = u32::from(Ipv4Addr::new(203, 0, 113, 5)) ^ STUN_MAGIC_COOKIE;
+        response.extend_from_slice(&ip.to_be_bytes());
+
+        let address = parse_binding_response(&response, &transaction);
+        assert_eq!(address, Some("203.0.113.5:7000".parse().unwrap()));
+        assert_eq!(parse_binding_response(&response, &[8; 12]), None);
+    }
+}
@@ -0,0 +1,77 @@
+use macroquad::prelude::*;
+
+use crate::game::{Frame, Tuning, WINDOW_WIDTH};
+
+// seconds between asking for slow motion and it starting, so the request reaches all peers before
+// they simulate that frame
+const SCHEDULE_LEAD: f32 = 0.5;
+
+// SlowMotion lets the simulation advance only every `factor`th tick, to watch a bug unfold on
+// several machines at once
+// every change is scheduled for a frame and sent to the other peers, so all of them switch on the
+// same frame and none runs ahead of the others
+#[derive(Default)]
+pub struct SlowMotion {
+    // 0 and 1 both advance on every tick
+    factor: u32,
+    scheduled: Option<(Frame, u32)>,
+    ticks: u32,
+}
+
+impl SlowMotion {
+    // switches between normal speed and `factor` a little after `frame`, returns the change to send
+    // to the other peers
+    pub fn toggle(&mut self, frame: Frame, factor: u32, tuning: &Tuning) -> (Frame, u32) {
+        let upcoming = self.scheduled.map_or(self.factor, |(_, factor)| factor);
+        let factor = if upcoming > 1 { 1 } else { factor };
+        let start = frame + tuning.frames(SCHEDULE_LEAD);
+        self.schedule(start, factor);
+        (start, factor)
+    }
+
+    // applies a change once `frame` is simulated
+    pub fn schedule(&mut self, frame: Frame, factor: u32) {
+        self.scheduled = Some((frame, factor));
+    }
+
+    // whether the simulation advances on this tick, `frame` is the last simulated one
+    pub fn tick(&mut self, frame: Frame) -> bool {
+        if let Some((start, factor)) = self.scheduled {
+            if frame >= start {
+                self.factor = factor;
+                self.scheduled = None;
+                self.ticks = 0;
+            }
+        }
+        self.ticks += 1;
+        self.ticks.is_multiple_of(self.factor.max(1))
+    }
+
+    pub fn draw(&self) {
+        if self.factor <= 1 {
+            return;
+        }
+        let text = format!("Slow motion 1/{}", self.factor);
+        let width = measure_text(&text, None, 24, 1.0).width;
+        draw_text(&text, (WINDOW_WIDTH - width) / 2.0, 40.0, 24.0, YELLOW);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::MovementModel;
+
+    #[test]
+    fn advances_every_nth_tick_from_the_scheduled_frame() {
+        let mut slow_motion = SlowMotion::default();
+        let tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        let (start, factor) = slow_motion.toggle(100, 3, &tuning);
+        assert_eq!((start, factor), (130, 3));
+        let advanced: Vec<bool> = (128..134).map(|frame| slow_motion.tick(frame)).collect();
+        assert_eq!(advanced, [true, true, false, false, true, false]);
+        assert_eq!(slow_motion.toggle(134, 3, &tuning), (164, 1));
+        assert!(slow_motion.tick(164));
+        assert!(slow_motion.tick(165));
+    }
+}
@@ -0,0 +1,307 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{Frame, GameState, Round},
+    meteor::Meteor,
+    rng::Rng,
+    win_condition::{ControlZone, Role},
+    Error,
+};
+
+// Snapshot is either a full game state or the changes since the previous snapshot
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Snapshot {
+    // boxed, since deltas are sent far more often and are much smaller
+    Keyframe(Box<GameState>),
+    Delta(StateDelta),
+}
+
+// StateDelta holds everything that changed between two states
+// players and tuning never change during a match, so they are only part of keyframes
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateDelta {
+    // the frame of the state this delta has to be applied to
+    pub base_frame: Frame,
+    pub frame: Frame,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub rng: Option<Rng>,
+    // boxed, since rounds rarely change and carry the layout of generated arenas
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub round: Option<Box<Round>>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub zone: Option<ControlZone>,
+    // only ships with at least one changed value are included
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub ships: Vec<ShipDelta>,
+    // only meteor slots that changed are included
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub meteors: Vec<MeteorDelta>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeteorDelta {
+    pub slot: usize,
+    // `None` once the meteor is gone
+    pub meteor: Option<Meteor>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShipDelta {
+    pub player: usize,
+    pub position: (f32, f32),
+    pub velocity: (f32, f32),
+    pub rotation: f32,
+    pub first_input_frame: Option<Frame>,
+    pub last_active_frame: Frame,
+    pub kicked: bool,
+    pub thrust_latched: bool,
+    pub toggle_held: bool,
+    pub score: u32,
+    pub round_wins: u32,
+    pub hull: u8,
+    pub role: Role,
+    pub rammed_frame: Option<Frame>,
+    pub checkpoint: u8,
+    pub lap: u8,
+}
+
+impl ShipDelta {
+    fn new(state: &GameState, player: usize) -> Self {
+        Self {
+            player,
+            position: state.positions[player],
+            velocity: state.velocities[player],
+            rotation: state.rotations[player],
+            first_input_frame: state.first_input_frames[player],
+            last_active_frame: state.last_active_frames[player],
+            kicked: state.kicked[player],
+            thrust_latched: state.thrust_latched[player],
+            toggle_held: state.toggle_held[player],
+            score: state.scores[player],
+            round_wins: state.round_wins[player],
+            hull: state.hull[player],
+            role: state.roles[player],
+            rammed_frame: state.rammed_frames[player],
+            checkpoint: state.checkpoints[player],
+            lap: state.laps[player],
+        }
+    }
+
+    fn apply(&self, state: &mut GameState) {
+        state.positions[self.player] = self.position;
+        state.velocities[self.player] = self.velocity;
+        state.rotations[self.player] = self.rotation;
+        state.first_input_frames[self.player] = self.first_input_frame;
+        state.last_active_frames[self.player] = self.last_active_frame;
+        state.kicked[self.player] = self.kicked;
+        state.thrust_latched[self.player] = self.thrust_latched;
+        state.toggle_held[self.player] = self.toggle_held;
+        state.scores[self.player] = self.score;
+        state.round_wins[self.player] = self.round_wins;
+        state.hull[self.player] = self.hull;
+        state.roles[self.player] = self.role;
+        state.rammed_frames[self.player] = self.rammed_frame;
+        state.checkpoints[self.player] = self.checkpoint;
+        state.laps[self.player] = self.lap;
+    }
+}
+
+// SnapshotEncoder turns consecutive states into snapshots
+// a keyframe is sent every `keyframe_interval` frames, so receivers can join at any time
+pub struct SnapshotEncoder {
+    keyframe_interval: Frame,
+    previous: Option<GameState>,
+}
+
+impl SnapshotEncoder {
+    pub fn new(keyframe_interval: Frame) -> Self {
+        Self {
+            keyframe_interval,
+            previous: None,
+        }
+    }
+
+    pub fn encode(&mut self, state: &GameState) -> Snapshot {
+        let snapshot = match &self.previous {
+            // the end of a match and the rematch change most of the state, votes are rare
+            Some(previous)
+                if state.frame % self.keyframe_interval != 0
+                    && previous.match_end == state.match_end
+                    && previous.vote == state.vote =>
+            {
+                Snapshot::Delta(StateDelta::new(previous, state))
+            }
+            _ => Snapshot::Keyframe(Box::new(state.clone())),
+        };
+        self.previous = Some(state.clone());
+        snapshot
+    }
+}
+
+impl StateDelta {
+    fn new(previous: &GameState, state: &GameState) -> Self {
+        let ships = (0..state.num_players)
+            .filter(|&i| ShipDelta::new(previous, i) != ShipDelta::new(state, i))
+            .map(|i| ShipDelta::new(state, i))
+            .collect();
+        let meteors = (0..state.meteors.len())
+            .filter(|&slot| previous.meteors[slot] != state.meteors[slot])
+            .map(|slot| MeteorDelta {
+                slot,
+                meteor: state.meteors[slot],
+            })
+            .collect();
+        Self {
+            base_frame: previous.frame,
+            frame: state.frame,
+            rng: (previous.rng != state.rng).then(|| state.rng.clone()),
+            round: (previous.round != state.round).then(|| Box::new(state.round)),
+            zone: (previous.zone != state.zone).then_some(state.zone),
+            ships,
+            meteors,
+        }
+    }
+}
+
+// SnapshotDecoder reassembles full states from a stream of snapshots
+#[derive(Default)]
+pub struct SnapshotDecoder {
+    current: Option<GameState>,
+}
+
+impl SnapshotDecoder {
+    // applies a snapshot and returns the resulting state
+    // deltas fail until the first keyframe arrived, or when a snapshot in between was lost
+    pub fn decode(&mut self, snapshot: Snapshot) -> Result<&GameState, Error> {
+        match snapshot {
+            Snapshot::Keyframe(state) => self.current = Some(*state),
+            Snapshot::Delta(delta) => {
+                let state = match &mut self.current {
+                    Some(state) if state.frame == delta.base_frame => state,
+                    _ => {
+                        self.current = None;
+                        return Err(Error::MissingBaseFrame {
+                            frame: delta.frame,
+                            base_frame: delta.base_frame,
+                        });
+                    }
+                };
+                state.frame = delta.frame;
+                if let Some(rng) = delta.rng {
+                    state.rng = rng;
+                }
+                if let Some(round) = delta.round {
+                    state.round = *round;
+                }
+                if let Some(zone) = delta.zone {
+                    state.zone = zone;
+                }
+                for ship in &delta.ships {
+                    if ship.player >= state.num_players {
+                        return Err(Error::UnknownPlayer(ship.player));
+                    }
+                    ship.apply(state);
+                }
+                for meteor in &delta.meteors {
+                    let slot = state
+                        .meteors
+                        .get_mut(meteor.slot)
+                        .ok_or(Error::UnknownMeteor(meteor.slot))?;
+                    *slot = meteor.meteor;
+                }
+            }
+        }
+        Ok(self.current.as_ref().expect("a state was just set"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{MovementModel, Tuning, INPUT_UP};
+
+    fn states(frames: Frame) -> Vec<GameState> {
+        let mut state = GameState::new(2, Tuning::new(MovementModel::Inertia, 1.0), 0);
+        let mut states = vec![state.clone()];
+        for frame in 1..=frames {
+            // the second player only starts moving halfway through
+            let second = if frame > frames / 2 { INPUT_UP } else { 0 };
+            state.advance(&[Some(INPUT_UP), Some(second)]);
+            states.push(state.clone());
+        }
+        states
+    }
+
+    #[test]
+    fn reassembles_all_states() {
+        let mut encoder = SnapshotEncoder::new(10);
+        let mut decoder = SnapshotDecoder::default();
+        for state in states(35) {
+            let snapshot = encoder.encode(&state);
+            assert_eq!(
+                matches!(snapshot, Snapshot::Keyframe(_)),
+                state.frame % 10 == 0
+            );
+            assert!(decoder.decode(snapshot).unwrap() == &state);
+        }
+    }
+
+    #[test]
+    fn deltas_leave_out_unchanged_ships() {
+        let states = states(10);
+        let mut encoder = SnapshotEncoder::new(100);
+        encoder.encode(&states[1]);
+        match encoder.encode(&states[2]) {
+            Snapshot::Delta(delta) => {
+                assert_eq!(delta.ships.len(), 1);
+                assert_eq!(delta.ships[0].player, 0);
+            }
+            Snapshot::Keyframe(_) => panic!("expected a delta"),
+        }
+    }
+
+    #[test]
+    fn reassembles_meteor_showers() {
+        let mut tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        tuning.meteor_showers = true;
+        let mut state = GameState::new(2, tuning, 0);
+        let mut encoder = SnapshotEncoder::new(60);
+        let mut decoder = SnapshotDecoder::default();
+        let mut meteor_deltas = 0;
+        while state.frame < 1500 {
+            state.advance(&[Some(INPUT_UP), Some(0)]);
+            let snapshot = encoder.encode(&state);
+            if let Snapshot::Delta(delta) = &snapshot {
+                meteor_deltas += !delta.meteors.is_empty() as usize;
+            }
+            assert!(decoder.decode(snapshot).unwrap() == &state);
+        }
+        assert!(meteor_deltas > 0);
+    }
+
+    #[test]
+    fn joins_at_the_next_keyframe() {
+        let mut encoder = SnapshotEncoder::new(10);
+        let mut decoder = SnapshotDecoder::default();
+        for state in states(25) {
+            let snapshot = encoder.encode(&state);
+            // the first snapshots are lost, like for a receiver joining late
+            if state.frame < 5 {
+                continue;
+            }
+            let decoded = decoder.decode(snapshot);
+            assert_eq!(decoded.is_ok(), state.frame >= 10);
+        }
+    }
+
+    #[test]
+    fn rejects_deltas_after_a_lost_snapshot() {
+        let states = states(5);
+        let mut encoder = SnapshotEncoder::new(100);
+        let mut decoder = SnapshotDecoder::default();
+        decoder.decode(encoder.encode(&states[0])).unwrap();
+        encoder.encode(&states[1]);
+        assert!(decoder.decode(encoder.encode(&states[2])).is_err());
+    }
+}
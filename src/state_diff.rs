@@ -0,0 +1,147 @@
+use serde_json::Value;
+use std::{
+    fs::File,
+    io::{BufReader, IsTerminal},
+    path::Path,
+};
+
+use crate::Error;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+// Difference is a value that differs between the two states, `path` leads to it from the root,
+// e.g. `state.positions[1][0]` for the x coordinate of the second ship
+#[derive(Debug, PartialEq)]
+pub struct Difference {
+    pub path: String,
+    // `None` if the value only exists on one side
+    pub left: Option<Value>,
+    pub right: Option<Value>,
+}
+
+// prints every value that differs between two dumps or serialized game states, colored if stdout
+// is a terminal, and returns the number of differences
+pub fn run(left: &Path, right: &Path) -> Result<usize, Error> {
+    let load = |path: &Path| -> Result<Value, Error> {
+        let file = File::open(path).map_err(Error::read(path))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(Error::corrupt(path, "dump"))
+    };
+    let differences = diff(&load(left)?, &load(right)?);
+    let colored = std::io::stdout().is_terminal();
+    let paint = |color: &'static str| if colored { color } else { "" };
+    for difference in differences.iter() {
+        let show = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => "missing".to_owned(),
+        };
+        let delta = match (&difference.left, &difference.right) {
+            (Some(Value::Number(left)), Some(Value::Number(right))) => left
+                .as_f64()
+                .zip(right.as_f64())
+                .map(|(left, right)| format!(" ({:+})", right - left))
+                .unwrap_or_default(),
+            _ => String::new(),
+        };
+        println!(
+            "{}{}{}: {}{}{} -> {}{}{}{}",
+            paint(BOLD),
+            difference.path,
+            paint(RESET),
+            paint(RED),
+            show(&difference.left),
+            paint(RESET),
+            paint(GREEN),
+            show(&difference.right),
+            paint(RESET),
+            delta
+        );
+    }
+    match differences.first() {
+        Some(first) => println!(
+            "{} differences, the first one in {}",
+            differences.len(),
+            first.path
+        ),
+        None => println!("The states are identical"),
+    }
+    Ok(differences.len())
+}
+
+// walks both values side by side, objects field by field in alphabetical order and arrays element
+// by element, and collects the leaves that differ
+pub fn diff(left: &Value, right: &Value) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    diff_at(String::new(), Some(left), Some(right), &mut differences);
+    differences
+}
+
+fn diff_at(
+    path: String,
+    left: Option<&Value>,
+    right: Option<&Value>,
+    differences: &mut Vec<Difference>,
+) {
+    match (left, right) {
+        (Some(Value::Object(left)), Some(Value::Object(right))) => {
+            let keys = left
+                .keys()
+                .chain(right.keys().filter(|key| !left.contains_key(*key)));
+            for key in keys {
+                let path = match path.is_empty() {
+                    true => key.clone(),
+                    false => format!("{}.{}", path, key),
+                };
+                diff_at(path, left.get(key), right.get(key), differences);
+            }
+        }
+        (Some(Value::Array(left)), Some(Value::Array(right))) => {
+            for i in 0..left.len().max(right.len()) {
+                let path = format!("{}[{}]", path, i);
+                diff_at(path, left.get(i), right.get(i), differences);
+            }
+        }
+        (left, right) if left != right => differences.push(Difference {
+            path,
+            left: left.cloned(),
+            right: right.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn finds_the_differing_leaves() {
+        let left = json!({
+            "frame": 300,
+            "state": {"positions": [[1.0, 2.0], [3.0, 4.0]], "hull": [3, 3]},
+        });
+        let right = json!({
+            "frame": 300,
+            "state": {"positions": [[1.0, 2.0], [3.5, 4.0]], "hull": [3, 3, 1]},
+        });
+        let differences = diff(&left, &right);
+        assert_eq!(
+            differences,
+            vec![
+                Difference {
+                    path: "state.hull[2]".to_owned(),
+                    left: None,
+                    right: Some(json!(1)),
+                },
+                Difference {
+                    path: "state.positions[1][0]".to_owned(),
+                    left: Some(json!(3.0)),
+                    right: Some(json!(3.5)),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::VecDeque;
+
+use crate::{
+    game::{scripted_input, Frame, GameState},
+    Error,
+};
+
+// runs the simulation headlessly with a rollback on every frame, like a sync test session:
+// after each frame, the state from `check_distance` frames ago is loaded and resimulated,
+// and every resimulated frame has to end up with the same checksum as the first time
+// catches anything the simulation depends on that isn't part of the game state
+pub fn run(mut state: GameState, frames: Frame, check_distance: usize) -> Result<(), Error> {
+    let num_players = state.num_players;
+    // the states and checksums of the last frames, with the inputs that led to them
+    let mut history: VecDeque<(GameState, Vec<Option<u8>>, u64)> = VecDeque::new();
+
+    while state.frame < frames {
+        let inputs: Vec<Option<u8>> = (0..num_players)
+            .map(|player| scripted_input(state.frame + 1, player, frames))
+            .collect();
+        let previous = state.clone();
+        state.advance(&inputs);
+        if history.len() >= check_distance.max(1) {
+            history.pop_front();
+        }
+        history.push_back((previous, inputs, state.checksum()));
+
+        // roll back to the oldest state and resimulate up to the current frame
+        let mut resimulated = history[0].0.clone();
+        for (_, inputs, checksum) in &history {
+            resimulated.advance(inputs);
+            if resimulated.checksum() != *checksum {
+                return Err(Error::RollbackChecksumMismatch {
+                    frame: resimulated.frame,
+                    distance: state.frame - history[0].0.frame,
+                });
+            }
+        }
+        if resimulated != state {
+            return Err(Error::RollbackStateMismatch(state.frame));
+        }
+    }
+    println!(
+        "{} frames resimulated with rollbacks of {} frames, no mismatches",
+        frames, check_distance
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::{MovementModel, Tuning},
+        win_condition::WinConditionId,
+    };
+
+    #[test]
+    fn rollbacks_change_nothing() {
+        let mut tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        tuning.meteor_showers = true;
+        tuning.win_condition = WinConditionId::ScoreLimit;
+        tuning.round_frames = 600;
+        run(GameState::new(4, tuning, 0), 1500, 8).unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+use macroquad::prelude::*;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::game::WINDOW_WIDTH;
+
+// toasts are shown this long, and fade out during the last second
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+const FADE_DURATION: Duration = Duration::from_secs(1);
+const MAX_TOASTS: usize = 5;
+
+// Toast is a short message about something that happened in the session
+struct Toast {
+    // the player the toast is about, a newer toast about them replaces this one
+    player: Option<usize>,
+    text: String,
+    color: Color,
+    shown: Instant,
+}
+
+// Toasts are fading messages in the top right corner, below the player status and votes
+#[derive(Default)]
+pub struct Toasts {
+    toasts: VecDeque<Toast>,
+}
+
+impl Toasts {
+    pub fn show(&mut self, player: Option<usize>, text: String, color: Color) {
+        if player.is_some() {
+            self.toasts.retain(|toast| toast.player != player);
+        }
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        self.toasts.push_back(Toast {
+            player,
+            text,
+            color,
+            shown: Instant::now(),
+        });
+    }
+
+    pub fn draw(&mut self) {
+        self.toasts
+            .retain(|toast| toast.shown.elapsed() < TOAST_DURATION);
+        for (line, toast) in self.toasts.iter().enumerate() {
+            let remaining = TOAST_DURATION.saturating_sub(toast.shown.elapsed());
+            let alpha = (remaining.as_secs_f32() / FADE_DURATION.as_secs_f32()).min(1.0);
+            let color = Color::new(toast.color.r, toast.color.g, toast.color.b, alpha);
+            let width = measure_text(&toast.text, None, 20, 1.0).width;
+            let y = 160.0 + 22.0 * line as f32;
+            draw_rectangle(
+                WINDOW_WIDTH - width - 30.0,
+                y - 16.0,
+                width + 20.0,
+                22.0,
+                Color::new(0.0, 0.0, 0.0, 0.6 * alpha),
+            );
+            draw_text(&toast.text, WINDOW_WIDTH - width - 20.0, y, 20.0, color);
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use backroll_transport::Peer;
+use backroll_transport_udp::{UdpConnectionConfig, UdpManager};
+use bevy_tasks::TaskPool;
+use futures_lite::future;
+use std::{
+    collections::{BinaryHeap, HashMap},
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{rng::Rng, Error};
+
+// time between attempts to reach a peer that isn't listening yet
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+// Transport selects how peers exchange messages, all peers have to use the same one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    // for networks where UDP is blocked, at the cost of latency spikes on packet loss
+    Tcp,
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "udp" => Ok(Self::Udp),
+            "tcp" => Ok(Self::Tcp),
+            _ => Err(format!("unknown transport: {}", s)),
+        }
+    }
+}
+
+// the address to listen on for all interfaces
+// on most systems, sockets bound to the ipv6 one reach ipv4 peers as well
+pub fn listen_addr(port: u16, ipv6: bool) -> SocketAddr {
+    let ip = match ipv6 {
+        true => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        false => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+    SocketAddr::new(ip, port)
+}
+
+// looks up the address of a peer given as `host:port`, hosts can be ip addresses or names
+// of the addresses a name resolves to, the first one of the preferred family is picked
+pub fn resolve(addr: &str, prefer_ipv6: bool) -> Result<SocketAddr, Error> {
+    let failed = |reason: String| Error::Resolve {
+        addr: addr.to_owned(),
+        reason,
+    };
+    let addrs: Vec<SocketAddr> = addr
+        .to_socket_addrs()
+        .map_err(|e| failed(e.to_string()))?
+        .collect();
+    pick(&addrs, prefer_ipv6).ok_or_else(|| failed("it has no address".to_owned()))
+}
+
+fn pick(addrs: &[SocketAddr], prefer_ipv6: bool) -> Option<SocketAddr> {
+    addrs
+        .iter()
+        .find(|addr| addr.is_ipv6() == prefer_ipv6)
+        .or_else(|| addrs.first())
+        .copied()
+}
+
+// ipv6 sockets reach ipv4 peers at their ipv4-mapped address, which is also the address their
+// messages arrive from
+pub fn peer_addr(addr: SocketAddr, ipv6: bool) -> SocketAddr {
+    match (addr.ip(), ipv6) {
+        (IpAddr::V4(ip), true) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+        _ => addr,
+    }
+}
+
+// Connections hands out the peers backroll talks to remote players through
+pub enum Connections {
+    Udp(UdpManager),
+    Tcp(TcpManager),
+}
+
+impl Connections {
+    pub fn bind(transport: Transport, pool: TaskPool, addr: SocketAddr) -> Result<Self, Error> {
+        let connections = match transport {
+            Transport::Udp => UdpManager::bind(pool, addr).map(Self::Udp),
+            Transport::Tcp => TcpManager::bind(addr).map(Self::Tcp),
+        };
+        connections.map_err(|source| Error::Listen { addr, source })
+    }
+
+    // `local_player` and `remote_player` are the indices of the players in the player list,
+    // which has the same order on all peers
+    // with `queue_size`, udp connections use bounded queues
+    pub fn connect(
+        &self,
+        local_player: usize,
+        remote_player: usize,
+        addr: SocketAddr,
+        queue_size: Option<usize>,
+    ) -> Peer {
+        match self {
+            Self::Udp(socket) => socket.connect(match queue_size {
+                Some(queue_size) => UdpConnectionConfig::bounded(addr, queue_size),
+                None => UdpConnectionConfig::unbounded(addr),
+            }),
+            Self::Tcp(manager) => manager.connect(local_player, remote_player, addr),
+        }
+    }
+}
+
+// TcpManager connects peers over TCP, messages are prefixed with their length
+// of every pair of peers, the one with the higher player index connects to the other one
+pub struct TcpManager {
+    pending: Arc<Mutex<PendingConnections>>,
+}
+
+// PendingConnections matches the connections of remote players with the peers waiting for them,
+// whichever of the two comes first waits for the other
+#[derive(Default)]
+struct PendingConnections {
+    // peers waiting for a remote player to connect, by the index of that player
+    listening: HashMap<usize, Peer>,
+    // connections of remote players that connected before we waited for them, by their index
+    unclaimed: HashMap<usize, TcpStream>,
+}
+
+impl TcpManager {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let pending: Arc<Mutex<PendingConnections>> = Arc::default();
+        let waiting = pending.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                // the connecting peer introduces itself with its player index
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        println!("Failed to accept TCP connection: {}", e);
+                        continue;
+                    }
+                };
+                let mut player = [0; 1];
+                if stream.read_exact(&mut player).is_err() {
+                    continue;
+                }
+                let player = player[0] as usize;
+                let mut pending = waiting.lock().unwrap();
+                match pending.listening.remove(&player) {
+                    Some(peer) => forward(stream, peer),
+                    // a faster peer may connect while we are still setting up, a later
+                    // connection of the same player replaces this one
+                    None => {
+                        pending.unclaimed.insert(player, stream);
+                    }
+                }
+            }
+        });
+        Ok(Self { pending })
+    }
+
+    pub fn connect(&self, local_player: usize, remote_player: usize, addr: SocketAddr) -> Peer {
+        let (peer, transport) = Peer::create_unbounded();
+        if local_player < remote_player {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.unclaimed.remove(&remote_player) {
+                Some(stream) => forward(stream, transport),
+                None => {
+                    pending.listening.insert(remote_player, transport);
+                }
+            }
+            return peer;
+        }
+        thread::spawn(move || {
+            // the other peer might not be listening yet
+            let mut stream = loop {
+                match TcpStream::connect(addr) {
+                    Ok(stream) => break stream,
+                    Err(_) => thread::sleep(CONNECT_RETRY_INTERVAL),
+                }
+            };
+            if stream.write_all(&[local_player as u8]).is_ok() {
+                forward(stream, transport);
+            }
+        });
+        peer
+    }
+}
+
+// moves messages between a TCP stream and a peer until either side disconnects
+fn forward(stream: TcpStream, peer: Peer) {
+    // game messages are tiny and latency sensitive, they shouldn't wait to be batched
+    if let Err(e) = stream.set_nodelay(true) {
+        println!("Failed to disable Nagle's algorithm: {}", e);
+    }
+    let (mut reader, mut writer) = match stream.try_clone() {
+        Ok(reader) => (reader, stream),
+        Err(e) => {
+            println!("Failed to set up TCP connection: {}", e);
+            return;
+        }
+    };
+
+    let incoming = peer.clone();
+    thread::spawn(move || {
+        while let Ok(message) = read_message(&mut reader) {
+            if incoming.send(message).is_err() {
+                break;
+            }
+        }
+        incoming.disconnect();
+    });
+
+    thread::spawn(move || {
+        while let Ok(message) = future::block_on(peer.recv()) {
+            if let Err(e) = write_message(&mut writer, &message) {
+                println!("Failed to send over TCP: {}", e);
+                break;
+            }
+        }
+        peer.disconnect();
+    });
+}
+
+// messages are prefixed with their length as a little endian u32, so even keyframes of large
+// states fit
+fn write_message(writer: &mut impl Write, message: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(message.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("a message of {} bytes is too long", message.len()),
+        )
+    })?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(message)
+}
+
+fn read_message(reader: &mut impl Read) -> io::Result<Box<[u8]>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let mut message = vec![0; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut message)?;
+    Ok(message.into_boxed_slice())
+}
+
+// puts a layer between backroll and the network, e.g. to encrypt or compress messages
+// the returned peer is handed to backroll, messages the layer returns `None` for are dropped
+pub fn wrap_peer(
+    pool: &TaskPool,
+    network: Peer,
+    outgoing: impl Fn(&[u8]) -> Option<Box<[u8]>> + Send + 'static,
+    incoming: impl Fn(&[u8]) -> Option<Box<[u8]>> + Send + 'static,
+) -> Peer {
+    let (session, plain) = Peer::create_unbounded();
+    forward_layer(pool, plain, network, outgoing, incoming, None);
+    session
+}
+
+// DropStats counts the messages of a peer that were dropped because a bounded queue was full
+#[derive(Default)]
+pub struct DropStats {
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+}
+
+// limits the number of messages waiting to be sent or handled to `queue_size` in each direction
+// further messages are dropped instead of piling up, which is counted in the returned stats
+pub fn bound_peer(pool: &TaskPool, network: Peer, queue_size: usize) -> (Peer, Arc<DropStats>) {
+    let (session, plain) = Peer::create_bounded(queue_size);
+    let stats = Arc::new(DropStats::default());
+    let keep = |message: &[u8]| Some(message.into());
+    forward_layer(pool, plain, network, keep, keep, Some(stats.clone()));
+    (session, stats)
+}
+
+// moves messages between the session and network side of a layer until either side disconnects
+fn forward_layer(
+    pool: &TaskPool,
+    plain: Peer,
+    network: Peer,
+    outgoing: impl Fn(&[u8]) -> Option<Box<[u8]>> + Send + 'static,
+    incoming: impl Fn(&[u8]) -> Option<Box<[u8]>> + Send + 'static,
+    stats: Option<Arc<DropStats>>,
+) {
+    let (from_session, to_network) = (plain.clone(), network.clone());
+    let sent_stats = stats.clone();
+    pool.spawn(async move {
+        while let Ok(message) = from_session.recv().await {
+            if let Some(message) = outgoing(&message) {
+                if !send_or_drop(&to_network, message, sent_stats.as_ref().map(|s| &s.sent)) {
+                    break;
+                }
+            }
+        }
+        to_network.disconnect();
+    })
+    .detach();
+
+    pool.spawn(async move {
+        while let Ok(message) = network.recv().await {
+            if let Some(message) = incoming(&message) {
+                if !send_or_drop(&plain, message, stats.as_ref().map(|s| &s.received)) {
+                    break;
+                }
+            }
+        }
+        plain.disconnect();
+    })
+    .detach();
+}
+
+// returns false once the peer disconnected, messages that don't fit a full queue are dropped
+fn send_or_drop(peer: &Peer, message: Box<[u8]>, dropped: Option<&AtomicU64>) -> bool {
+    if peer.send(message).is_ok() {
+        return true;
+    }
+    if !peer.is_connected() {
+        return false;
+    }
+    if let Some(dropped) = dropped {
+        dropped.fetch_add(1, Ordering::Relaxed);
+    }
+    true
+}
+
+// how often the simulated network checks for messages that are due
+const DELAY_POLL_INTERVAL: Duration = Duration::from_millis(1);
+// reordered messages arrive this much later than they would have, so the following ones overtake
+// them
+const REORDER_DELAY: Duration = Duration::from_millis(30);
+
+// NetworkConditions describes a bad network to simulate, in each direction
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetworkConditions {
+    pub latency: Duration,
+    // every message is delayed by up to this much more, picked at random
+    pub jitter: Duration,
+    // fractions of the messages that are dropped or held back long enough to be overtaken
+    pub loss: f32,
+    pub reorder: f32,
+}
+
+impl NetworkConditions {
+    pub fn is_perfect(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // when a message sent now arrives, `None` if it is lost
+    fn arrival(&self, rng: &mut Rng, now: Instant) -> Option<Instant> {
+        if rng.next_f32() < self.loss {
+            return None;
+        }
+        let mut arrival = now + self.latency + self.jitter.mul_f32(rng.next_f32());
+        if rng.next_f32() < self.reorder {
+            arrival += REORDER_DELAY;
+        }
+        Some(arrival)
+    }
+}
+
+// Delayed is a message in flight, the queue delivers the earliest arrival first and keeps the
+// order of messages arriving at the same time
+struct Delayed {
+    arrival: Instant,
+    sequence: u64,
+    message: Box<[u8]>,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (other.arrival, other.sequence).cmp(&(self.arrival, self.sequence))
+    }
+}
+
+// passes every message in both directions through a simulated bad network, to reproduce rollbacks
+// under latency, jitter, reordering and loss on one machine
+// `seed` picks which messages are lost or delayed
+// the returned peer is handed to backroll
+pub fn simulate_network(network: Peer, conditions: NetworkConditions, seed: u64) -> Peer {
+    let (session, plain) = Peer::create_unbounded();
+    thread::spawn(move || {
+        let mut rng = Rng::new(seed);
+        let mut sequence = 0;
+        let mut outgoing = BinaryHeap::new();
+        let mut incoming = BinaryHeap::new();
+        while plain.is_connected() && network.is_connected() {
+            for (source, queue) in [(&plain, &mut outgoing), (&network, &mut incoming)] {
+                while let Ok(message) = source.try_recv() {
+                    if let Some(arrival) = conditions.arrival(&mut rng, Instant::now()) {
+                        sequence += 1;
+                        queue.push(Delayed {
+                            arrival,
+                            sequence,
+                            message,
+                        });
+                    }
+                }
+            }
+            for (queue, target) in [(&mut outgoing, &network), (&mut incoming, &plain)] {
+                while queue
+                    .peek()
+                    .is_some_and(|delayed| delayed.arrival <= Instant::now())
+                {
+                    if let Some(delayed) = queue.pop() {
+                        // messages that don't fit are dropped, like on a real network
+                        let _ = target.send(delayed.message);
+                    }
+                }
+            }
+            thread::sleep(DELAY_POLL_INTERVAL);
+        }
+        plain.disconnect();
+        network.disconnect();
+    });
+    session
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_peers_are_mapped_for_ipv6_sockets() {
+        let v4: SocketAddr = "192.0.2.7:7000".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:7000".parse().unwrap();
+        assert_eq!(peer_addr(v4, false), v4);
+        assert_eq!(
+            peer_addr(v4, true),
+            "[::ffff:192.0.2.7]:7000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(peer_addr(v6, true), v6);
+    }
+
+    #[test]
+    fn resolving_prefers_the_chosen_family() {
+        let v4: SocketAddr = "192.0.2.7:7000".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:7000".parse().unwrap();
+        assert_eq!(pick(&[v4, v6], false), Some(v4));
+        assert_eq!(pick(&[v4, v6], true), Some(v6));
+        assert_eq!(pick(&[v4], true), Some(v4));
+        assert_eq!(pick(&[], false), None);
+        assert_eq!(resolve("[2001:db8::1]:7000", false).unwrap(), v6);
+        assert!(resolve("no port", false).is_err());
+    }
+
+    #[test]
+    fn tcp_messages_keep_their_length_beyond_64_kib() {
+        let messages = [vec![7; 3], vec![], vec![1; 70_000]];
+        let mut stream = Vec::new();
+        for message in &messages {
+            write_message(&mut stream, message).unwrap();
+        }
+        let mut reader = stream.as_slice();
+        for message in &messages {
+            assert_eq!(*read_message(&mut reader).unwrap(), message[..]);
+        }
+        assert!(read_message(&mut reader).is_err());
+    }
+
+    #[test]
+    fn simulated_conditions_drop_and_delay_messages() {
+        let conditions = NetworkConditions {
+            latency: Duration::from_millis(50),
+            jitter: Duration::from_millis(10),
+            loss: 0.25,
+            reorder: 0.1,
+        };
+        let mut rng = Rng::new(0);
+        let now = Instant::now();
+        let arrivals: Vec<Option<Instant>> = (0..1000)
+            .map(|_| conditions.arrival(&mut rng, now))
+            .collect();
+        let lost = arrivals.iter().filter(|arrival| arrival.is_none()).count();
+        assert!((200..300).contains(&lost), "{} lost", lost);
+        let delays: Vec<Duration> = arrivals
+            .iter()
+            .flatten()
+            .map(|arrival| *arrival - now)
+            .collect();
+        let reordered = delays
+            .iter()
+            .filter(|delay| **delay >= Duration::from_millis(80))
+            .count();
+        assert!((40..110).contains(&reordered), "{} reordered", reordered);
+        assert!(delays
+            .iter()
+            .all(|delay| (Duration::from_millis(50)..Duration::from_millis(90)).contains(delay)));
+    }
+}
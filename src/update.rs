@@ -0,0 +1,80 @@
+use macroquad::prelude::*;
+use std::sync::mpsc::{self, Receiver};
+
+use crate::{
+    game::WINDOW_WIDTH,
+    upload::{self, Endpoint},
+};
+
+// UpdateCheck asks a release endpoint for the latest version in the background, peers running
+// different versions usually fail to synchronize or desync
+// the endpoint answers with the latest version as plain text, e.g. `0.2.0` or `v0.2.0`
+pub struct UpdateCheck {
+    receiver: Receiver<String>,
+    latest: Option<String>,
+}
+
+impl UpdateCheck {
+    pub fn start(endpoint: Endpoint) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || match upload::request(&endpoint, "GET", None) {
+            Ok(body) => {
+                let latest = String::from_utf8_lossy(&body).trim().to_owned();
+                if is_newer(&latest, env!("CARGO_PKG_VERSION")) {
+                    let _ = sender.send(latest);
+                }
+            }
+            Err(e) => println!("Failed to check for updates: {}", e),
+        });
+        Self {
+            receiver,
+            latest: None,
+        }
+    }
+
+    // shows a banner at the bottom of the window once a newer version is known
+    pub fn draw(&mut self) {
+        if let Ok(latest) = self.receiver.try_recv() {
+            self.latest = Some(latest);
+        }
+        let Some(latest) = &self.latest else {
+            return;
+        };
+        let text = format!(
+            "Version {} is available, this is {}. All peers need the same version.",
+            latest.trim_start_matches('v'),
+            env!("CARGO_PKG_VERSION")
+        );
+        let width = measure_text(&text, None, 16, 1.0).width;
+        draw_text(&text, (WINDOW_WIDTH - width) / 2.0, 14.0, 16.0, YELLOW);
+    }
+}
+
+// compares dotted version numbers part by part, anything unparsable is never newer
+fn is_newer(latest: &str, current: &str) -> bool {
+    let parse = |version: &str| -> Option<Vec<u32>> {
+        version
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect()
+    };
+    match (parse(latest), parse(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions_by_number() {
+        assert!(is_newer("0.10.0", "0.9.1"));
+        assert!(is_newer("v1.0.0", "0.1.0"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1", "0.1.0"));
+        assert!(!is_newer("<html>", "0.1.0"));
+    }
+}
@@ -0,0 +1,202 @@
+use serde::Serialize;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{game::Frame, Error};
+
+// the server has this long to accept a request and answer
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+const BOUNDARY: &str = "box-game-upload-boundary";
+const MAX_RESPONSE_LEN: u64 = 64 * 1024;
+
+// MatchStats are sent along with the replay, for leaderboards and desync telemetry
+#[derive(Serialize)]
+pub struct MatchStats {
+    pub version: &'static str,
+    pub players: usize,
+    pub frames: Frame,
+    pub round_wins: Vec<u32>,
+    pub checksum: u64,
+    // the last periodic checksum, servers can compare it between the uploads of all peers
+    pub periodic_checksum: (Frame, u64),
+    // the hash of all inputs up to the periodic checksum, tells input and simulation desyncs apart
+    pub periodic_input_hash: u64,
+    pub rollbacks: u32,
+    pub mirror_mismatches: Option<u32>,
+}
+
+// Endpoint is a plain http url replays are posted to, e.g. `http://example.com:8080/replays`
+#[derive(Clone)]
+pub struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl std::str::FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("only http urls are supported: {}", s))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| "invalid port")?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host: {}", s));
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+// asks on the terminal whether the match may be uploaded, anything but yes declines
+pub fn ask_consent(endpoint: &Endpoint) -> io::Result<bool> {
+    print!(
+        "Upload the replay and stats of this match to {}? [y/N] ",
+        endpoint
+    );
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+// posts the replay and stats as a multipart form with the fields `stats` and `replay`
+pub fn upload(endpoint: &Endpoint, replay: &[u8], stats: &MatchStats) -> Result<(), Error> {
+    let mut body = Vec::new();
+    write!(
+        body,
+        "--{}\r\nContent-Disposition: form-data; name=\"stats\"\r\nContent-Type: application/json\r\n\r\n",
+        BOUNDARY
+    )?;
+    serde_json::to_writer(&mut body, stats).map_err(Error::encode("stats"))?;
+    write!(
+        body,
+        "\r\n--{}\r\nContent-Disposition: form-data; name=\"replay\"; filename=\"match.replay\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+        BOUNDARY
+    )?;
+    body.extend_from_slice(replay);
+    write!(body, "\r\n--{}--\r\n", BOUNDARY)?;
+
+    let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+    request(endpoint, "POST", Some((&content_type, &body)))?;
+    Ok(())
+}
+
+// sends an http request with an optional body of the given content type and returns the body of
+// the response, anything but a 2xx status is an error
+pub fn request(
+    endpoint: &Endpoint,
+    method: &str,
+    body: Option<(&str, &[u8])>,
+) -> Result<Vec<u8>, Error> {
+    let resolve_failed = |reason: String| Error::Resolve {
+        addr: endpoint.host.clone(),
+        reason,
+    };
+    let address = (endpoint.host.as_str(), endpoint.port)
+        .to_socket_addrs()
+        .map_err(|e| resolve_failed(e.to_string()))?
+        .next()
+        .ok_or_else(|| resolve_failed("the host has no address".to_owned()))?;
+    let mut stream = TcpStream::connect_timeout(&address, UPLOAD_TIMEOUT)?;
+    stream.set_read_timeout(Some(UPLOAD_TIMEOUT))?;
+    stream.set_write_timeout(Some(UPLOAD_TIMEOUT))?;
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+        method, endpoint.path, endpoint.host
+    )?;
+    if let Some((content_type, body)) = body {
+        write!(
+            stream,
+            "Content-Type: {}\r\nContent-Length: {}\r\n",
+            content_type,
+            body.len()
+        )?;
+    }
+    write!(stream, "\r\n")?;
+    if let Some((_, body)) = body {
+        stream.write_all(body)?;
+    }
+
+    // the server closes the connection after the response, which is kept short
+    let mut response = BufReader::new(stream.take(MAX_RESPONSE_LEN));
+    let mut status_line = String::new();
+    response.read_line(&mut status_line)?;
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => {}
+        _ => return Err(Error::RequestRejected(status_line.trim().to_owned())),
+    }
+    // skip the headers
+    let mut header = String::new();
+    while response.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut body = Vec::new();
+    response.read_to_end(&mut body)?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn posts_replay_and_stats() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            // the body ends with the closing boundary
+            while !request.ends_with(format!("--{}--\r\n", BOUNDARY).as_bytes()) {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream.write_all(b"HTTP/1.1 201 Created\r\n\r\n").unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+
+        let endpoint: Endpoint = format!("http://127.0.0.1:{}/replays", port)
+            .parse()
+            .unwrap();
+        let stats = MatchStats {
+            version: "0.1.0",
+            players: 2,
+            frames: 600,
+            round_wins: vec![1, 0],
+            checksum: 7,
+            periodic_checksum: (600, 7),
+            periodic_input_hash: 9,
+            rollbacks: 3,
+            mirror_mismatches: None,
+        };
+        upload(&endpoint, b"replay bytes", &stats).unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /replays HTTP/1.1\r\n"));
+        assert!(request.contains("\"round_wins\":[1,0]"));
+        assert!(request.contains("\r\n\r\nreplay bytes\r\n"));
+    }
+}
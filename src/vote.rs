@@ -0,0 +1,203 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Frame, GameState, MatchEnd, MAX_PLAYERS, WINDOW_WIDTH};
+
+// the three highest bits of an input are reserved for votes, they hold a `VoteAction`
+pub const INPUT_VOTE_SHIFT: u32 = 5;
+pub const INPUT_VOTE_MASK: u8 = 0b111 << INPUT_VOTE_SHIFT;
+// votes are tallied after this many seconds, or as soon as everyone voted
+const VOTE_DURATION: f32 = 10.0;
+// players who didn't press a button for this many seconds can be kicked
+const AFK_DURATION: f32 = 30.0;
+
+// VoteAction is what a player does about votes on a frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoteAction {
+    Yes = 1,
+    No = 2,
+    CallKick = 3,
+    CallSkipMap = 4,
+    CallEndMatch = 5,
+    // not a vote during the match, it asks for a rematch once the match is over
+    Rematch = 6,
+}
+
+impl VoteAction {
+    pub fn input(self) -> u8 {
+        (self as u8) << INPUT_VOTE_SHIFT
+    }
+
+    pub fn from_input(input: u8) -> Option<Self> {
+        match (input & INPUT_VOTE_MASK) >> INPUT_VOTE_SHIFT {
+            1 => Some(Self::Yes),
+            2 => Some(Self::No),
+            3 => Some(Self::CallKick),
+            4 => Some(Self::CallSkipMap),
+            5 => Some(Self::CallEndMatch),
+            6 => Some(Self::Rematch),
+            _ => None,
+        }
+    }
+}
+
+// VoteKind is what players vote on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub enum VoteKind {
+    // kicked players are treated as disconnected for the rest of the session
+    KickAfk(usize),
+    SkipMap,
+    EndMatch,
+}
+
+impl VoteKind {
+    fn description(self) -> String {
+        match self {
+            Self::KickAfk(target) => format!("kick P{} for being away", target + 1),
+            Self::SkipMap => "skip this map".to_owned(),
+            Self::EndMatch => "end the match".to_owned(),
+        }
+    }
+}
+
+// Vote is a vote in progress, every player casts their ballot with their inputs
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct Vote {
+    pub kind: VoteKind,
+    pub caller: usize,
+    pub start_frame: Frame,
+    // `None` until the player voted
+    pub ballots: [Option<bool>; MAX_PLAYERS],
+}
+
+impl Vote {
+    // the target of a kick doesn't get a say
+    fn can_vote(&self, player: usize) -> bool {
+        self.kind != VoteKind::KickAfk(player)
+    }
+
+    fn tally(&self) -> (usize, usize) {
+        let yes = self.ballots.iter().filter(|&&b| b == Some(true)).count();
+        let no = self.ballots.iter().filter(|&&b| b == Some(false)).count();
+        (yes, no)
+    }
+
+    // draws the vote at the top of the window
+    pub fn draw(&self, state: &GameState) {
+        let (yes, no) = self.tally();
+        let remaining = state.tuning.frames(VOTE_DURATION) - (state.frame - self.start_frame);
+        let remaining = state.tuning.seconds(remaining.max(0));
+        let lines = [
+            format!("P{} wants to {}", self.caller + 1, self.kind.description()),
+            format!("Y {} / N {}  ({:.0}s left)", yes, no, remaining),
+        ];
+        draw_rectangle(
+            WINDOW_WIDTH / 2.0 - 160.0,
+            70.0,
+            320.0,
+            60.0,
+            Color::new(0.0, 0.0, 0.0, 0.7),
+        );
+        for (line, text) in lines.iter().enumerate() {
+            let width = measure_text(text, None, 20, 1.0).width;
+            let y = 95.0 + 22.0 * line as f32;
+            draw_text(text, (WINDOW_WIDTH - width) / 2.0, y, 20.0, WHITE);
+        }
+    }
+}
+
+// starts, tallies and carries out votes with the vote actions in the inputs of all players
+// kicked and disconnected players don't vote
+pub fn advance_votes(state: &mut GameState, inputs: &[Option<u8>]) {
+    let mut actions = [None; MAX_PLAYERS];
+    for (i, input) in inputs.iter().enumerate().take(state.num_players) {
+        if !state.kicked[i] {
+            actions[i] = input.and_then(VoteAction::from_input);
+        }
+    }
+
+    match &mut state.vote {
+        Some(vote) => {
+            for (i, action) in actions.iter().enumerate() {
+                let ballot = match action {
+                    Some(VoteAction::Yes) => Some(true),
+                    Some(VoteAction::No) => Some(false),
+                    _ => None,
+                };
+                if ballot.is_some() && vote.can_vote(i) {
+                    vote.ballots[i] = ballot;
+                }
+            }
+        }
+        None => {
+            // the first player calling a vote starts it, so simultaneous calls don't race
+            let call = actions.iter().enumerate().find_map(|(i, action)| {
+                let kind = match (*action)? {
+                    VoteAction::CallKick => VoteKind::KickAfk(afk_player(state, i)?),
+                    VoteAction::CallSkipMap => VoteKind::SkipMap,
+                    VoteAction::CallEndMatch => VoteKind::EndMatch,
+                    _ => return None,
+                };
+                Some((i, kind))
+            });
+            if let Some((caller, kind)) = call {
+                let mut ballots = [None; MAX_PLAYERS];
+                ballots[caller] = Some(true);
+                state.vote = Some(Vote {
+                    kind,
+                    caller,
+                    start_frame: state.frame,
+                    ballots,
+                });
+            }
+        }
+    }
+
+    let Some(vote) = state.vote else {
+        return;
+    };
+    let everyone_voted = (0..state.num_players)
+        .filter(|&i| vote.can_vote(i) && !state.kicked[i])
+        .filter(|&i| inputs.get(i).is_some_and(|input| input.is_some()))
+        .all(|i| vote.ballots[i].is_some());
+    if !everyone_voted && state.frame - vote.start_frame < state.tuning.frames(VOTE_DURATION) {
+        return;
+    }
+    state.vote = None;
+    let (yes, no) = vote.tally();
+    if yes <= no {
+        return;
+    }
+    match vote.kind {
+        VoteKind::KickAfk(target) => state.kicked[target] = true,
+        VoteKind::SkipMap => {
+            if state.round.transition.is_none() {
+                state.end_round(None);
+            }
+        }
+        VoteKind::EndMatch => {
+            if state.round.transition.is_none() {
+                state.end_round(None);
+            }
+            state.match_end = Some(MatchEnd::default());
+        }
+    }
+}
+
+// the player who has been away the longest, if anyone has been away long enough
+fn afk_player(state: &GameState, caller: usize) -> Option<usize> {
+    (0..state.num_players)
+        .filter(|&i| i != caller && !state.kicked[i])
+        .filter(|&i| state.frame - state.last_active_frames[i] >= state.tuning.frames(AFK_DURATION))
+        .min_by_key(|&i| (state.last_active_frames[i], i))
+}
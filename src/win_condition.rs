@@ -0,0 +1,584 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, str::FromStr};
+
+use crate::game::{Frame, GameState};
+
+// hits a ship takes before it is out of the round in last ship standing
+pub const HULL: u8 = 3;
+// rams needed to win a round with a score limit
+const SCORE_LIMIT: u32 = 5;
+// durations are given in seconds, the tuning converts them to frames
+// a rammed ship can't be rammed again for this long
+const RAM_COOLDOWN: f32 = 1.0;
+// how long a player has to control the zone to win king of the hill
+const CONTROL_TARGET: f32 = 15.0;
+// the zone moves to another spot this often
+const ZONE_MOVE_INTERVAL: f32 = 10.0;
+const ZONE_RADIUS: f32 = 60.0;
+// spots the zone moves between, reachable in every arena
+const ZONE_SPOTS: [(f32, f32); 4] = [
+    (300.0, 290.0),
+    (450.0, 400.0),
+    (300.0, 510.0),
+    (150.0, 400.0),
+];
+// laps needed to win a race
+pub const LAPS: u8 = 3;
+const CHECKPOINT_RADIUS: f32 = 40.0;
+// checkpoints of a lap in the order they have to be passed, reachable in every arena
+const CHECKPOINTS: [(f32, f32); 4] = [
+    (300.0, 100.0),
+    (500.0, 400.0),
+    (300.0, 700.0),
+    (100.0, 400.0),
+];
+// runners that aren't caught within this time win the hunt
+const HUNT_DURATION: f32 = 45.0;
+
+// the centers and radii of all spots win conditions send ships to, generated arenas keep them
+// reachable
+pub fn landmarks() -> impl Iterator<Item = ((f32, f32), f32)> {
+    let zones = ZONE_SPOTS.iter().map(|spot| (*spot, ZONE_RADIUS));
+    zones.chain(
+        CHECKPOINTS
+            .iter()
+            .map(|checkpoint| (*checkpoint, CHECKPOINT_RADIUS)),
+    )
+}
+
+// ControlZone is the area fought over in king of the hill
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub struct ControlZone {
+    // index into the spots the zone moves between
+    pub spot: usize,
+    pub moved_frame: Frame,
+}
+
+impl ControlZone {
+    pub fn position(&self) -> (f32, f32) {
+        ZONE_SPOTS[self.spot]
+    }
+}
+
+// Outcome is how a round ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub enum Outcome {
+    Won(usize),
+    Draw,
+    // the runners of a hunt weren't all caught
+    Escaped,
+}
+
+// Role is the part a player plays in a round, every role moves differently
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub enum Role {
+    // every player is a runner outside of hunts
+    Runner,
+    Hunter,
+}
+
+impl Role {
+    // factor applied to the acceleration and top speed of the ship
+    pub fn speed(self) -> f32 {
+        match self {
+            Self::Runner => 1.0,
+            Self::Hunter => 1.3,
+        }
+    }
+
+    // factor applied to the turning speed of the ship
+    pub fn turn_speed(self) -> f32 {
+        match self {
+            Self::Runner => 1.0,
+            Self::Hunter => 0.7,
+        }
+    }
+}
+
+// WinCondition decides when a round is over and runs the rules it needs for that
+// conditions are stateless, everything they track lives in the game state
+pub trait WinCondition {
+    // prepares the state when a round starts, after the ships spawned
+    fn start_round(&self, _state: &mut GameState) {}
+
+    // runs the rules of the condition after all ships moved, e.g. awarding points
+    fn advance(&self, _state: &mut GameState) {}
+
+    // the outcome of the round once it is decided
+    fn outcome(&self, state: &GameState) -> Option<Outcome>;
+
+    // progress of a player shown in the HUD
+    fn status(&self, _state: &GameState, _player: usize) -> Option<String> {
+        None
+    }
+
+    // draws what the condition adds to the arena, below the ships, `colors` are the player colors
+    fn draw(&self, _state: &GameState, _colors: &[Color]) {}
+}
+
+// WinConditionId selects the active win condition, it is part of the game state
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
+)]
+pub enum WinConditionId {
+    // rounds never end by a win
+    Endless,
+    ScoreLimit,
+    LastShipStanding,
+    KingOfTheHill,
+    Race,
+    Hunt,
+}
+
+impl WinConditionId {
+    pub fn get(self) -> &'static dyn WinCondition {
+        match self {
+            Self::Endless => &Endless,
+            Self::ScoreLimit => &ScoreLimit,
+            Self::LastShipStanding => &LastShipStanding,
+            Self::KingOfTheHill => &KingOfTheHill,
+            Self::Race => &Race,
+            Self::Hunt => &Hunt,
+        }
+    }
+}
+
+impl FromStr for WinConditionId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "endless" => Ok(Self::Endless),
+            "score-limit" => Ok(Self::ScoreLimit),
+            "last-ship-standing" => Ok(Self::LastShipStanding),
+            "king-of-the-hill" => Ok(Self::KingOfTheHill),
+            "race" => Ok(Self::Race),
+            "hunt" => Ok(Self::Hunt),
+            _ => Err(format!("unknown win condition: {}", s)),
+        }
+    }
+}
+
+struct Endless;
+
+impl WinCondition for Endless {
+    fn outcome(&self, _state: &GameState) -> Option<Outcome> {
+        None
+    }
+}
+
+// every ram scores a point, the first player to reach the limit wins
+struct ScoreLimit;
+
+impl WinCondition for ScoreLimit {
+    fn advance(&self, state: &mut GameState) {
+        resolve_rams(state, |state, rammer, _| state.scores[rammer] += 1);
+    }
+
+    fn outcome(&self, state: &GameState) -> Option<Outcome> {
+        let mut winners = (0..state.num_players).filter(|&i| state.scores[i] >= SCORE_LIMIT);
+        match (winners.next(), winners.next()) {
+            (Some(winner), None) => Some(Outcome::Won(winner)),
+            (Some(_), Some(_)) => Some(Outcome::Draw),
+            _ => None,
+        }
+    }
+
+    fn status(&self, state: &GameState, player: usize) -> Option<String> {
+        Some(format!("{} / {}", state.scores[player], SCORE_LIMIT))
+    }
+}
+
+// every ram damages the rammed ship, the last ship with an intact hull wins
+struct LastShipStanding;
+
+impl WinCondition for LastShipStanding {
+    fn advance(&self, state: &mut GameState) {
+        resolve_rams(state, |state, _, target| {
+            state.hull[target] = state.hull[target].saturating_sub(1)
+        });
+    }
+
+    fn outcome(&self, state: &GameState) -> Option<Outcome> {
+        if state.num_players < 2 {
+            return None;
+        }
+        let mut standing = (0..state.num_players).filter(|&i| state.hull[i] > 0);
+        match (standing.next(), standing.next()) {
+            (Some(winner), None) => Some(Outcome::Won(winner)),
+            (None, _) => Some(Outcome::Draw),
+            _ => None,
+        }
+    }
+
+    fn status(&self, state: &GameState, player: usize) -> Option<String> {
+        match state.hull[player] {
+            0 => Some("out".to_owned()),
+            hull => Some(format!("hull {} / {}", hull, HULL)),
+        }
+    }
+}
+
+// a player alone in the zone controls it, the first one to control it long enough wins
+// the zone moves to a random other spot every few seconds
+struct KingOfTheHill;
+
+impl KingOfTheHill {
+    // the only ship inside the zone, nobody controls a contested zone
+    fn controller(state: &GameState) -> Option<usize> {
+        let (zone_x, zone_y) = state.zone.position();
+        let mut inside = (0..state.num_players).filter(|&i| {
+            let (x, y) = state.positions[i];
+            let (dx, dy) = (x - zone_x, y - zone_y);
+            state.hull[i] > 0 && dx * dx + dy * dy < ZONE_RADIUS * ZONE_RADIUS
+        });
+        match (inside.next(), inside.next()) {
+            (Some(controller), None) => Some(controller),
+            _ => None,
+        }
+    }
+}
+
+impl WinCondition for KingOfTheHill {
+    fn advance(&self, state: &mut GameState) {
+        if state.frame - state.zone.moved_frame >= state.tuning.frames(ZONE_MOVE_INTERVAL) {
+            // never stays on the same spot
+            let offset = 1 + state.rng.next_u32() as usize % (ZONE_SPOTS.len() - 1);
+            state.zone = ControlZone {
+                spot: (state.zone.spot + offset) % ZONE_SPOTS.len(),
+                moved_frame: state.frame,
+            };
+        }
+        if let Some(controller) = Self::controller(state) {
+            state.scores[controller] += 1;
+        }
+    }
+
+    fn outcome(&self, state: &GameState) -> Option<Outcome> {
+        let target = state.tuning.frames(CONTROL_TARGET) as u32;
+        (0..state.num_players)
+            .find(|&i| state.scores[i] >= target)
+            .map(Outcome::Won)
+    }
+
+    fn status(&self, state: &GameState, player: usize) -> Option<String> {
+        Some(format!(
+            "{:.1}s / {}s",
+            state.tuning.seconds(state.scores[player] as Frame),
+            CONTROL_TARGET
+        ))
+    }
+
+    fn draw(&self, state: &GameState, colors: &[Color]) {
+        let (x, y) = state.zone.position();
+        let color = match Self::controller(state) {
+            Some(controller) => colors[controller],
+            None => WHITE,
+        };
+        draw_circle(x, y, ZONE_RADIUS, Color { a: 0.15, ..color });
+        draw_circle_lines(x, y, ZONE_RADIUS, 2.0, color);
+    }
+}
+
+// ships pass the checkpoints in order, the first one to finish all laps wins
+struct Race;
+
+impl Race {
+    // how far a player got, the distance to the next checkpoint breaks ties
+    fn progress(state: &GameState, player: usize) -> (u8, u8, f32) {
+        let (x, y) = state.positions[player];
+        let (checkpoint_x, checkpoint_y) = CHECKPOINTS[state.checkpoints[player] as usize];
+        let (dx, dy) = (checkpoint_x - x, checkpoint_y - y);
+        (
+            state.laps[player],
+            state.checkpoints[player],
+            -(dx * dx + dy * dy),
+        )
+    }
+
+    // position of a player in the race, counting from 1
+    fn place(state: &GameState, player: usize) -> usize {
+        let progress = Self::progress(state, player);
+        1 + (0..state.num_players)
+            .filter(|&i| Self::progress(state, i) > progress)
+            .count()
+    }
+}
+
+impl WinCondition for Race {
+    fn advance(&self, state: &mut GameState) {
+        for i in 0..state.num_players {
+            let (x, y) = state.positions[i];
+            let (checkpoint_x, checkpoint_y) = CHECKPOINTS[state.checkpoints[i] as usize];
+            let (dx, dy) = (checkpoint_x - x, checkpoint_y - y);
+            if dx * dx + dy * dy >= CHECKPOINT_RADIUS * CHECKPOINT_RADIUS {
+                continue;
+            }
+            state.checkpoints[i] += 1;
+            if state.checkpoints[i] as usize == CHECKPOINTS.len() {
+                state.checkpoints[i] = 0;
+                state.laps[i] += 1;
+            }
+        }
+    }
+
+    fn outcome(&self, state: &GameState) -> Option<Outcome> {
+        let mut winners = (0..state.num_players).filter(|&i| state.laps[i] >= LAPS);
+        match (winners.next(), winners.next()) {
+            (Some(winner), None) => Some(Outcome::Won(winner)),
+            (Some(_), Some(_)) => Some(Outcome::Draw),
+            _ => None,
+        }
+    }
+
+    fn status(&self, state: &GameState, player: usize) -> Option<String> {
+        Some(format!(
+            "#{} lap {}/{} checkpoint {}/{}",
+            Self::place(state, player),
+            (state.laps[player] + 1).min(LAPS),
+            LAPS,
+            state.checkpoints[player] + 1,
+            CHECKPOINTS.len()
+        ))
+    }
+
+    fn draw(&self, state: &GameState, colors: &[Color]) {
+        for (i, &(x, y)) in CHECKPOINTS.iter().enumerate() {
+            draw_circle_lines(x, y, CHECKPOINT_RADIUS, 2.0, GRAY);
+            let label = (i + 1).to_string();
+            let size = measure_text(&label, None, 30, 1.0);
+            draw_text(
+                &label,
+                x - size.width / 2.0,
+                y + size.height / 2.0,
+                30.0,
+                GRAY,
+            );
+        }
+        // mark the next checkpoint of every player with a dot in their color
+        for i in 0..state.num_players {
+            let (x, y) = CHECKPOINTS[state.checkpoints[i] as usize];
+            let angle = i as f32 / state.num_players as f32 * 2.0 * std::f32::consts::PI;
+            draw_circle(
+                x + CHECKPOINT_RADIUS * angle.cos(),
+                y + CHECKPOINT_RADIUS * angle.sin(),
+                5.0,
+                colors[i],
+            );
+        }
+    }
+}
+
+// one player hunts the others, who are out of the round once the hunter touches them
+// the hunter is faster but turns slower, the runners win if anyone escapes until time runs out
+struct Hunt;
+
+impl Hunt {
+    fn hunter(state: &GameState) -> Option<usize> {
+        (0..state.num_players).find(|&i| state.roles[i] == Role::Hunter)
+    }
+}
+
+impl WinCondition for Hunt {
+    // the hunter is drawn from the shared rng, so every peer picks the same one
+    // it is picked by player index, so the mirror check leaves out the frame that starts a round
+    fn start_round(&self, state: &mut GameState) {
+        let hunter = state.rng.next_u32() as usize % state.num_players;
+        for (i, role) in state.roles.iter_mut().enumerate() {
+            *role = if i == hunter {
+                Role::Hunter
+            } else {
+                Role::Runner
+            };
+        }
+    }
+
+    fn advance(&self, state: &mut GameState) {
+        let Some(hunter) = Self::hunter(state) else {
+            return;
+        };
+        let (hunter_x, hunter_y) = state.positions[hunter];
+        let min_distance = state.tuning.ship_height;
+        for i in 0..state.num_players {
+            let (x, y) = state.positions[i];
+            let (dx, dy) = (x - hunter_x, y - hunter_y);
+            if i != hunter && state.hull[i] > 0 && dx * dx + dy * dy < min_distance * min_distance {
+                state.hull[i] = 0;
+                state.scores[hunter] += 1;
+            }
+        }
+    }
+
+    fn outcome(&self, state: &GameState) -> Option<Outcome> {
+        let hunter = Self::hunter(state)?;
+        if state.num_players < 2 {
+            return None;
+        }
+        if (0..state.num_players).all(|i| i == hunter || state.hull[i] == 0) {
+            Some(Outcome::Won(hunter))
+        } else if state.frame - state.round.start_frame >= state.tuning.frames(HUNT_DURATION) {
+            Some(Outcome::Escaped)
+        } else {
+            None
+        }
+    }
+
+    fn status(&self, state: &GameState, player: usize) -> Option<String> {
+        let remaining =
+            state.tuning.frames(HUNT_DURATION) - (state.frame - state.round.start_frame);
+        let seconds = state.tuning.seconds(remaining.max(0));
+        Some(match state.roles[player] {
+            Role::Hunter => format!("hunter {} caught {:.0}s", state.scores[player], seconds),
+            Role::Runner if state.hull[player] == 0 => "caught".to_owned(),
+            Role::Runner => format!("runner {:.0}s", seconds),
+        })
+    }
+
+    fn draw(&self, state: &GameState, _colors: &[Color]) {
+        if let Some(hunter) = Self::hunter(state) {
+            let (x, y) = state.positions[hunter];
+            draw_circle_lines(x, y, state.tuning.ship_height, 2.0, RED);
+        }
+    }
+}
+
+// bounces touching ships off each other, like billiard balls of the same weight
+// the ship moving faster towards the other one rams it, which calls `on_ram(state, rammer, target)`
+fn resolve_rams(state: &mut GameState, mut on_ram: impl FnMut(&mut GameState, usize, usize)) {
+    let min_distance = state.tuning.ship_height;
+    for a in 0..state.num_players {
+        for b in a + 1..state.num_players {
+            if state.hull[a] == 0 || state.hull[b] == 0 {
+                continue;
+            }
+            let ((a_x, a_y), (b_x, b_y)) = (state.positions[a], state.positions[b]);
+            let (dx, dy) = (b_x - a_x, b_y - a_y);
+            let distance_sq = dx * dx + dy * dy;
+            if distance_sq >= min_distance * min_distance || distance_sq == 0.0 {
+                continue;
+            }
+            let distance = distance_sq.sqrt();
+            let (normal_x, normal_y) = (dx / distance, dy / distance);
+
+            // push both ships apart until they just touch
+            let overlap = (min_distance - distance) / 2.0;
+            state.positions[a] = (a_x - normal_x * overlap, a_y - normal_y * overlap);
+            state.positions[b] = (b_x + normal_x * overlap, b_y + normal_y * overlap);
+
+            let (a_vel, b_vel) = (state.velocities[a], state.velocities[b]);
+            let a_towards = a_vel.0 * normal_x + a_vel.1 * normal_y;
+            let b_towards = -(b_vel.0 * normal_x + b_vel.1 * normal_y);
+            if a_towards <= 0.0 && b_towards <= 0.0 {
+                continue;
+            }
+            state.velocities[a] = b_vel;
+            state.velocities[b] = a_vel;
+
+            // head-on collisions at the same speed are nobody's ram
+            let (rammer, target) = match a_towards.total_cmp(&b_towards) {
+                Ordering::Greater => (a, b),
+                Ordering::Less => (b, a),
+                Ordering::Equal => continue,
+            };
+            let rammed_frame = state.rammed_frames[target];
+            let cooldown = state.tuning.frames(RAM_COOLDOWN);
+            if rammed_frame.is_none_or(|frame| state.frame - frame >= cooldown) {
+                state.rammed_frames[target] = Some(state.frame);
+                on_ram(state, rammer, target);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{MovementModel, Tuning};
+
+    #[test]
+    fn contested_zones_are_not_controlled() {
+        let mut state = GameState::new(2, Tuning::new(MovementModel::Inertia, 1.0), 0);
+        state.positions[0] = state.zone.position();
+        state.positions[1] = (0.0, 0.0);
+        KingOfTheHill.advance(&mut state);
+        assert_eq!(state.scores[..2], [1, 0]);
+
+        state.positions[1] = state.zone.position();
+        KingOfTheHill.advance(&mut state);
+        assert_eq!(state.scores[..2], [1, 0]);
+
+        // the zone moves away from both ships
+        state.frame = state.tuning.frames(ZONE_MOVE_INTERVAL);
+        KingOfTheHill.advance(&mut state);
+        assert_ne!(state.zone.spot, 0);
+        assert_eq!(state.zone.moved_frame, state.frame);
+        assert_eq!(state.scores[..2], [1, 0]);
+    }
+
+    #[test]
+    fn checkpoints_count_in_order() {
+        let mut state = GameState::new(2, Tuning::new(MovementModel::Inertia, 1.0), 0);
+        // skipping ahead doesn't count
+        state.positions[0] = CHECKPOINTS[1];
+        state.positions[1] = CHECKPOINTS[0];
+        Race.advance(&mut state);
+        assert_eq!(state.checkpoints[..2], [0, 1]);
+        assert_eq!(Race::place(&state, 1), 1);
+
+        for _ in 0..LAPS {
+            for &checkpoint in &CHECKPOINTS {
+                state.positions[0] = checkpoint;
+                Race.advance(&mut state);
+            }
+        }
+        assert_eq!((state.laps[0], state.checkpoints[0]), (LAPS, 0));
+        assert_eq!(Race.outcome(&state), Some(Outcome::Won(0)));
+    }
+
+    #[test]
+    fn hunters_catch_runners() {
+        let mut tuning = Tuning::new(MovementModel::Inertia, 1.0);
+        tuning.win_condition = WinConditionId::Hunt;
+        let mut state = GameState::new(3, tuning, 0);
+        let hunter = Hunt::hunter(&state).unwrap();
+        assert_eq!(
+            state
+                .roles
+                .iter()
+                .filter(|&&role| role == Role::Hunter)
+                .count(),
+            1
+        );
+
+        let runners: Vec<usize> = (0..3).filter(|&i| i != hunter).collect();
+        state.positions[runners[0]] = state.positions[hunter];
+        Hunt.advance(&mut state);
+        assert_eq!(state.hull[runners[0]], 0);
+        assert_eq!(Hunt.outcome(&state), None);
+
+        state.frame = state.tuning.frames(HUNT_DURATION);
+        assert_eq!(Hunt.outcome(&state), Some(Outcome::Escaped));
+        state.positions[runners[1]] = state.positions[hunter];
+        Hunt.advance(&mut state);
+        assert_eq!(Hunt.outcome(&state), Some(Outcome::Won(hunter)));
+    }
+}